@@ -0,0 +1,215 @@
+//! ISO 7816-4 APDU encoding
+//!
+//! Command and response APDUs used to talk to `YubiKey` applets over a smart
+//! card transport (PC/SC, NFC). The transport itself is abstracted behind
+//! [`CardTransport`] so applet logic can run against a scripted card.
+
+use crate::error::{Result, YkvcError};
+
+/// Status word: command completed successfully
+pub const SW_SUCCESS: u16 = 0x9000;
+
+/// Status word: security status not satisfied (e.g. PIN not verified)
+pub const SW_SECURITY_STATUS_NOT_SATISFIED: u16 = 0x6982;
+
+/// Status word: authentication method blocked (e.g. PIN blocked)
+pub const SW_AUTH_METHOD_BLOCKED: u16 = 0x6983;
+
+/// Status word: conditions of use not satisfied (e.g. slot not programmed)
+pub const SW_CONDITIONS_NOT_SATISFIED: u16 = 0x6985;
+
+/// Status word: incorrect parameters in the command data
+pub const SW_WRONG_DATA: u16 = 0x6a80;
+
+/// Status word: applet or file not found
+pub const SW_FILE_NOT_FOUND: u16 = 0x6a82;
+
+/// Status word: referenced data (e.g. a key) not found
+pub const SW_REFERENCE_NOT_FOUND: u16 = 0x6a88;
+
+/// Status word: instruction not supported
+pub const SW_INS_NOT_SUPPORTED: u16 = 0x6d00;
+
+/// SELECT instruction
+const INS_SELECT: u8 = 0xa4;
+
+/// Select by DF name (AID)
+const P1_SELECT_BY_NAME: u8 = 0x04;
+
+/// Command APDU
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Apdu {
+    /// Class byte
+    pub cla: u8,
+    /// Instruction byte
+    pub ins: u8,
+    /// First parameter
+    pub p1: u8,
+    /// Second parameter
+    pub p2: u8,
+    /// Command data
+    pub data: Vec<u8>,
+}
+
+impl Apdu {
+    /// Creates a command APDU with class byte 0
+    #[must_use]
+    pub fn new(ins: u8, p1: u8, p2: u8, data: &[u8]) -> Self {
+        Self { cla: 0, ins, p1, p2, data: data.to_vec() }
+    }
+
+    /// Creates a SELECT command for the given application identifier
+    #[must_use]
+    pub fn select(aid: &[u8]) -> Self {
+        Self::new(INS_SELECT, P1_SELECT_BY_NAME, 0, aid)
+    }
+
+    /// Encodes the APDU in short form
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data does not fit a short APDU
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let lc = u8::try_from(self.data.len()).map_err(|_| {
+            YkvcError::Protocol(format!("APDU data too long: {} bytes", self.data.len()))
+        })?;
+
+        let mut bytes = vec![self.cla, self.ins, self.p1, self.p2];
+        if lc > 0 {
+            bytes.push(lc);
+            bytes.extend_from_slice(&self.data);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Response APDU
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    /// Response data
+    pub data: Vec<u8>,
+    /// Status word (SW1 SW2)
+    pub sw: u16,
+}
+
+impl Response {
+    /// Splits a raw response into data and status word
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the response is shorter than a status word
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes {
+            [data @ .., sw1, sw2] => {
+                Ok(Self { data: data.to_vec(), sw: u16::from_be_bytes([*sw1, *sw2]) })
+            }
+            _ => Err(YkvcError::Protocol("Response APDU without status word".to_string())),
+        }
+    }
+
+    /// Whether the command succeeded
+    #[must_use]
+    pub const fn is_success(&self) -> bool {
+        self.sw == SW_SUCCESS
+    }
+}
+
+/// Raw APDU exchange with a smart card
+pub trait CardTransport {
+    /// Send a raw command APDU and return the raw response APDU
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transport fails
+    fn transmit(&mut self, command: &[u8]) -> Result<Vec<u8>>;
+
+    /// Encode, send and decode a command APDU
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transport fails or the response is malformed
+    fn send(&mut self, apdu: &Apdu) -> Result<Response> {
+        let raw = self.transmit(&apdu.to_bytes()?)?;
+        Response::from_bytes(&raw)
+    }
+}
+
+impl<T: CardTransport + ?Sized> CardTransport for Box<T> {
+    fn transmit(&mut self, command: &[u8]) -> Result<Vec<u8>> {
+        (**self).transmit(command)
+    }
+}
+
+#[cfg(test)]
+pub mod scripted {
+    //! Scripted card used to test applet logic without a reader
+
+    use super::CardTransport;
+    use crate::error::{Result, YkvcError};
+    use std::collections::VecDeque;
+
+    /// Card that expects an exact sequence of command APDUs
+    #[derive(Debug, Default)]
+    pub struct ScriptedCard {
+        exchanges: VecDeque<(Vec<u8>, Vec<u8>)>,
+    }
+
+    impl ScriptedCard {
+        /// Creates a card from `(command, response)` pairs in hex
+        pub fn new(exchanges: &[(&str, &str)]) -> Self {
+            Self {
+                exchanges: exchanges
+                    .iter()
+                    .map(|(c, r)| (hex::decode(c).unwrap(), hex::decode(r).unwrap()))
+                    .collect(),
+            }
+        }
+
+        /// Whether every scripted exchange has been consumed
+        pub fn is_done(&self) -> bool {
+            self.exchanges.is_empty()
+        }
+    }
+
+    impl CardTransport for ScriptedCard {
+        fn transmit(&mut self, command: &[u8]) -> Result<Vec<u8>> {
+            let (expected, response) = self
+                .exchanges
+                .pop_front()
+                .ok_or_else(|| YkvcError::Protocol("Unexpected APDU".to_string()))?;
+            assert_eq!(hex::encode(command), hex::encode(expected), "Unexpected command APDU");
+            Ok(response)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apdu_encoding() {
+        let apdu = Apdu::select(&[0xa0, 0x00, 0x00, 0x05, 0x27, 0x20, 0x01]);
+        assert_eq!(hex::encode(apdu.to_bytes().unwrap()), "00a4040007a0000005272001");
+
+        let apdu = Apdu::new(0x01, 0x10, 0x00, &[]);
+        assert_eq!(hex::encode(apdu.to_bytes().unwrap()), "00011000");
+
+        let apdu = Apdu::new(0x01, 0x38, 0x00, &[0u8; 256]);
+        assert!(apdu.to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_response_parsing() {
+        let response = Response::from_bytes(&[0x01, 0x02, 0x90, 0x00]).unwrap();
+        assert_eq!(response.data, [0x01, 0x02]);
+        assert!(response.is_success());
+
+        let response = Response::from_bytes(&[0x6a, 0x82]).unwrap();
+        assert!(response.data.is_empty());
+        assert_eq!(response.sw, SW_FILE_NOT_FOUND);
+        assert!(!response.is_success());
+
+        assert!(Response::from_bytes(&[0x90]).is_err());
+    }
+}
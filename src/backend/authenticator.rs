@@ -0,0 +1,564 @@
+//! Software FIDO2 authenticator
+//!
+//! Stands in for a FIDO2 key in tests and demos. It is reached through the
+//! same CTAP2 [`Transport`] as a real authenticator, so every request and
+//! answer is encoded and decoded exactly as on the wire: ES256 credentials
+//! with the `hmac-secret` extension, PIN/UV auth protocol 1 key agreement
+//! and PIN tokens, and `none` attestation. The button counts as pressed as
+//! soon as the touch prompt is shown.
+//!
+//! Credentials and the optional PIN (`pin = "1234"`) live as TOML in a state
+//! file. Unlike a real authenticator, which wraps non-resident keys into the
+//! credential ID, the stand-in keeps every credential in that file and only
+//! records whether it was created as resident. The file holds all secrets in
+//! the clear and must never back real containers.
+
+use super::cbor::{self, Value};
+use super::ctap::{
+    self, Transport, ALG_ECDH_ES_HKDF_256, ALG_ES256, CMD_CLIENT_PIN, CMD_GET_ASSERTION,
+    CMD_GET_INFO, CMD_MAKE_CREDENTIAL, ERR_INVALID_CBOR, ERR_INVALID_COMMAND,
+    ERR_INVALID_PARAMETER, ERR_INVALID_SUBCOMMAND, ERR_MISSING_PARAMETER, ERR_NO_CREDENTIALS,
+    ERR_OTHER, ERR_PIN_AUTH_INVALID, ERR_PIN_INVALID, ERR_PIN_NOT_SET, ERR_PIN_REQUIRED,
+    ERR_UNSUPPORTED_ALGORITHM, FLAG_AT, FLAG_ED, FLAG_UP, FLAG_UV, HMAC_SECRET,
+    PIN_GET_KEY_AGREEMENT, PIN_GET_PIN_TOKEN, PIN_PROTOCOL, SECRET_SIZE, STATUS_OK,
+};
+use crate::crypto;
+use crate::error::{Result, YkvcError};
+use crate::prompt;
+use p256::ecdsa::signature::Signer;
+use p256::ecdsa::{Signature, SigningKey};
+use p256::SecretKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Firmware version reported in `authenticatorGetInfo`, Yubico encoding
+const FIRMWARE: i64 = 0x05_07_01;
+
+/// Model identifier, all zero as for `none` attestation
+const AAGUID: [u8; 16] = [0; 16];
+
+/// Size of a credential ID
+const CREDENTIAL_ID_SIZE: usize = 32;
+
+/// Name reported as the device type
+pub const NAME: &str = "FIDO2 authenticator (emulated)";
+
+/// One credential, secrets in hex
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct StoredCredential {
+    /// Credential ID
+    id: String,
+    /// Relying party the credential is scoped to
+    rp_id: String,
+    /// Whether the credential was created as resident (discoverable)
+    #[serde(default)]
+    resident: bool,
+    /// ES256 private key
+    private_key: String,
+    /// Secret behind the `hmac-secret` outputs
+    cred_random: String,
+}
+
+/// Persistent state of the authenticator
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct AuthenticatorState {
+    /// PIN, if one is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pin: Option<String>,
+    /// Every credential created so far
+    #[serde(default)]
+    credentials: Vec<StoredCredential>,
+}
+
+/// Outcome of a command: the answer, or a CTAP2 status code
+type Reply = std::result::Result<Value, u8>;
+
+/// Software authenticator keeping its credentials in a file
+#[derive(Debug)]
+pub struct SoftAuthenticator {
+    path: PathBuf,
+    touch_timeout: Duration,
+    /// Key agreement key, new for every connection like after a power cycle
+    key_agreement: SecretKey,
+    /// PIN token handed out for the correct PIN
+    pin_token: [u8; 32],
+}
+
+impl SoftAuthenticator {
+    /// Connects to the authenticator whose state is kept in `path`
+    ///
+    /// `touch_timeout` is only shown in the touch prompt.
+    #[must_use]
+    pub fn open(path: PathBuf, touch_timeout: Duration) -> Self {
+        Self {
+            path,
+            touch_timeout,
+            key_agreement: SecretKey::random(&mut rand::rngs::OsRng),
+            pin_token: rand::random(),
+        }
+    }
+
+    fn load(&self) -> Result<AuthenticatorState> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| {
+                YkvcError::FileError(format!(
+                    "Invalid authenticator state {}: {e}",
+                    self.path.display()
+                ))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(AuthenticatorState::default()),
+            Err(e) => Err(YkvcError::FileError(format!(
+                "Failed to read authenticator state {}: {e}",
+                self.path.display()
+            ))),
+        }
+    }
+
+    /// Writes the state, readable by the owner only
+    fn store(&self, state: &AuthenticatorState) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                YkvcError::FileError(format!("Failed to create {}: {e}", parent.display()))
+            })?;
+        }
+
+        let contents = toml::to_string(state)
+            .map_err(|e| YkvcError::Other(format!("Failed to encode authenticator state: {e}")))?;
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.path)
+            .map_err(|e| {
+                YkvcError::FileError(format!("Failed to open {}: {e}", self.path.display()))
+            })?;
+
+        file.write_all(contents.as_bytes())
+            .map_err(|e| YkvcError::FileError(format!("Failed to write authenticator state: {e}")))
+    }
+
+    fn handle(&self, state: &mut AuthenticatorState, command: u8, request: &[u8]) -> Reply {
+        let request = if request.is_empty() {
+            Value::Null
+        } else {
+            cbor::decode(request).map_err(|_| ERR_INVALID_CBOR)?
+        };
+
+        match command {
+            CMD_GET_INFO => Ok(Self::info(state)),
+            CMD_CLIENT_PIN => self.client_pin(state, &request),
+            CMD_MAKE_CREDENTIAL => self.make_credential(state, &request),
+            CMD_GET_ASSERTION => self.get_assertion(state, &request),
+            _ => Err(ERR_INVALID_COMMAND),
+        }
+    }
+
+    fn info(state: &AuthenticatorState) -> Value {
+        Value::map([
+            (0x01, Value::Array(vec![Value::from("FIDO_2_0")])),
+            (0x02, Value::Array(vec![Value::from(HMAC_SECRET)])),
+            (0x03, Value::Bytes(AAGUID.to_vec())),
+            (
+                0x04,
+                Value::map([
+                    ("rk", Value::Bool(true)),
+                    ("up", Value::Bool(true)),
+                    ("clientPin", Value::Bool(state.pin.is_some())),
+                ]),
+            ),
+            (0x06, Value::Array(vec![Value::Int(PIN_PROTOCOL)])),
+            (0x0e, Value::Int(FIRMWARE)),
+        ])
+    }
+
+    fn client_pin(&self, state: &AuthenticatorState, request: &Value) -> Reply {
+        if field(request, 0x01)?.as_int() != Some(PIN_PROTOCOL) {
+            return Err(ERR_INVALID_PARAMETER);
+        }
+
+        match field(request, 0x02)?.as_int() {
+            Some(PIN_GET_KEY_AGREEMENT) => Ok(Value::map([(
+                0x01,
+                ctap::cose_key(&self.key_agreement.public_key(), ALG_ECDH_ES_HKDF_256),
+            )])),
+            Some(PIN_GET_PIN_TOKEN) => {
+                let pin = state.pin.as_ref().ok_or(ERR_PIN_NOT_SET)?;
+                let shared = self.shared_secret(field(request, 0x03)?)?;
+                let pin_hash = ctap::decrypt(&shared, bytes(request, 0x06)?)
+                    .map_err(|_| ERR_INVALID_PARAMETER)?;
+
+                if pin_hash[..] != Sha256::digest(pin.as_bytes())[..16] {
+                    return Err(ERR_PIN_INVALID);
+                }
+                let token = ctap::encrypt(&shared, &self.pin_token).map_err(|_| ERR_OTHER)?;
+                Ok(Value::map([(0x02, Value::Bytes(token))]))
+            }
+            _ => Err(ERR_INVALID_SUBCOMMAND),
+        }
+    }
+
+    fn make_credential(&self, state: &mut AuthenticatorState, request: &Value) -> Reply {
+        let client_data_hash = bytes(request, 0x01)?;
+        let rp_id = field(request, 0x02)?.get("id").and_then(Value::as_text);
+        let rp_id = rp_id.ok_or(ERR_MISSING_PARAMETER)?.to_string();
+        field(request, 0x03)?;
+
+        let algorithms = field(request, 0x04)?.as_array().ok_or(ERR_INVALID_PARAMETER)?;
+        if !algorithms.iter().any(|p| p.get("alg").and_then(Value::as_int) == Some(ALG_ES256)) {
+            return Err(ERR_UNSUPPORTED_ALGORITHM);
+        }
+
+        let verified = self.verify_pin(state, request, client_data_hash)?;
+        let resident = option(request, 0x07, "rk");
+        let hmac_secret = option(request, 0x06, HMAC_SECRET);
+
+        prompt::touch(self.touch_timeout);
+
+        let key = SecretKey::random(&mut rand::rngs::OsRng);
+        let id: [u8; CREDENTIAL_ID_SIZE] = rand::random();
+        let cred_random: [u8; SECRET_SIZE] = rand::random();
+
+        // A new resident credential replaces the one of the same user
+        if resident {
+            state.credentials.retain(|c| !(c.resident && c.rp_id == rp_id));
+        }
+        state.credentials.push(StoredCredential {
+            id: hex::encode(id),
+            rp_id: rp_id.clone(),
+            resident,
+            private_key: hex::encode(key.to_bytes()),
+            cred_random: hex::encode(cred_random),
+        });
+
+        let mut flags = FLAG_UP | FLAG_AT;
+        if verified {
+            flags |= FLAG_UV;
+        }
+        if hmac_secret {
+            flags |= FLAG_ED;
+        }
+
+        let mut data = authenticator_data(&rp_id, flags);
+        data.extend_from_slice(&AAGUID);
+        data.extend_from_slice(&u16::try_from(id.len()).map_err(|_| ERR_OTHER)?.to_be_bytes());
+        data.extend_from_slice(&id);
+        data.extend_from_slice(&cbor::encode(&ctap::cose_key(&key.public_key(), ALG_ES256)));
+        if hmac_secret {
+            data.extend_from_slice(&cbor::encode(&Value::map([(HMAC_SECRET, Value::Bool(true))])));
+        }
+
+        Ok(Value::map([
+            (0x01, Value::from("none")),
+            (0x02, Value::Bytes(data)),
+            (0x03, Value::Map(Vec::new())),
+        ]))
+    }
+
+    /// Checks the PIN token proof if a PIN is set, returns whether the user is verified
+    fn verify_pin(
+        &self,
+        state: &AuthenticatorState,
+        request: &Value,
+        client_data_hash: &[u8],
+    ) -> std::result::Result<bool, u8> {
+        let Some(pin_auth) = request.get(0x08) else {
+            return if state.pin.is_some() { Err(ERR_PIN_REQUIRED) } else { Ok(false) };
+        };
+        if state.pin.is_none() {
+            return Err(ERR_PIN_NOT_SET);
+        }
+        if request.get(0x09).and_then(Value::as_int) != Some(PIN_PROTOCOL)
+            || pin_auth.as_bytes()
+                != Some(&ctap::authenticate(&self.pin_token, client_data_hash)[..])
+        {
+            return Err(ERR_PIN_AUTH_INVALID);
+        }
+        Ok(true)
+    }
+
+    fn get_assertion(&self, state: &AuthenticatorState, request: &Value) -> Reply {
+        let rp_id = field(request, 0x01)?.as_text().ok_or(ERR_INVALID_PARAMETER)?;
+        let client_data_hash = bytes(request, 0x02)?;
+        let allowed: Option<Vec<&[u8]>> = request.get(0x03).and_then(Value::as_array).map(|list| {
+            list.iter().filter_map(|descriptor| descriptor.get("id")?.as_bytes()).collect()
+        });
+
+        let credential = state
+            .credentials
+            .iter()
+            .find(|c| {
+                c.rp_id == rp_id
+                    && allowed
+                        .as_ref()
+                        .map_or(c.resident, |ids| ids.iter().any(|id| hex::encode(id) == c.id))
+            })
+            .ok_or(ERR_NO_CREDENTIALS)?;
+
+        let output = match request.get(0x04).and_then(|e| e.get(HMAC_SECRET)) {
+            Some(input) => Some(self.hmac_secret(credential, input)?),
+            None => None,
+        };
+
+        prompt::touch(self.touch_timeout);
+
+        let mut data =
+            authenticator_data(rp_id, if output.is_some() { FLAG_UP | FLAG_ED } else { FLAG_UP });
+        if let Some(output) = output {
+            data.extend_from_slice(&cbor::encode(&Value::map([(
+                HMAC_SECRET,
+                Value::Bytes(output),
+            )])));
+        }
+
+        let key =
+            SecretKey::from_slice(&decode_hex(&credential.private_key)?).map_err(|_| ERR_OTHER)?;
+        let signature: Signature =
+            SigningKey::from(&key).sign(&[&data[..], client_data_hash].concat());
+
+        Ok(Value::map([
+            (
+                0x01,
+                Value::map([
+                    ("id", Value::Bytes(decode_hex(&credential.id)?)),
+                    ("type", Value::from("public-key")),
+                ]),
+            ),
+            (0x02, Value::Bytes(data)),
+            (0x03, Value::Bytes(signature.to_der().as_bytes().to_vec())),
+        ]))
+    }
+
+    /// Evaluates `hmac-secret` for one or two encrypted salts
+    fn hmac_secret(
+        &self,
+        credential: &StoredCredential,
+        input: &Value,
+    ) -> std::result::Result<Vec<u8>, u8> {
+        let shared = self.shared_secret(field(input, 0x01)?)?;
+        let salt_enc = bytes(input, 0x02)?;
+        if bytes(input, 0x03)? != ctap::authenticate(&shared, salt_enc) {
+            return Err(ERR_PIN_AUTH_INVALID);
+        }
+
+        let salts = ctap::decrypt(&shared, salt_enc).map_err(|_| ERR_INVALID_PARAMETER)?;
+        if salts.len() != SECRET_SIZE && salts.len() != 2 * SECRET_SIZE {
+            return Err(ERR_INVALID_PARAMETER);
+        }
+
+        let cred_random = decode_hex(&credential.cred_random)?;
+        let output: Vec<u8> = salts
+            .chunks(SECRET_SIZE)
+            .flat_map(|salt| crypto::hmac_sha256(&cred_random, salt))
+            .collect();
+        ctap::encrypt(&shared, &output).map_err(|_| ERR_OTHER)
+    }
+
+    fn shared_secret(&self, platform_key: &Value) -> std::result::Result<[u8; 32], u8> {
+        let platform_key = ctap::parse_cose_key(platform_key).map_err(|_| ERR_INVALID_PARAMETER)?;
+        Ok(ctap::shared_secret(&self.key_agreement, &platform_key))
+    }
+}
+
+impl Transport for SoftAuthenticator {
+    fn transact(&mut self, command: u8, request: &[u8]) -> Result<Vec<u8>> {
+        let mut state = self.load()?;
+        let before = state.clone();
+
+        let response = match self.handle(&mut state, command, request) {
+            Ok(Value::Null) => vec![STATUS_OK],
+            Ok(answer) => [vec![STATUS_OK], cbor::encode(&answer)].concat(),
+            Err(status) => vec![status],
+        };
+
+        if state != before {
+            self.store(&state)?;
+        }
+        Ok(response)
+    }
+}
+
+/// Looks up a request parameter the command needs
+fn field(map: &Value, key: i64) -> std::result::Result<&Value, u8> {
+    map.get(key).ok_or(ERR_MISSING_PARAMETER)
+}
+
+/// Looks up a byte string parameter the command needs
+fn bytes(map: &Value, key: i64) -> std::result::Result<&[u8], u8> {
+    field(map, key)?.as_bytes().ok_or(ERR_INVALID_PARAMETER)
+}
+
+/// Whether `name` is set to `true` in the map under `key`
+fn option(request: &Value, key: i64, name: &str) -> bool {
+    request.get(key).and_then(|map| map.get(name)).and_then(Value::as_bool) == Some(true)
+}
+
+/// Decodes a secret from the state file
+fn decode_hex(value: &str) -> std::result::Result<Vec<u8>, u8> {
+    hex::decode(value).map_err(|_| ERR_OTHER)
+}
+
+/// RP ID hash, flags and a zero signature counter
+fn authenticator_data(rp_id: &str, flags: u8) -> Vec<u8> {
+    let mut data = ctap::rp_id_hash(rp_id).to_vec();
+    data.push(flags);
+    data.extend_from_slice(&0u32.to_be_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::ctap::{AuthenticatorData, Client, ERR_PIN_REQUIRED};
+    use p256::ecdsa::signature::Verifier;
+    use p256::ecdsa::VerifyingKey;
+
+    const TIMEOUT: Duration = Duration::from_secs(15);
+
+    fn client(path: &std::path::Path) -> Client<SoftAuthenticator> {
+        Client::new(SoftAuthenticator::open(path.to_path_buf(), TIMEOUT))
+    }
+
+    fn stored(path: &std::path::Path) -> AuthenticatorState {
+        toml::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let info = client(&dir.path().join("fido2.toml")).info().unwrap();
+
+        assert!(info.supports_hmac_secret());
+        assert_eq!(info.option("rk"), Some(true));
+        assert_eq!(info.option("clientPin"), Some(false));
+        assert_eq!(info.firmware.unwrap().to_string(), "5.7.1");
+    }
+
+    #[test]
+    fn test_hmac_secret_matches_credential_secret() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fido2.toml");
+
+        let id = client(&path).make_credential("ykvc", false, None).unwrap();
+        let state = stored(&path);
+        assert_eq!(state.credentials.len(), 1);
+        assert_eq!(state.credentials[0].id, hex::encode(&id));
+        assert!(!state.credentials[0].resident);
+
+        // Every connection agrees on a new shared secret
+        let salt = [0x5a; SECRET_SIZE];
+        let first = client(&path).hmac_secret("ykvc", &id, &salt).unwrap();
+        let second = client(&path).hmac_secret("ykvc", &id, &salt).unwrap();
+        let cred_random = hex::decode(&state.credentials[0].cred_random).unwrap();
+        assert_eq!(first, crypto::hmac_sha256(&cred_random, &salt));
+        assert_eq!(first, second);
+
+        assert_ne!(client(&path).hmac_secret("ykvc", &id, &[0; SECRET_SIZE]).unwrap(), first);
+        assert!(matches!(
+            client(&path).hmac_secret("other", &id, &salt),
+            Err(YkvcError::Fido2Rejected { code: ERR_NO_CREDENTIALS, .. })
+        ));
+        assert!(matches!(
+            client(&path).hmac_secret("ykvc", &[0; CREDENTIAL_ID_SIZE], &salt),
+            Err(YkvcError::Fido2Rejected { code: ERR_NO_CREDENTIALS, .. })
+        ));
+    }
+
+    #[test]
+    fn test_resident_credential_replaces_previous() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fido2.toml");
+
+        client(&path).make_credential("ykvc", true, None).unwrap();
+        let id = client(&path).make_credential("ykvc", true, None).unwrap();
+
+        let state = stored(&path);
+        assert_eq!(state.credentials.len(), 1);
+        assert!(state.credentials[0].resident);
+        assert_eq!(state.credentials[0].id, hex::encode(id));
+    }
+
+    #[test]
+    fn test_pin_is_required() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fido2.toml");
+        fs::write(&path, "pin = \"1234\"\n").unwrap();
+
+        assert!(matches!(
+            client(&path).make_credential("ykvc", false, None),
+            Err(YkvcError::Fido2Rejected { code: ERR_PIN_REQUIRED, .. })
+        ));
+        assert!(matches!(
+            client(&path).pin_token("4321"),
+            Err(YkvcError::Fido2Rejected { code: ERR_PIN_INVALID, .. })
+        ));
+
+        // The token is only valid on the connection that handed it out
+        let mut connection = client(&path);
+        let token = connection.pin_token("1234").unwrap();
+        assert!(matches!(
+            client(&path).make_credential("ykvc", false, Some(&token)),
+            Err(YkvcError::Fido2Rejected { code: ERR_PIN_AUTH_INVALID, .. })
+        ));
+        let id = connection.make_credential("ykvc", false, Some(&token)).unwrap();
+        assert!(client(&path).hmac_secret("ykvc", &id, &[1; SECRET_SIZE]).is_ok());
+    }
+
+    #[test]
+    fn test_assertion_is_signed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fido2.toml");
+        client(&path).make_credential("ykvc", true, None).unwrap();
+        let credential = stored(&path).credentials.remove(0);
+
+        // Without an allow list the resident credential answers
+        let client_data_hash = [7u8; 32];
+        let request = Value::map([
+            (0x01, Value::from("ykvc")),
+            (0x02, Value::Bytes(client_data_hash.to_vec())),
+        ]);
+        let mut authenticator = SoftAuthenticator::open(path, TIMEOUT);
+        let response = ctap::parse_response(
+            &authenticator.transact(CMD_GET_ASSERTION, &cbor::encode(&request)).unwrap(),
+        )
+        .unwrap();
+
+        let data = response.get(0x02).and_then(Value::as_bytes).unwrap();
+        assert_eq!(AuthenticatorData::parse(data).unwrap().flags, FLAG_UP);
+
+        let key = SecretKey::from_slice(&hex::decode(credential.private_key).unwrap()).unwrap();
+        let der = response.get(0x03).and_then(Value::as_bytes).unwrap();
+        let signature = Signature::from_der(der).unwrap();
+        VerifyingKey::from(key.public_key())
+            .verify(&[data, &client_data_hash[..]].concat(), &signature)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_malformed_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut authenticator = SoftAuthenticator::open(dir.path().join("fido2.toml"), TIMEOUT);
+
+        assert_eq!(authenticator.transact(0x42, &[]).unwrap(), [ERR_INVALID_COMMAND]);
+        assert_eq!(
+            authenticator.transact(CMD_MAKE_CREDENTIAL, &[0xff]).unwrap(),
+            [ERR_INVALID_CBOR]
+        );
+        assert_eq!(
+            authenticator
+                .transact(
+                    CMD_MAKE_CREDENTIAL,
+                    &cbor::encode(&Value::map([(0x01, Value::Bytes(vec![0; 32]))]))
+                )
+                .unwrap(),
+            [ERR_MISSING_PARAMETER]
+        );
+        assert!(!dir.path().join("fido2.toml").exists());
+    }
+}
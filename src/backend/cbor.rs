@@ -0,0 +1,403 @@
+//! Minimal CBOR codec for CTAP2
+//!
+//! Covers the subset of CBOR (RFC 8949) that CTAP2 messages use: integers,
+//! byte and text strings, arrays, maps and booleans. Maps are written in the
+//! CTAP2 canonical order (shorter encoded keys first, then bytewise), which
+//! authenticators require. Floats, tags and indefinite lengths are rejected.
+
+use crate::error::{Result, YkvcError};
+
+/// Major types
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_SIMPLE: u8 = 7;
+
+/// Simple values
+const SIMPLE_FALSE: u8 = 20;
+const SIMPLE_TRUE: u8 = 21;
+const SIMPLE_NULL: u8 = 22;
+
+/// Deepest nesting accepted when decoding
+const MAX_DEPTH: usize = 16;
+
+/// A decoded CBOR data item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// Unsigned or negative integer
+    Int(i64),
+    /// Byte string
+    Bytes(Vec<u8>),
+    /// UTF-8 text string
+    Text(String),
+    /// Array of items
+    Array(Vec<Self>),
+    /// Map, in the order the pairs were added or decoded
+    Map(Vec<(Self, Self)>),
+    /// `true` or `false`
+    Bool(bool),
+    /// `null`
+    Null,
+}
+
+impl Value {
+    /// Builds a map from its key/value pairs
+    pub fn map<K: Into<Self>>(entries: impl IntoIterator<Item = (K, Self)>) -> Self {
+        Self::Map(entries.into_iter().map(|(key, value)| (key.into(), value)).collect())
+    }
+
+    /// Looks up `key` in a map
+    ///
+    /// Returns `None` if the key is missing or this is not a map.
+    pub fn get(&self, key: impl Into<Self>) -> Option<&Self> {
+        let key = key.into();
+        match self {
+            Self::Map(entries) => entries.iter().find(|(k, _)| *k == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// The integer, if this is one
+    #[must_use]
+    pub const fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The bytes, if this is a byte string
+    #[must_use]
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// The text, if this is a text string
+    #[must_use]
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Self::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The items, if this is an array
+    #[must_use]
+    pub fn as_array(&self) -> Option<&[Self]> {
+        match self {
+            Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// The boolean, if this is one
+    #[must_use]
+    pub const fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::Text(value.to_string())
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(value: &[u8]) -> Self {
+        Self::Bytes(value.to_vec())
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+/// Encodes `value` in canonical CTAP2 form
+#[must_use]
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_value(&mut out, value);
+    out
+}
+
+fn write_head(out: &mut Vec<u8>, major: u8, argument: u64) {
+    let major = major << 5;
+    match argument {
+        0..=23 => out.push(major | u8::try_from(argument).unwrap_or_default()),
+        24..=0xff => {
+            out.push(major | 0x18);
+            out.push(u8::try_from(argument).unwrap_or_default());
+        }
+        0x100..=0xffff => {
+            out.push(major | 0x19);
+            out.extend_from_slice(&u16::try_from(argument).unwrap_or_default().to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 0x1a);
+            out.extend_from_slice(&u32::try_from(argument).unwrap_or_default().to_be_bytes());
+        }
+        _ => {
+            out.push(major | 0x1b);
+            out.extend_from_slice(&argument.to_be_bytes());
+        }
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Int(n) => match u64::try_from(*n) {
+            Ok(n) => write_head(out, MAJOR_UNSIGNED, n),
+            // -1 - n fits, as n is negative
+            Err(_) => write_head(out, MAJOR_NEGATIVE, (-1 - *n).unsigned_abs()),
+        },
+        Value::Bytes(bytes) => {
+            write_head(out, MAJOR_BYTES, bytes.len() as u64);
+            out.extend_from_slice(bytes);
+        }
+        Value::Text(text) => {
+            write_head(out, MAJOR_TEXT, text.len() as u64);
+            out.extend_from_slice(text.as_bytes());
+        }
+        Value::Array(items) => {
+            write_head(out, MAJOR_ARRAY, items.len() as u64);
+            for item in items {
+                write_value(out, item);
+            }
+        }
+        Value::Map(entries) => {
+            let mut encoded: Vec<(Vec<u8>, Vec<u8>)> =
+                entries.iter().map(|(key, value)| (encode(key), encode(value))).collect();
+            encoded.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+
+            write_head(out, MAJOR_MAP, encoded.len() as u64);
+            for (key, value) in encoded {
+                out.extend_from_slice(&key);
+                out.extend_from_slice(&value);
+            }
+        }
+        Value::Bool(value) => {
+            out.push(MAJOR_SIMPLE << 5 | if *value { SIMPLE_TRUE } else { SIMPLE_FALSE });
+        }
+        Value::Null => out.push(MAJOR_SIMPLE << 5 | SIMPLE_NULL),
+    }
+}
+
+/// Decodes the single data item filling `data`
+///
+/// # Errors
+///
+/// Returns an error if the data is not valid CBOR of the supported subset or
+/// has bytes left over
+pub fn decode(data: &[u8]) -> Result<Value> {
+    let (value, used) = decode_prefix(data)?;
+    if used != data.len() {
+        return Err(malformed("trailing bytes after the data item"));
+    }
+    Ok(value)
+}
+
+/// Decodes the data item at the start of `data`
+///
+/// Returns the item and the number of bytes it took, for data items followed
+/// by other fields (like the credential key in authenticator data).
+///
+/// # Errors
+///
+/// Returns an error if the data does not start with valid CBOR of the
+/// supported subset
+pub fn decode_prefix(data: &[u8]) -> Result<(Value, usize)> {
+    let mut reader = Reader { data, pos: 0 };
+    let value = reader.value(0)?;
+    Ok((value, reader.pos))
+}
+
+fn malformed(message: &str) -> YkvcError {
+    YkvcError::Protocol(format!("Malformed CBOR: {message}"))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.data.len());
+        let end = end.ok_or_else(|| malformed("unexpected end of data"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn argument(&mut self, info: u8) -> Result<u64> {
+        let size = match info {
+            0..=23 => return Ok(u64::from(info)),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => return Err(malformed("indefinite or reserved length")),
+        };
+        Ok(self.take(size)?.iter().fold(0, |n, byte| n << 8 | u64::from(*byte)))
+    }
+
+    fn length(&mut self, info: u8) -> Result<usize> {
+        usize::try_from(self.argument(info)?).map_err(|_| malformed("length too large"))
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(malformed("nested too deeply"));
+        }
+
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+
+        match major {
+            MAJOR_UNSIGNED => i64::try_from(self.argument(info)?)
+                .map(Value::Int)
+                .map_err(|_| malformed("integer out of range")),
+            MAJOR_NEGATIVE => i64::try_from(self.argument(info)?)
+                .map(|n| Value::Int(-1 - n))
+                .map_err(|_| malformed("integer out of range")),
+            MAJOR_BYTES => {
+                let len = self.length(info)?;
+                Ok(Value::Bytes(self.take(len)?.to_vec()))
+            }
+            MAJOR_TEXT => {
+                let len = self.length(info)?;
+                String::from_utf8(self.take(len)?.to_vec())
+                    .map(Value::Text)
+                    .map_err(|_| malformed("text is not UTF-8"))
+            }
+            MAJOR_ARRAY => {
+                let len = self.length(info)?;
+                let mut items = Vec::new();
+                for _ in 0..len {
+                    items.push(self.value(depth + 1)?);
+                }
+                Ok(Value::Array(items))
+            }
+            MAJOR_MAP => {
+                let len = self.length(info)?;
+                let mut entries = Vec::new();
+                for _ in 0..len {
+                    let key = self.value(depth + 1)?;
+                    let value = self.value(depth + 1)?;
+                    entries.push((key, value));
+                }
+                Ok(Value::Map(entries))
+            }
+            MAJOR_SIMPLE => match info {
+                SIMPLE_FALSE => Ok(Value::Bool(false)),
+                SIMPLE_TRUE => Ok(Value::Bool(true)),
+                SIMPLE_NULL => Ok(Value::Null),
+                _ => Err(malformed("unsupported simple value or float")),
+            },
+            _ => Err(malformed("tags are not supported")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_of(value: &Value) -> String {
+        hex::encode(encode(value))
+    }
+
+    #[test]
+    fn test_encode_rfc8949_examples() {
+        assert_eq!(hex_of(&Value::Int(0)), "00");
+        assert_eq!(hex_of(&Value::Int(23)), "17");
+        assert_eq!(hex_of(&Value::Int(24)), "1818");
+        assert_eq!(hex_of(&Value::Int(1000)), "1903e8");
+        assert_eq!(hex_of(&Value::Int(1_000_000)), "1a000f4240");
+        assert_eq!(hex_of(&Value::Int(1_000_000_000_000)), "1b000000e8d4a51000");
+        assert_eq!(hex_of(&Value::Int(-1)), "20");
+        assert_eq!(hex_of(&Value::Int(-1000)), "3903e7");
+        assert_eq!(hex_of(&Value::Int(i64::MIN)), "3b7fffffffffffffff");
+        assert_eq!(hex_of(&Value::from(&[1u8, 2, 3, 4][..])), "4401020304");
+        assert_eq!(hex_of(&Value::from("IETF")), "6449455446");
+        assert_eq!(hex_of(&Value::Bool(true)), "f5");
+        assert_eq!(hex_of(&Value::Null), "f6");
+        assert_eq!(
+            hex_of(&Value::Array(vec![Value::Int(1), Value::Array(vec![Value::Int(2)])])),
+            "82018102"
+        );
+    }
+
+    #[test]
+    fn test_maps_are_canonical() {
+        // Integer keys sort before text keys, shorter keys before longer ones
+        let map = Value::Map(vec![
+            (Value::from("up"), Value::Bool(true)),
+            (Value::from("a"), Value::Int(1)),
+            (Value::Int(-1), Value::Int(2)),
+            (Value::Int(3), Value::Int(3)),
+            (Value::Int(1), Value::Int(4)),
+        ]);
+        assert_eq!(hex_of(&map), "a5010403032002616101627570f5");
+    }
+
+    #[test]
+    fn test_decode_round_trip() {
+        let value = Value::map([
+            (1, Value::from("FIDO_2_0")),
+            (2, Value::Array(vec![Value::from("hmac-secret")])),
+            (3, Value::Bytes(vec![0xab; 300])),
+            (4, Value::map([("rk", Value::Bool(true)), ("clientPin", Value::Bool(false))])),
+            (-25, Value::Null),
+        ]);
+        let decoded = decode(&encode(&value)).unwrap();
+
+        assert_eq!(encode(&decoded), encode(&value));
+        assert_eq!(decoded.get(1).and_then(Value::as_text), Some("FIDO_2_0"));
+        assert_eq!(decoded.get(3).and_then(Value::as_bytes).map(<[u8]>::len), Some(300));
+        assert_eq!(decoded.get(4).and_then(|o| o.get("rk")).and_then(Value::as_bool), Some(true));
+        assert_eq!(decoded.get(-25), Some(&Value::Null));
+        assert_eq!(decoded.get(5), None);
+    }
+
+    #[test]
+    fn test_decode_prefix_reports_length() {
+        let mut data = encode(&Value::map([(1, Value::Int(2))]));
+        let len = data.len();
+        data.extend_from_slice(&[0xde, 0xad]);
+
+        let (value, used) = decode_prefix(&data).unwrap();
+        assert_eq!(used, len);
+        assert_eq!(value.get(1).and_then(Value::as_int), Some(2));
+        assert!(decode(&data).is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed() {
+        for data in ["", "62ff", "5f", "fa47c35000", "c074", "1bffffffffffffffff", "62c328"] {
+            let bytes = hex::decode(data).unwrap();
+            assert!(matches!(decode(&bytes), Err(YkvcError::Protocol(_))), "{data}");
+        }
+
+        let deep = [vec![0x81; MAX_DEPTH + 2], vec![0x00]].concat();
+        assert!(decode(&deep).is_err());
+    }
+}
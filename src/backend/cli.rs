@@ -0,0 +1,761 @@
+//! Command-line tool backend
+//!
+//! Talks to the `YubiKey` through the external Yubico tools:
+//! - `ykman` - `YubiKey` Manager for device information
+//! - `ykpersonalize` - `YubiKey` Personalization Tool for programming slots
+//! - `ykchalresp` - Challenge-Response tool for generating responses
+//!
+//! The last two come from the deprecated yubikey-personalization package.
+//! Without them, the [`Toolchain::Ykman`] toolchain programs the slots with
+//! `ykman otp chalresp` and computes responses with `ykman otp calculate`.
+//!
+//! Command lines are world-readable through `ps` and `/proc/<pid>/cmdline`, so
+//! secrets, challenges and access codes are only ever written to the tool's
+//! stdin. Operations a tool can only take on its command line are refused.
+//!
+//! The tools run under the configured tool timeout and are retried when the
+//! device reports a transient USB error, see [`crate::process`].
+
+use super::{otp, TokenBackend};
+use crate::error::{Result, YkvcError};
+use crate::platform::Toolchain;
+use crate::process::Tool;
+use crate::yubikey::{
+    AccessCode, Application, FirmwareVersion, FormFactor, ProgramOptions, Slot, SlotConfig,
+    YubiKeyInfo,
+};
+use std::process::Output;
+use std::time::{Duration, Instant};
+
+/// Backend that shells out to `ykman`, `ykpersonalize` and `ykchalresp`
+///
+/// `ykpersonalize` and `ykchalresp` cannot pick a device by serial number, so
+/// when a serial is selected programming and challenge-response go through
+/// `ykman otp` instead, as they always do with the ykman-only toolchain.
+#[derive(Debug, Clone, Copy)]
+pub struct CliBackend {
+    serial: Option<u32>,
+    toolchain: Toolchain,
+}
+
+impl CliBackend {
+    /// Creates a new command-line tool backend, optionally bound to one serial number
+    #[must_use]
+    pub const fn new(serial: Option<u32>, toolchain: Toolchain) -> Self {
+        Self { serial, toolchain }
+    }
+
+    /// Whether programming and challenge-response go through `ykman otp`
+    fn uses_ykman_otp(self) -> bool {
+        self.serial.is_some() || self.toolchain == Toolchain::Ykman
+    }
+
+    /// Arguments of a `ykman` invocation targeting the selected device
+    fn ykman_args(self, args: &[&str]) -> Vec<String> {
+        let mut ykman_args = Vec::new();
+        if let Some(serial) = self.serial {
+            ykman_args.extend(["--device".to_string(), serial.to_string()]);
+        }
+        ykman_args.extend(args.iter().map(ToString::to_string));
+        ykman_args
+    }
+
+    /// Prepares a `ykman` run against the selected device
+    fn ykman<'a>(self, args: &[&str]) -> Tool<'a> {
+        device_tool("ykman").args(self.ykman_args(args))
+    }
+
+    /// Runs `ykman` against the selected device and returns its stdout
+    fn run_ykman(self, args: &[&str]) -> Result<String> {
+        self.check_ykman(args, &self.ykman(args).run()?)
+    }
+
+    /// Runs `ykman` with `input` answering its prompts on stdin and returns its stdout
+    fn run_ykman_input(self, args: &[&str], input: &str) -> Result<String> {
+        self.check_ykman(args, &self.ykman(args).input(input).run()?)
+    }
+
+    /// Runs a `ykman` command that may wait for touch, killing it after `touch_timeout`
+    ///
+    /// `input` answers the prompts of the command on stdin.
+    fn run_ykman_touch(
+        self,
+        args: &[&str],
+        input: &str,
+        touch_timeout: Duration,
+    ) -> Result<String> {
+        let output = self.ykman(args).input(input).wait_for_touch(touch_timeout).run()?;
+        self.check_ykman(args, &output)
+    }
+
+    /// Returns the stdout of a finished `ykman` run, or the error it reported
+    fn check_ykman(self, args: &[&str], output: &Output) -> Result<String> {
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(self.classify_failure(&stderr).unwrap_or_else(|| {
+                YkvcError::YkmanFailed(format!("ykman {} failed: {stderr}", args.join(" ")))
+            }));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Deletes a freshly programmed slot whose access code could not be
+    /// changed, after `step` failed with `cause`
+    ///
+    /// `ykman otp chalresp` keeps the code the slot had, so `access_code`
+    /// still locks the new configuration and is presented to delete it.
+    /// Returns the error to report, saying what state the slot is left in.
+    fn roll_back_access_code(
+        self,
+        slot: Slot,
+        access_code: Option<AccessCode>,
+        step: &str,
+        cause: &YkvcError,
+    ) -> YkvcError {
+        let slot_arg = slot.to_string();
+        let mut args = otp_group(access_code.is_some());
+        args.extend(["delete", "--force", &slot_arg]);
+
+        match self.run_ykman_input(&args, &prompt_input(access_code, &[])) {
+            Ok(_) => YkvcError::YkmanFailed(format!(
+                "{step} of slot {slot} failed, so the new configuration was deleted again and \
+                 the slot is empty: {cause}"
+            )),
+            Err(e) if access_code.is_some() => YkvcError::YkmanFailed(format!(
+                "Slot {slot} holds the new configuration, still locked with its current access \
+                 code: {step} failed ({cause}) and deleting the configuration failed too ({e})"
+            )),
+            Err(e) => YkvcError::YkmanFailed(format!(
+                "Slot {slot} is programmed but NOT protected: {step} failed ({cause}) and \
+                 deleting the configuration failed too ({e}). Delete the slot or set an access \
+                 code with ykman before using it"
+            )),
+        }
+    }
+
+    /// Maps device selection and write failures reported on stderr to errors
+    fn classify_failure(self, stderr: &str) -> Option<YkvcError> {
+        if is_multiple_devices(stderr) {
+            return Some(YkvcError::MultipleDevices);
+        }
+
+        if is_write_refused(stderr) {
+            return Some(YkvcError::CommandRejected);
+        }
+
+        if is_not_found(stderr) {
+            return Some(self.serial.map_or(YkvcError::YubiKeyNotFound, YkvcError::SerialNotFound));
+        }
+
+        None
+    }
+
+    /// Lists the serial numbers of all connected devices with `ykman list --serials`
+    fn serials() -> Result<Vec<u32>> {
+        Self::new(None, Toolchain::Ykman)
+            .run_ykman(&["list", "--serials"])?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                line.parse().map_err(|_| {
+                    YkvcError::YkmanFailed(format!("Could not parse serial number '{line}'"))
+                })
+            })
+            .collect()
+    }
+
+    /// Fails if the Yubico OTP tools would have to pick among several devices
+    fn require_single_device() -> Result<()> {
+        if Self::serials()?.len() > 1 {
+            return Err(YkvcError::MultipleDevices);
+        }
+        Ok(())
+    }
+}
+
+/// Prepares a run of one of the Yubico tools
+fn device_tool<'a>(program: &str) -> Tool<'a> {
+    Tool::new(program).retry_transient()
+}
+
+/// Returns `true` if the tool's stderr indicates that no device is connected
+fn is_not_found(stderr: &str) -> bool {
+    stderr.contains("No YubiKey detected")
+        || stderr.contains("not connected")
+        || stderr.contains("Failed connecting to a YubiKey with serial")
+}
+
+/// Returns `true` if `ykman` refused to pick among several connected devices
+fn is_multiple_devices(stderr: &str) -> bool {
+    stderr.contains("Multiple YubiKeys detected")
+}
+
+/// Returns `true` if the device refused a slot write, usually over the access code
+fn is_write_refused(stderr: &str) -> bool {
+    stderr.contains("restricted access") || stderr.contains("write error")
+}
+
+/// Arguments selecting the `ykman otp` command group
+///
+/// With `access_code` set, `ykman` prompts for the current access code, which
+/// must then be the first line of its stdin.
+fn otp_group(access_code: bool) -> Vec<&'static str> {
+    let mut args = vec!["otp"];
+    if access_code {
+        args.extend(["--access-code", "-"]);
+    }
+    args
+}
+
+/// Stdin answering the access code prompt of [`otp_group`], if any, followed by `lines`
+fn prompt_input(access_code: Option<AccessCode>, lines: &[&str]) -> String {
+    let mut input = access_code.map(AccessCode::to_hex).unwrap_or_default();
+    if !input.is_empty() {
+        input.push('\n');
+    }
+    for line in lines {
+        input.push_str(line);
+        input.push('\n');
+    }
+    input
+}
+
+/// Slot option understood by `ykpersonalize` and `ykchalresp`
+const fn slot_flag(slot: Slot) -> &'static str {
+    match slot {
+        Slot::One => "-1",
+        Slot::Two => "-2",
+    }
+}
+
+/// Checks `ykman otp info` output for the slot
+///
+/// Output typically contains "Slot 2: programmed" or "Slot 2: empty". `ykman`
+/// does not say what a programmed slot holds.
+fn otp_slot_config(otp_info: &str, slot: Slot) -> SlotConfig {
+    let label = format!("slot {slot}");
+    let programmed = otp_info
+        .lines()
+        .map(str::to_lowercase)
+        .any(|line| line.contains(&label) && line.contains("programmed"));
+
+    if programmed {
+        SlotConfig::Unknown
+    } else {
+        SlotConfig::Empty
+    }
+}
+
+/// Parses `ykman info` output
+///
+/// The output is a list of `Name: value` lines followed by a table of the
+/// applications and whether each is enabled over USB and, on keys with NFC,
+/// over NFC. Keys without NFC have a single status column. Slot fields are
+/// left for `ykman otp info` to fill in.
+///
+/// # Errors
+///
+/// Returns an error if the serial number or firmware version is missing
+fn parse_ykman_info(stdout: &str) -> Result<YubiKeyInfo> {
+    let mut info = YubiKeyInfo::default();
+    let mut serial = None;
+    let mut firmware = None;
+
+    let mut lines = stdout.lines();
+    while let Some(line) = lines.next() {
+        if line.starts_with("Applications") {
+            let (usb, nfc) = parse_applications(line, &mut lines);
+            info.usb_applications = Some(usb);
+            info.nfc_applications = nfc;
+            continue;
+        }
+
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_lowercase().as_str() {
+            "device type" => info.device_type = Some(value.to_string()),
+            "serial number" => serial = Some(value.to_string()),
+            "firmware version" => firmware = FirmwareVersion::parse(value),
+            "form factor" => info.form_factor = FormFactor::from_label(value),
+            _ => {}
+        }
+    }
+
+    info.serial = serial
+        .ok_or_else(|| YkvcError::YkmanFailed("Could not parse serial number".to_string()))?;
+    info.firmware = firmware
+        .ok_or_else(|| YkvcError::YkmanFailed("Could not parse firmware version".to_string()))?;
+    info.fips = info.device_type.as_ref().map(|device_type| device_type.contains("FIPS"));
+    Ok(info)
+}
+
+/// Reads the applications table up to the next blank line
+///
+/// `header` names the status columns; without an `NFC` column the key has no
+/// NFC and the NFC list is `None`.
+fn parse_applications<'a>(
+    header: &str,
+    rows: &mut impl Iterator<Item = &'a str>,
+) -> (Vec<Application>, Option<Vec<Application>>) {
+    let has_nfc = header.split('\t').any(|column| column.trim() == "NFC");
+    let mut usb = Vec::new();
+    let mut nfc = Vec::new();
+
+    for row in rows.take_while(|row| !row.trim().is_empty()) {
+        let columns: Vec<&str> = row.split('\t').map(str::trim).collect();
+        let Some(application) = columns.first().copied().and_then(Application::from_name) else {
+            continue;
+        };
+        if columns.get(1) == Some(&"Enabled") {
+            usb.push(application);
+        }
+        if columns.get(2) == Some(&"Enabled") {
+            nfc.push(application);
+        }
+    }
+
+    (usb, has_nfc.then_some(nfc))
+}
+
+impl TokenBackend for CliBackend {
+    fn requires_dependencies(&self) -> bool {
+        true
+    }
+
+    fn uses_slots(&self) -> bool {
+        true
+    }
+
+    /// Runs `ykman list` and checks whether any device is reported
+    fn detect(&self) -> Result<bool> {
+        let output = device_tool("ykman").arg("list").run()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if is_not_found(&stderr) {
+                return Ok(false);
+            }
+            return Err(YkvcError::YkmanFailed(format!("ykman list failed: {stderr}")));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).lines().any(|line| !line.trim().is_empty()))
+    }
+
+    /// Runs `ykman info` for every serial reported by `ykman list --serials`
+    fn list(&self) -> Result<Vec<YubiKeyInfo>> {
+        Self::serials()?
+            .into_iter()
+            .map(|serial| Self::new(Some(serial), self.toolchain).info())
+            .collect()
+    }
+
+    /// Runs `ykman info` and `ykman otp info` to get device and slot details
+    fn info(&self) -> Result<YubiKeyInfo> {
+        let info = parse_ykman_info(&self.run_ykman(&["info"])?)?;
+        let otp_info = self.run_ykman(&["otp", "info"])?;
+
+        Ok(YubiKeyInfo {
+            slot1_config: otp_slot_config(&otp_info, Slot::One),
+            slot2_config: otp_slot_config(&otp_info, Slot::Two),
+            // ykman cannot tell whether a slot has an access code
+            slot1_protected: None,
+            slot2_protected: None,
+            ..info
+        })
+    }
+
+    /// Runs `ykman otp info` and checks if the slot is programmed
+    fn slot_config(&self, slot: Slot) -> Result<SlotConfig> {
+        Ok(otp_slot_config(&self.run_ykman(&["otp", "info"])?, slot))
+    }
+
+    /// Programs the slot using `ykpersonalize` with the following configuration:
+    /// - HMAC-SHA1 Challenge-Response mode
+    /// - Challenges shorter than 64 bytes, unless fixed 64-byte challenges are requested
+    /// - Serial number visible via API
+    /// - Button-triggered challenges, if touch is required
+    /// - Access code, if one is requested
+    ///
+    /// The secret is written to the stdin of `ykpersonalize`. The tool only takes
+    /// access codes on its command line, so writes involving one are refused.
+    ///
+    /// With a serial selected or the ykman-only toolchain, `ykman otp chalresp`
+    /// writes the same configuration, keeping the current access code, and
+    /// `ykman otp settings` then sets the new code or removes the current one,
+    /// all prompted for the secret and codes on stdin. If that fails, the slot
+    /// is deleted again rather than left with the wrong protection.
+    fn program(
+        &self,
+        slot: Slot,
+        secret: &[u8],
+        options: ProgramOptions,
+        access_code: Option<AccessCode>,
+    ) -> Result<()> {
+        // Convert secret to hex format for ykpersonalize
+        let secret_hex = hex::encode(secret);
+        let slot_arg = slot.to_string();
+
+        if self.uses_ykman_otp() {
+            if options.fixed_64 {
+                return Err(YkvcError::YkmanFailed(
+                    "ykman cannot program fixed 64-byte challenges, use the hid backend"
+                        .to_string(),
+                ));
+            }
+
+            // The secret is prompted for when left off the command line
+            let mut args = otp_group(access_code.is_some());
+            args.extend(["chalresp", "--force"]);
+            if options.require_touch {
+                args.push("--touch");
+            }
+            args.push(&slot_arg);
+            self.run_ykman_input(&args, &prompt_input(access_code, &[&secret_hex]))?;
+
+            // The new configuration still has the current code, if any
+            let mut args = otp_group(access_code.is_some());
+            args.extend(["settings", "--force"]);
+            let (step, input) = match options.access_code.map(AccessCode::to_hex) {
+                Some(new_code) => {
+                    args.extend(["--new-access-code", "-"]);
+                    // The new code is entered twice, once more for confirmation
+                    ("Setting the access code", prompt_input(access_code, &[&new_code, &new_code]))
+                }
+                None if access_code.is_some() => {
+                    args.push("--delete-access-code");
+                    ("Removing the access code", prompt_input(access_code, &[]))
+                }
+                None => return Ok(()),
+            };
+            args.push(&slot_arg);
+            if let Err(e) = self.run_ykman_input(&args, &input) {
+                return Err(self.roll_back_access_code(slot, access_code, step, &e));
+            }
+            return Ok(());
+        }
+
+        if access_code.is_some() || options.access_code.is_some() {
+            return Err(YkvcError::YkpersonalizeFailed(
+                "ykpersonalize only takes access codes on its command line, where other local \
+                 users can read them. Select the YubiKey with --serial or use the hid backend"
+                    .to_string(),
+            ));
+        }
+
+        Self::require_single_device()?;
+
+        // Run ykpersonalize with secret via stdin
+        let mut args = vec![slot_flag(slot)];
+        if options.require_touch {
+            args.push("-ochal-btn-trig"); // Wait for touch on every challenge
+        }
+        if !options.fixed_64 {
+            args.push("-ohmac-lt64"); // Challenges shorter than 64 bytes
+        }
+        args.extend([
+            "-ochal-resp",          // Challenge-Response mode
+            "-ochal-hmac",          // HMAC mode
+            "-oserial-api-visible", // Make serial visible
+            "-y",                   // Skip confirmation
+            "-a",                   // Secret from stdin (hex format)
+        ]);
+        let input = format!("{secret_hex}\n");
+        let output = device_tool("ykpersonalize").args(args).input(&input).run()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if is_write_refused(&stderr) {
+                return Err(YkvcError::CommandRejected);
+            }
+            return Err(YkvcError::YkpersonalizeFailed(format!("ykpersonalize failed: {stderr}")));
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the slot configuration with `ykman otp delete`
+    fn delete(&self, slot: Slot, access_code: Option<AccessCode>) -> Result<()> {
+        let slot_arg = slot.to_string();
+
+        let mut args = otp_group(access_code.is_some());
+        args.extend(["delete", "--force", &slot_arg]);
+        self.run_ykman_input(&args, &prompt_input(access_code, &[]))?;
+        Ok(())
+    }
+
+    /// Swaps the slot configurations with `ykman otp swap`
+    fn swap(&self) -> Result<()> {
+        self.run_ykman(&["otp", "swap", "--force"])?;
+        Ok(())
+    }
+
+    /// Sends the challenge to the slot with `ykchalresp -x` and decodes the hex response
+    ///
+    /// The challenge is padded to the full 64-byte block before it is handed to
+    /// the tool on stdin, so both tools send exactly what the native backends
+    /// send. With a serial selected or the ykman-only toolchain, `ykman otp
+    /// calculate` is used instead and prompted for the challenge. Either tool
+    /// is killed if the key is not touched within `touch_timeout`.
+    fn challenge(&self, slot: Slot, challenge: &[u8], touch_timeout: Duration) -> Result<Vec<u8>> {
+        let block_hex = hex::encode(otp::pad_hmac_challenge(challenge)?);
+
+        if self.uses_ykman_otp() {
+            let stdout = self.run_ykman_touch(
+                &["otp", "calculate", &slot.to_string()],
+                &format!("{block_hex}\n"),
+                touch_timeout,
+            )?;
+            return hex::decode(stdout.trim()).map_err(|e| {
+                YkvcError::YkmanFailed(format!("Failed to decode hex response: {e}"))
+            });
+        }
+
+        Self::require_single_device()?;
+
+        let started = Instant::now();
+        let output = device_tool("ykchalresp")
+            .args([
+                slot_flag(slot),
+                "-x", // Challenge in hex
+                "-i", // Challenge read from a file...
+                "-",  // ...which is stdin
+            ])
+            .input(&block_hex)
+            .wait_for_touch(touch_timeout)
+            .run()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            if is_not_found(&stderr) {
+                return Err(YkvcError::YubiKeyNotFound);
+            }
+
+            // The device gave up waiting for the button on its own
+            if stderr.to_lowercase().contains("timeout") {
+                return Err(YkvcError::TouchTimeout(started.elapsed().as_secs()));
+            }
+
+            if stderr.contains(&format!("slot {slot}")) && stderr.contains("not programmed") {
+                return Err(YkvcError::SlotNotProgrammed(slot));
+            }
+
+            return Err(YkvcError::YkchalrespFailed(format!("ykchalresp failed: {stderr}")));
+        }
+
+        // Parse hex response from stdout
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let response_hex = stdout.trim();
+
+        hex::decode(response_hex)
+            .map_err(|e| YkvcError::YkchalrespFailed(format!("Failed to decode hex response: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_backend_requires_dependencies() {
+        assert!(CliBackend::new(None, Toolchain::Personalization).requires_dependencies());
+    }
+
+    #[test]
+    fn test_uses_ykman_otp() {
+        assert!(CliBackend::new(None, Toolchain::Ykman).uses_ykman_otp());
+        assert!(CliBackend::new(Some(42), Toolchain::Personalization).uses_ykman_otp());
+        assert!(!CliBackend::new(None, Toolchain::Personalization).uses_ykman_otp());
+    }
+
+    #[test]
+    fn test_ykman_targets_selected_device() {
+        let args = CliBackend::new(Some(12_345_678), Toolchain::Personalization)
+            .ykman_args(&["otp", "info"]);
+        assert_eq!(args, ["--device", "12345678", "otp", "info"]);
+
+        assert_eq!(
+            CliBackend::new(None, Toolchain::Personalization).ykman_args(&["info"]),
+            ["info"]
+        );
+    }
+
+    #[test]
+    fn test_classify_failure() {
+        let backend = CliBackend::new(Some(42), Toolchain::Personalization);
+        assert!(matches!(
+            backend.classify_failure("ERROR: Failed connecting to a YubiKey with serial: 42"),
+            Some(YkvcError::SerialNotFound(42))
+        ));
+        assert!(matches!(
+            CliBackend::new(None, Toolchain::Personalization)
+                .classify_failure("ERROR: No YubiKey detected!"),
+            Some(YkvcError::YubiKeyNotFound)
+        ));
+        assert!(matches!(
+            CliBackend::new(None, Toolchain::Personalization).classify_failure(
+                "ERROR: Multiple YubiKeys detected. Use --device SERIAL to specify which one to use."
+            ),
+            Some(YkvcError::MultipleDevices)
+        ));
+        assert!(matches!(
+            backend.classify_failure(
+                "ERROR: Failed to write to the YubiKey. Make sure the device does not have restricted access."
+            ),
+            Some(YkvcError::CommandRejected)
+        ));
+        assert!(backend.classify_failure("ERROR: Something else").is_none());
+    }
+
+    #[test]
+    fn test_otp_group() {
+        assert_eq!(otp_group(false), ["otp"]);
+        assert_eq!(otp_group(true), ["otp", "--access-code", "-"]);
+    }
+
+    #[test]
+    fn test_prompt_input() {
+        let code = AccessCode::from_hex("010203040506").unwrap();
+        assert_eq!(prompt_input(None, &["abcd"]), "abcd\n");
+        assert_eq!(prompt_input(Some(code), &["abcd"]), "010203040506\nabcd\n");
+        assert_eq!(prompt_input(Some(code), &[]), "010203040506\n");
+    }
+
+    #[test]
+    fn test_is_write_refused() {
+        assert!(is_write_refused("Yubikey core error: write error"));
+        assert!(!is_write_refused("Yubikey core error: no yubikey present"));
+    }
+
+    #[test]
+    fn test_otp_slot_config() {
+        let otp_info = "Slot 1: programmed\nSlot 2: empty\n";
+        assert_eq!(otp_slot_config(otp_info, Slot::One), SlotConfig::Unknown);
+        assert_eq!(otp_slot_config(otp_info, Slot::Two), SlotConfig::Empty);
+    }
+
+    /// Parses the `ykman info` output of one device in `tests/fixtures/ykman-info`
+    macro_rules! corpus {
+        ($name:literal) => {
+            parse_ykman_info(include_str!(concat!(
+                "../../tests/fixtures/ykman-info/",
+                $name,
+                ".txt"
+            )))
+            .unwrap()
+        };
+    }
+
+    #[test]
+    fn test_parse_ykman_info_5_nfc() {
+        let info = corpus!("yubikey-5-nfc-5.4.3");
+        assert_eq!(info.serial, "12345678");
+        assert_eq!(info.firmware, FirmwareVersion::new(5, 4, 3));
+        assert_eq!(info.device_type.as_deref(), Some("YubiKey 5 NFC"));
+        assert_eq!(info.form_factor, Some(FormFactor::KeychainUsbA));
+        assert_eq!(info.fips, Some(false));
+        assert_eq!(
+            info.usb_applications.unwrap(),
+            [
+                Application::Otp,
+                Application::U2f,
+                Application::Fido2,
+                Application::Oath,
+                Application::Piv,
+                Application::OpenPgp,
+            ]
+        );
+        assert_eq!(
+            info.nfc_applications.unwrap(),
+            [
+                Application::Otp,
+                Application::U2f,
+                Application::Fido2,
+                Application::Oath,
+                Application::OpenPgp,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ykman_info_without_nfc() {
+        let info = corpus!("yubikey-5ci-5.2.7");
+        assert_eq!(info.firmware, FirmwareVersion::new(5, 2, 7));
+        assert_eq!(info.form_factor, Some(FormFactor::KeychainUsbCLightning));
+        assert!(!info.usb_applications.unwrap().contains(&Application::Piv));
+        assert_eq!(info.nfc_applications, None);
+
+        let info = corpus!("yubikey-4-4.3.7");
+        assert_eq!(info.firmware, FirmwareVersion::new(4, 3, 7));
+        assert_eq!(info.device_type.as_deref(), Some("YubiKey 4"));
+        assert_eq!(info.form_factor, None);
+        assert!(!info.usb_applications.unwrap().contains(&Application::Fido2));
+        assert_eq!(info.nfc_applications, None);
+    }
+
+    #[test]
+    fn test_parse_ykman_info_neo() {
+        let info = corpus!("yubikey-neo-3.4.9");
+        assert_eq!(info.serial, "3456789");
+        assert_eq!(info.firmware, FirmwareVersion::new(3, 4, 9));
+        assert_eq!(info.form_factor, None);
+        assert!(!info.usb_applications.unwrap().contains(&Application::U2f));
+        assert!(info.nfc_applications.unwrap().contains(&Application::Otp));
+    }
+
+    #[test]
+    fn test_parse_ykman_info_fips() {
+        let info = corpus!("yubikey-fips-4.4.5");
+        assert_eq!(info.fips, Some(true));
+        assert_eq!(info.form_factor, Some(FormFactor::NanoUsbA));
+
+        let info = corpus!("yubikey-5-nfc-fips-5.7.2");
+        assert_eq!(info.fips, Some(true));
+        assert_eq!(info.firmware, FirmwareVersion::new(5, 7, 2));
+        assert!(info.nfc_applications.unwrap().contains(&Application::HsmAuth));
+    }
+
+    #[test]
+    fn test_parse_ykman_info_incomplete() {
+        assert!(parse_ykman_info("Device type: YubiKey 5 NFC\nFirmware version: 5.4.3\n").is_err());
+        assert!(parse_ykman_info("Serial number: 1\nFirmware version: unknown\n").is_err());
+
+        let info = parse_ykman_info("Serial number: 1\nFirmware version: 5.1.2\n").unwrap();
+        assert_eq!(info.device_type, None);
+        assert_eq!(info.fips, None);
+        assert_eq!(info.usb_applications, None);
+    }
+
+    #[test]
+    fn test_slot_flag() {
+        assert_eq!(slot_flag(Slot::One), "-1");
+        assert_eq!(slot_flag(Slot::Two), "-2");
+    }
+
+    #[test]
+    fn test_is_not_found() {
+        assert!(is_not_found("Error: No YubiKey detected!"));
+        assert!(is_not_found("YubiKey not connected"));
+        assert!(!is_not_found("Error: failed to open device"));
+    }
+
+    // Note: The following tests require mocking or actual YubiKey hardware
+    // They are documented here for coverage awareness:
+    //
+    // - info() with real hardware
+    // - info() with no device connected
+    // - slot_config() with programmed slot
+    // - delete() and swap()
+    // - slot_config() with empty slot
+    // - program() successful programming
+    // - challenge() with various challenge strings
+    // - challenge() with empty challenge
+    // - challenge() with no device
+    // - challenge() with unprogrammed slot
+    //
+    // These are tested via integration tests with real or mocked hardware
+}
@@ -0,0 +1,765 @@
+//! CTAP2 protocol
+//!
+//! Builds and parses the messages of the FIDO2 Client to Authenticator
+//! Protocol that ykvc needs: `authenticatorGetInfo`, `authenticatorMakeCredential`
+//! and `authenticatorGetAssertion` with the `hmac-secret` extension, and the
+//! parts of `authenticatorClientPIN` (PIN/UV auth protocol 1) that agree on a
+//! shared secret and fetch a PIN token. Commands travel over a [`Transport`],
+//! which only moves bytes.
+//!
+//! Protocol 1 derives the shared secret as SHA-256 of the ECDH (P-256)
+//! x-coordinate. Salts, PIN hashes and `hmac-secret` outputs are encrypted with
+//! AES-256-CBC under it with a zero IV, and authenticated with the first 16
+//! bytes of HMAC-SHA256.
+
+use super::cbor::{self, Value};
+use crate::crypto;
+use crate::error::{Result, YkvcError};
+use crate::yubikey::FirmwareVersion;
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes256;
+use p256::elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint};
+use p256::{EncodedPoint, PublicKey, SecretKey};
+use sha2::{Digest, Sha256};
+
+/// Creates a credential
+pub const CMD_MAKE_CREDENTIAL: u8 = 0x01;
+
+/// Asserts a credential, evaluating extensions such as `hmac-secret`
+pub const CMD_GET_ASSERTION: u8 = 0x02;
+
+/// Reports versions, extensions and options
+pub const CMD_GET_INFO: u8 = 0x04;
+
+/// PIN and key agreement subcommands
+pub const CMD_CLIENT_PIN: u8 = 0x06;
+
+/// `authenticatorClientPIN` subcommand returning the authenticator's key agreement key
+pub const PIN_GET_KEY_AGREEMENT: i64 = 0x02;
+
+/// `authenticatorClientPIN` subcommand exchanging the encrypted PIN hash for a PIN token
+pub const PIN_GET_PIN_TOKEN: i64 = 0x05;
+
+/// PIN/UV auth protocol spoken by ykvc
+pub const PIN_PROTOCOL: i64 = 1;
+
+/// Status: success
+pub const STATUS_OK: u8 = 0x00;
+
+/// Status: the command is not supported
+pub const ERR_INVALID_COMMAND: u8 = 0x01;
+
+/// Status: a parameter has the wrong type or value
+pub const ERR_INVALID_PARAMETER: u8 = 0x02;
+
+/// Status: the request is not valid CBOR
+pub const ERR_INVALID_CBOR: u8 = 0x12;
+
+/// Status: a required parameter is missing
+pub const ERR_MISSING_PARAMETER: u8 = 0x14;
+
+/// Status: none of the offered credential algorithms is supported
+pub const ERR_UNSUPPORTED_ALGORITHM: u8 = 0x26;
+
+/// Status: the request was cancelled by the platform
+pub const ERR_KEEPALIVE_CANCEL: u8 = 0x2d;
+
+/// Status: none of the allowed credentials is known
+pub const ERR_NO_CREDENTIALS: u8 = 0x2e;
+
+/// Status: the PIN is wrong
+pub const ERR_PIN_INVALID: u8 = 0x31;
+
+/// Status: a PIN or salt authentication tag does not verify
+pub const ERR_PIN_AUTH_INVALID: u8 = 0x33;
+
+/// Status: a PIN token was requested, but no PIN is set
+pub const ERR_PIN_NOT_SET: u8 = 0x35;
+
+/// Status: the request needs a PIN token
+pub const ERR_PIN_REQUIRED: u8 = 0x36;
+
+/// Status: the subcommand is not supported
+pub const ERR_INVALID_SUBCOMMAND: u8 = 0x3e;
+
+/// Status: the authenticator failed internally
+pub const ERR_OTHER: u8 = 0x7f;
+
+/// Authenticator data flag: the user touched the authenticator
+pub const FLAG_UP: u8 = 0x01;
+
+/// Authenticator data flag: the user was verified, for example by PIN
+pub const FLAG_UV: u8 = 0x04;
+
+/// Authenticator data flag: attested credential data follows
+pub const FLAG_AT: u8 = 0x40;
+
+/// Authenticator data flag: extension outputs follow
+pub const FLAG_ED: u8 = 0x80;
+
+/// Name of the extension deriving secrets from a credential
+pub const HMAC_SECRET: &str = "hmac-secret";
+
+/// COSE algorithm of ykvc credentials (ES256)
+pub const ALG_ES256: i64 = -7;
+
+/// COSE algorithm of key agreement keys (ECDH-ES+HKDF-256)
+pub const ALG_ECDH_ES_HKDF_256: i64 = -25;
+
+/// COSE key parameters of an EC2 P-256 key
+const COSE_KTY: i64 = 1;
+const COSE_ALG: i64 = 3;
+const COSE_CRV: i64 = -1;
+const COSE_X: i64 = -2;
+const COSE_Y: i64 = -3;
+const COSE_KTY_EC2: i64 = 2;
+const COSE_CRV_P256: i64 = 1;
+
+/// Size of a salt and of an `hmac-secret` output
+pub const SECRET_SIZE: usize = 32;
+
+/// User handle given to ykvc credentials, so re-enrolling replaces a resident one
+const USER_ID: &[u8] = b"ykvc";
+
+/// Moves CTAP2 commands to an authenticator and back
+pub trait Transport {
+    /// Sends `request` (CBOR, possibly empty) as `command`
+    ///
+    /// Returns the raw answer: the status byte, followed by CBOR if the
+    /// command succeeded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transport fails or no answer arrives in time
+    fn transact(&mut self, command: u8, request: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn transact(&mut self, command: u8, request: &[u8]) -> Result<Vec<u8>> {
+        (**self).transact(command, request)
+    }
+}
+
+/// What a CTAP2 status code means
+#[must_use]
+pub const fn status_message(code: u8) -> &'static str {
+    match code {
+        ERR_INVALID_COMMAND => "command not supported",
+        ERR_INVALID_PARAMETER => "invalid parameter",
+        0x03 => "invalid length",
+        ERR_INVALID_CBOR | 0x11 => "malformed request",
+        ERR_MISSING_PARAMETER => "missing parameter",
+        0x16 => "unsupported extension",
+        0x19 => "credential excluded",
+        ERR_UNSUPPORTED_ALGORITHM => "unsupported algorithm",
+        0x27 => "operation denied",
+        0x28 => "key store full",
+        0x2b => "unsupported option",
+        0x2c => "invalid option",
+        ERR_KEEPALIVE_CANCEL => "request cancelled",
+        ERR_NO_CREDENTIALS => "the enrolled credential is not known to this authenticator",
+        0x2f => "the authenticator was not touched in time",
+        0x30 => "not allowed",
+        ERR_PIN_INVALID => "wrong PIN",
+        0x32 => "PIN blocked, reset the FIDO2 application",
+        ERR_PIN_AUTH_INVALID => "PIN or salt authentication failed",
+        0x34 => "PIN blocked until the authenticator is reconnected",
+        ERR_PIN_NOT_SET => "no PIN is set",
+        ERR_PIN_REQUIRED => "a PIN is required",
+        0x37 => "PIN does not meet the policy",
+        ERR_INVALID_SUBCOMMAND => "subcommand not supported",
+        ERR_OTHER => "internal authenticator failure",
+        _ => "unknown error",
+    }
+}
+
+/// Splits an authenticator answer into status and CBOR
+///
+/// Returns [`Value::Null`] for successful commands without data.
+///
+/// # Errors
+///
+/// Returns [`YkvcError::Fido2Rejected`] for a failure status, or an error if
+/// the answer is malformed
+pub fn parse_response(response: &[u8]) -> Result<Value> {
+    let (&status, data) = response
+        .split_first()
+        .ok_or_else(|| YkvcError::Protocol("Empty CTAP2 response".to_string()))?;
+
+    if status != STATUS_OK {
+        return Err(YkvcError::Fido2Rejected { code: status, message: status_message(status) });
+    }
+    if data.is_empty() {
+        return Ok(Value::Null);
+    }
+    cbor::decode(data)
+}
+
+/// Looks up a field the message must have
+///
+/// # Errors
+///
+/// Returns an error naming the field if it is missing
+pub fn required<'a>(map: &'a Value, key: impl Into<Value>, name: &str) -> Result<&'a Value> {
+    map.get(key).ok_or_else(|| YkvcError::Protocol(format!("Missing {name} in CTAP2 message")))
+}
+
+/// Looks up a byte string field the message must have
+///
+/// # Errors
+///
+/// Returns an error naming the field if it is missing or not a byte string
+pub fn required_bytes<'a>(map: &'a Value, key: impl Into<Value>, name: &str) -> Result<&'a [u8]> {
+    required(map, key, name)?
+        .as_bytes()
+        .ok_or_else(|| YkvcError::Protocol(format!("Invalid {name} in CTAP2 message")))
+}
+
+/// Encodes a P-256 public key as a COSE key
+#[must_use]
+pub fn cose_key(key: &PublicKey, alg: i64) -> Value {
+    let point = key.to_encoded_point(false);
+    let coordinate =
+        |c: Option<&GenericArray<u8, _>>| Value::Bytes(c.map_or(vec![], |c| c.to_vec()));
+
+    Value::map([
+        (COSE_KTY, Value::Int(COSE_KTY_EC2)),
+        (COSE_ALG, Value::Int(alg)),
+        (COSE_CRV, Value::Int(COSE_CRV_P256)),
+        (COSE_X, coordinate(point.x())),
+        (COSE_Y, coordinate(point.y())),
+    ])
+}
+
+/// Decodes a COSE P-256 public key
+///
+/// # Errors
+///
+/// Returns an error if the key is not an EC2 P-256 key or not on the curve
+pub fn parse_cose_key(value: &Value) -> Result<PublicKey> {
+    let invalid = || YkvcError::Protocol("Invalid COSE key".to_string());
+
+    if value.get(COSE_KTY).and_then(Value::as_int) != Some(COSE_KTY_EC2)
+        || value.get(COSE_CRV).and_then(Value::as_int) != Some(COSE_CRV_P256)
+    {
+        return Err(invalid());
+    }
+
+    let x = required_bytes(value, COSE_X, "key x-coordinate")?;
+    let y = required_bytes(value, COSE_Y, "key y-coordinate")?;
+    if x.len() != 32 || y.len() != 32 {
+        return Err(invalid());
+    }
+
+    let point = EncodedPoint::from_affine_coordinates(
+        GenericArray::from_slice(x),
+        GenericArray::from_slice(y),
+        false,
+    );
+    Option::from(PublicKey::from_encoded_point(&point)).ok_or_else(invalid)
+}
+
+/// Protocol 1 shared secret: SHA-256 of the ECDH x-coordinate
+#[must_use]
+pub fn shared_secret(secret: &SecretKey, peer: &PublicKey) -> [u8; 32] {
+    let shared = p256::ecdh::diffie_hellman(secret.to_nonzero_scalar(), peer.as_affine());
+    Sha256::digest(shared.raw_secret_bytes()).into()
+}
+
+/// Encrypts whole AES blocks with AES-256-CBC and a zero IV
+///
+/// # Errors
+///
+/// Returns an error if `data` is not a multiple of the block size
+pub fn encrypt(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256::new(GenericArray::from_slice(key));
+    let mut previous = [0u8; 16];
+    let mut out = Vec::with_capacity(data.len());
+
+    for block in blocks(data)? {
+        let mut buffer = GenericArray::from(previous);
+        buffer.iter_mut().zip(block).for_each(|(b, d)| *b ^= d);
+        cipher.encrypt_block(&mut buffer);
+        previous = buffer.into();
+        out.extend_from_slice(&previous);
+    }
+    Ok(out)
+}
+
+/// Decrypts data encrypted by [`encrypt`]
+///
+/// # Errors
+///
+/// Returns an error if `data` is not a multiple of the block size
+pub fn decrypt(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256::new(GenericArray::from_slice(key));
+    let mut previous = [0u8; 16];
+    let mut out = Vec::with_capacity(data.len());
+
+    for block in blocks(data)? {
+        let mut buffer = GenericArray::clone_from_slice(block);
+        cipher.decrypt_block(&mut buffer);
+        out.extend(buffer.iter().zip(previous).map(|(b, p)| b ^ p));
+        previous.copy_from_slice(block);
+    }
+    Ok(out)
+}
+
+fn blocks(data: &[u8]) -> Result<std::slice::Chunks<'_, u8>> {
+    if data.len() % 16 != 0 {
+        return Err(YkvcError::Protocol(format!(
+            "Encrypted CTAP2 data must be whole AES blocks, got {} bytes",
+            data.len()
+        )));
+    }
+    Ok(data.chunks(16))
+}
+
+/// Protocol 1 authentication tag: the first 16 bytes of HMAC-SHA256
+#[must_use]
+pub fn authenticate(key: &[u8], message: &[u8]) -> Vec<u8> {
+    crypto::hmac_sha256(key, message)[..16].to_vec()
+}
+
+/// The part of `authenticatorGetInfo` ykvc looks at
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthenticatorInfo {
+    /// Supported protocol versions, such as `FIDO_2_0`
+    pub versions: Vec<String>,
+    /// Supported extensions
+    pub extensions: Vec<String>,
+    /// Authenticator model identifier
+    pub aaguid: Vec<u8>,
+    /// Options and whether they are enabled
+    pub options: Vec<(String, bool)>,
+    /// Firmware version, if reported (CTAP 2.1)
+    pub firmware: Option<FirmwareVersion>,
+}
+
+impl AuthenticatorInfo {
+    /// Parses the answer to `authenticatorGetInfo`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the answer is malformed
+    pub fn parse(value: &Value) -> Result<Self> {
+        let texts = |key: i64| -> Vec<String> {
+            value
+                .get(key)
+                .and_then(Value::as_array)
+                .map(|items| items.iter().filter_map(Value::as_text).map(str::to_string).collect())
+                .unwrap_or_default()
+        };
+
+        let options = match value.get(0x04) {
+            Some(Value::Map(entries)) => entries
+                .iter()
+                .filter_map(|(k, v)| Some((k.as_text()?.to_string(), v.as_bool()?)))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        // Yubico encodes the firmware version as 0x00MMmmpp
+        let firmware = value.get(0x0e).and_then(Value::as_int).map(|v| {
+            let part = |shift: u32| u8::try_from(v >> shift & 0xff).unwrap_or_default();
+            FirmwareVersion::new(part(16), part(8), part(0))
+        });
+
+        Ok(Self {
+            versions: texts(0x01),
+            extensions: texts(0x02),
+            aaguid: required_bytes(value, 0x03, "AAGUID")?.to_vec(),
+            options,
+            firmware,
+        })
+    }
+
+    /// Whether an option is enabled, `None` if the authenticator lacks it
+    #[must_use]
+    pub fn option(&self, name: &str) -> Option<bool> {
+        self.options.iter().find(|(option, _)| option == name).map(|(_, enabled)| *enabled)
+    }
+
+    /// Whether the authenticator implements the `hmac-secret` extension
+    #[must_use]
+    pub fn supports_hmac_secret(&self) -> bool {
+        self.extensions.iter().any(|extension| extension == HMAC_SECRET)
+    }
+}
+
+/// Parsed authenticator data of a credential or assertion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticatorData {
+    /// SHA-256 of the relying party ID
+    pub rp_id_hash: [u8; 32],
+    /// `FLAG_*` bits
+    pub flags: u8,
+    /// ID of a newly created credential
+    pub credential_id: Option<Vec<u8>>,
+    /// Extension outputs
+    pub extensions: Option<Value>,
+}
+
+impl AuthenticatorData {
+    /// Parses authenticator data
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data is truncated or malformed
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let truncated = || YkvcError::Protocol("Truncated authenticator data".to_string());
+
+        // RP ID hash, flags and signature counter
+        let header = data.get(..37).ok_or_else(truncated)?;
+        let mut rp_id_hash = [0u8; 32];
+        rp_id_hash.copy_from_slice(&header[..32]);
+        let flags = header[32];
+        let mut rest = &data[37..];
+
+        let mut credential_id = None;
+        if flags & FLAG_AT != 0 {
+            // AAGUID and credential ID length
+            let len = rest.get(16..18).ok_or_else(truncated)?;
+            let len = usize::from(u16::from_be_bytes([len[0], len[1]]));
+            credential_id = Some(rest.get(18..18 + len).ok_or_else(truncated)?.to_vec());
+
+            let (_, key_len) = cbor::decode_prefix(&rest[18 + len..])?;
+            rest = &rest[18 + len + key_len..];
+        }
+
+        let extensions = if flags & FLAG_ED == 0 { None } else { Some(cbor::decode(rest)?) };
+
+        Ok(Self { rp_id_hash, flags, credential_id, extensions })
+    }
+}
+
+/// SHA-256 of a relying party ID, as found in authenticator data
+#[must_use]
+pub fn rp_id_hash(rp_id: &str) -> [u8; 32] {
+    Sha256::digest(rp_id.as_bytes()).into()
+}
+
+/// Shared secret agreed with the authenticator for one request
+struct KeyAgreement {
+    secret: [u8; 32],
+    /// The platform's public key, sent along with everything encrypted
+    platform_key: Value,
+}
+
+/// CTAP2 client talking to one authenticator
+#[derive(Debug)]
+pub struct Client<T> {
+    transport: T,
+}
+
+impl<T: Transport> Client<T> {
+    /// Wraps a transport
+    pub const fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    fn call(&mut self, command: u8, request: Option<&Value>) -> Result<Value> {
+        let request = request.map(cbor::encode).unwrap_or_default();
+        parse_response(&self.transport.transact(command, &request)?)
+    }
+
+    /// Reads versions, extensions and options
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transport fails or the answer is malformed
+    pub fn info(&mut self) -> Result<AuthenticatorInfo> {
+        AuthenticatorInfo::parse(&self.call(CMD_GET_INFO, None)?)
+    }
+
+    /// Agrees on a protocol 1 shared secret with an ephemeral platform key
+    fn key_agreement(&mut self) -> Result<KeyAgreement> {
+        let request = Value::map([
+            (0x01, Value::Int(PIN_PROTOCOL)),
+            (0x02, Value::Int(PIN_GET_KEY_AGREEMENT)),
+        ]);
+        let response = self.call(CMD_CLIENT_PIN, Some(&request))?;
+        let authenticator_key = parse_cose_key(required(&response, 0x01, "key agreement")?)?;
+
+        let platform = SecretKey::random(&mut rand::rngs::OsRng);
+        Ok(KeyAgreement {
+            secret: shared_secret(&platform, &authenticator_key),
+            platform_key: cose_key(&platform.public_key(), ALG_ECDH_ES_HKDF_256),
+        })
+    }
+
+    /// Exchanges the PIN for a PIN token
+    ///
+    /// # Errors
+    ///
+    /// Returns [`YkvcError::Fido2Rejected`] if the PIN is wrong or blocked, or
+    /// an error if the transport fails
+    pub fn pin_token(&mut self, pin: &str) -> Result<Vec<u8>> {
+        let agreement = self.key_agreement()?;
+        let pin_hash = &Sha256::digest(pin.as_bytes())[..16];
+
+        let request = Value::map([
+            (0x01, Value::Int(PIN_PROTOCOL)),
+            (0x02, Value::Int(PIN_GET_PIN_TOKEN)),
+            (0x03, agreement.platform_key),
+            (0x06, Value::Bytes(encrypt(&agreement.secret, pin_hash)?)),
+        ]);
+        let response = self.call(CMD_CLIENT_PIN, Some(&request))?;
+        decrypt(&agreement.secret, required_bytes(&response, 0x02, "PIN token")?)
+    }
+
+    /// Creates a credential with `hmac-secret` enabled
+    ///
+    /// `pin_token` is required if the authenticator has a PIN. The user has
+    /// to touch the authenticator. Returns the credential ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the authenticator refuses, does not enable
+    /// `hmac-secret` for the credential, or the transport fails
+    pub fn make_credential(
+        &mut self,
+        rp_id: &str,
+        resident: bool,
+        pin_token: Option<&[u8]>,
+    ) -> Result<Vec<u8>> {
+        let client_data_hash: [u8; 32] = rand::random();
+        let mut request = vec![
+            (0x01, Value::Bytes(client_data_hash.to_vec())),
+            (0x02, Value::map([("id", Value::from(rp_id)), ("name", Value::from("ykvc"))])),
+            (
+                0x03,
+                Value::map([
+                    ("id", Value::from(USER_ID)),
+                    ("name", Value::from("ykvc")),
+                    ("displayName", Value::from("ykvc keyfile")),
+                ]),
+            ),
+            (
+                0x04,
+                Value::Array(vec![Value::map([
+                    ("alg", Value::Int(ALG_ES256)),
+                    ("type", Value::from("public-key")),
+                ])]),
+            ),
+            (0x06, Value::map([(HMAC_SECRET, Value::Bool(true))])),
+            (0x07, Value::map([("rk", Value::Bool(resident))])),
+        ];
+        if let Some(token) = pin_token {
+            request.push((0x08, Value::Bytes(authenticate(token, &client_data_hash))));
+            request.push((0x09, Value::Int(PIN_PROTOCOL)));
+        }
+
+        let response = self.call(CMD_MAKE_CREDENTIAL, Some(&Value::map(request)))?;
+        let data =
+            AuthenticatorData::parse(required_bytes(&response, 0x02, "authenticator data")?)?;
+
+        if data.rp_id_hash != rp_id_hash(rp_id) {
+            return Err(YkvcError::Protocol("Credential is bound to another RP ID".to_string()));
+        }
+        let enabled = data.extensions.as_ref().and_then(|e| e.get(HMAC_SECRET));
+        if enabled.and_then(Value::as_bool) != Some(true) {
+            return Err(YkvcError::Fido2Unsupported(
+                "hmac-secret was not enabled for the new credential".to_string(),
+            ));
+        }
+
+        data.credential_id
+            .ok_or_else(|| YkvcError::Protocol("No credential in authenticator data".to_string()))
+    }
+
+    /// Evaluates `hmac-secret` of a credential for `salt`
+    ///
+    /// The user has to touch the authenticator. The result is HMAC-SHA256 of
+    /// `salt` under a secret that never leaves the authenticator.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the authenticator does not know the credential,
+    /// refuses the request, or the transport fails
+    pub fn hmac_secret(
+        &mut self,
+        rp_id: &str,
+        credential_id: &[u8],
+        salt: &[u8; SECRET_SIZE],
+    ) -> Result<[u8; SECRET_SIZE]> {
+        let agreement = self.key_agreement()?;
+        let salt_enc = encrypt(&agreement.secret, salt)?;
+        let salt_auth = authenticate(&agreement.secret, &salt_enc);
+
+        let client_data_hash: [u8; 32] = rand::random();
+        let request = Value::map([
+            (0x01, Value::from(rp_id)),
+            (0x02, Value::Bytes(client_data_hash.to_vec())),
+            (
+                0x03,
+                Value::Array(vec![Value::map([
+                    ("id", Value::from(credential_id)),
+                    ("type", Value::from("public-key")),
+                ])]),
+            ),
+            (
+                0x04,
+                Value::map([(
+                    HMAC_SECRET,
+                    Value::map([
+                        (0x01, agreement.platform_key),
+                        (0x02, Value::Bytes(salt_enc)),
+                        (0x03, Value::Bytes(salt_auth)),
+                    ]),
+                )]),
+            ),
+        ]);
+
+        let response = self.call(CMD_GET_ASSERTION, Some(&request))?;
+        let data =
+            AuthenticatorData::parse(required_bytes(&response, 0x02, "authenticator data")?)?;
+        if data.rp_id_hash != rp_id_hash(rp_id) {
+            return Err(YkvcError::Protocol("Assertion is bound to another RP ID".to_string()));
+        }
+
+        let output = data
+            .extensions
+            .as_ref()
+            .and_then(|e| e.get(HMAC_SECRET))
+            .and_then(Value::as_bytes)
+            .ok_or_else(|| YkvcError::Protocol("No hmac-secret output in assertion".to_string()))?;
+
+        decrypt(&agreement.secret, output)?
+            .try_into()
+            .map_err(|_| YkvcError::Protocol("hmac-secret output has the wrong size".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aes_cbc_nist_vector() {
+        // NIST SP 800-38A, F.2.5 (CBC-AES256.Encrypt) with the IV folded into
+        // the first block, as protocol 1 uses a zero IV
+        let key: [u8; 32] =
+            hex::decode("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        let iv = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let plain = hex::decode("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51")
+            .unwrap();
+        let mut first = plain;
+        first.iter_mut().zip(&iv).for_each(|(p, i)| *p ^= i);
+
+        let encrypted = encrypt(&key, &first).unwrap();
+        assert_eq!(
+            hex::encode(&encrypted),
+            "f58c4c04d6e5f1ba779eabfb5f7bfbd69cfc4e967edb808d679f777bc6702c7d"
+        );
+        assert_eq!(decrypt(&key, &encrypted).unwrap(), first);
+        assert!(encrypt(&key, &[0u8; 15]).is_err());
+        assert!(decrypt(&key, &[0u8; 17]).is_err());
+    }
+
+    #[test]
+    fn test_shared_secret_agrees() {
+        let platform = SecretKey::random(&mut rand::rngs::OsRng);
+        let authenticator = SecretKey::random(&mut rand::rngs::OsRng);
+
+        let sent = cose_key(&authenticator.public_key(), ALG_ECDH_ES_HKDF_256);
+        let received = parse_cose_key(&cbor::decode(&cbor::encode(&sent)).unwrap()).unwrap();
+
+        assert_eq!(
+            shared_secret(&platform, &received),
+            shared_secret(&authenticator, &platform.public_key())
+        );
+    }
+
+    #[test]
+    fn test_parse_cose_key_rejects_invalid() {
+        let key = cose_key(&SecretKey::random(&mut rand::rngs::OsRng).public_key(), ALG_ES256);
+        let Value::Map(entries) = key else { unreachable!() };
+
+        let off_curve = entries
+            .iter()
+            .map(|(k, v)| {
+                if *k == Value::Int(COSE_Y) {
+                    (k.clone(), Value::Bytes(vec![1; 32]))
+                } else {
+                    (k.clone(), v.clone())
+                }
+            })
+            .collect();
+        assert!(parse_cose_key(&Value::Map(off_curve)).is_err());
+
+        let rsa = entries
+            .iter()
+            .map(|(k, v)| {
+                if *k == Value::Int(COSE_KTY) {
+                    (k.clone(), Value::Int(3))
+                } else {
+                    (k.clone(), v.clone())
+                }
+            })
+            .collect();
+        assert!(parse_cose_key(&Value::Map(rsa)).is_err());
+    }
+
+    #[test]
+    fn test_parse_response_status() {
+        assert_eq!(parse_response(&[STATUS_OK]).unwrap(), Value::Null);
+        assert_eq!(
+            parse_response(&[STATUS_OK, 0xa1, 0x01, 0x02]).unwrap().get(1),
+            Some(&Value::Int(2))
+        );
+        assert!(matches!(
+            parse_response(&[ERR_PIN_INVALID]),
+            Err(YkvcError::Fido2Rejected { code: ERR_PIN_INVALID, message: "wrong PIN" })
+        ));
+        assert!(matches!(parse_response(&[]), Err(YkvcError::Protocol(_))));
+    }
+
+    #[test]
+    fn test_parse_info() {
+        let value = Value::map([
+            (0x01, Value::Array(vec![Value::from("U2F_V2"), Value::from("FIDO_2_0")])),
+            (0x02, Value::Array(vec![Value::from("credProtect"), Value::from(HMAC_SECRET)])),
+            (0x03, Value::Bytes(vec![0xee; 16])),
+            (0x04, Value::map([("rk", Value::Bool(true)), ("clientPin", Value::Bool(false))])),
+            (0x0e, Value::Int(0x05_04_03)),
+        ]);
+        let info = AuthenticatorInfo::parse(&value).unwrap();
+
+        assert!(info.supports_hmac_secret());
+        assert_eq!(info.versions, ["U2F_V2", "FIDO_2_0"]);
+        assert_eq!(info.option("rk"), Some(true));
+        assert_eq!(info.option("clientPin"), Some(false));
+        assert_eq!(info.option("uv"), None);
+        assert_eq!(info.firmware, Some(FirmwareVersion::new(5, 4, 3)));
+
+        let bare = AuthenticatorInfo::parse(&Value::map([(0x03, Value::Bytes(vec![0; 16]))]));
+        assert!(!bare.unwrap().supports_hmac_secret());
+        assert!(AuthenticatorInfo::parse(&Value::map([(0x01, Value::Null)])).is_err());
+    }
+
+    #[test]
+    fn test_parse_authenticator_data() {
+        let key = cose_key(&SecretKey::random(&mut rand::rngs::OsRng).public_key(), ALG_ES256);
+        let mut data = rp_id_hash("ykvc").to_vec();
+        data.push(FLAG_UP | FLAG_AT | FLAG_ED);
+        data.extend_from_slice(&7u32.to_be_bytes());
+        data.extend_from_slice(&[0xaa; 16]);
+        data.extend_from_slice(&3u16.to_be_bytes());
+        data.extend_from_slice(&[1, 2, 3]);
+        data.extend_from_slice(&cbor::encode(&key));
+        data.extend_from_slice(&cbor::encode(&Value::map([(HMAC_SECRET, Value::Bool(true))])));
+
+        let parsed = AuthenticatorData::parse(&data).unwrap();
+        assert_eq!(parsed.rp_id_hash, rp_id_hash("ykvc"));
+        assert_eq!(parsed.credential_id.as_deref(), Some(&[1u8, 2, 3][..]));
+        assert_eq!(parsed.extensions.unwrap().get(HMAC_SECRET), Some(&Value::Bool(true)));
+
+        assert!(AuthenticatorData::parse(&data[..40]).is_err());
+        assert!(AuthenticatorData::parse(&data[..data.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,602 @@
+//! Software token emulator
+//!
+//! Emulates a `YubiKey` with two HMAC-SHA1 slots entirely in software, for CI,
+//! demos and offline recovery from the backup secret. The slot configurations
+//! live as TOML in the file named by `YKVC_EMULATOR_FILE`, by default
+//! `ykvc/emulator.toml` under the local data directory; a missing file is a
+//! token with both slots empty. Alternatively `YKVC_EMULATOR_SECRET` (hex)
+//! fixes the slot 2 secret, which makes the token read-only. The button of a
+//! touch-triggered slot counts as pressed as soon as the prompt is shown.
+//! Access codes are enforced like on a real device. The state file may set a
+//! `serial` for the token, [`SERIAL`] if unset, so rewriting it with another
+//! serial stands in for swapping keys. Slots the emulator
+//! programs hold HMAC-SHA1; other configuration types can be written to the
+//! state file by hand (`kind = "static-password"`) to see how ykvc treats them.
+//!
+//! The emulator offers no protection for the secrets and must never be used
+//! for real containers.
+
+use super::TokenBackend;
+use crate::crypto;
+use crate::error::{Result, YkvcError};
+use crate::prompt;
+use crate::yubikey::{AccessCode, FirmwareVersion, ProgramOptions, Slot, SlotConfig, YubiKeyInfo};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Environment variable holding a fixed slot 2 secret in hex
+pub const SECRET_ENV: &str = "YKVC_EMULATOR_SECRET";
+
+/// Environment variable naming the file that holds the emulated slots
+pub const FILE_ENV: &str = "YKVC_EMULATOR_FILE";
+
+/// Serial number reported by the emulator unless the state file sets one
+pub const SERIAL: u32 = 0;
+
+/// Firmware version reported by the emulator
+const FIRMWARE: FirmwareVersion = FirmwareVersion::new(5, 7, 1);
+
+/// Device type reported by the emulator
+const DEVICE_TYPE: &str = "YubiKey (emulated)";
+
+/// Configuration type of an emulated slot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum SlotKind {
+    /// HMAC-SHA1 challenge-response
+    #[default]
+    HmacSha1,
+    /// Yubico OTP
+    YubicoOtp,
+    /// Static password
+    StaticPassword,
+    /// OATH-HOTP
+    Hotp,
+}
+
+/// Configuration of one emulated slot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SlotState {
+    /// Configuration type
+    #[serde(default)]
+    kind: SlotKind,
+    /// HMAC-SHA1 secret in hex
+    secret: String,
+    /// Whether challenges wait for the button
+    #[serde(default)]
+    require_touch: bool,
+    /// Whether challenges are hashed as the full 64-byte block
+    #[serde(default)]
+    fixed_64: bool,
+    /// Access code locking the configuration, in hex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    access_code: Option<String>,
+}
+
+impl SlotState {
+    /// Configuration type as reported to ykvc
+    const fn config(&self) -> SlotConfig {
+        match self.kind {
+            SlotKind::HmacSha1 => SlotConfig::HmacSha1 { require_touch: self.require_touch },
+            SlotKind::YubicoOtp => SlotConfig::YubicoOtp,
+            SlotKind::StaticPassword => SlotConfig::StaticPassword,
+            SlotKind::Hotp => SlotConfig::Hotp,
+        }
+    }
+
+    /// Whether `access_code` unlocks this configuration
+    fn unlocked_by(&self, access_code: Option<AccessCode>) -> bool {
+        self.access_code == access_code.map(AccessCode::to_hex)
+    }
+}
+
+/// Persistent state of the emulated token, one table per programmed slot
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct TokenState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    serial: Option<u32>,
+    slot1: Option<SlotState>,
+    slot2: Option<SlotState>,
+}
+
+impl TokenState {
+    /// Serial number the token reports
+    fn serial(&self) -> u32 {
+        self.serial.unwrap_or(SERIAL)
+    }
+
+    const fn slot(&self, slot: Slot) -> Option<&SlotState> {
+        match slot {
+            Slot::One => self.slot1.as_ref(),
+            Slot::Two => self.slot2.as_ref(),
+        }
+    }
+
+    fn slot_mut(&mut self, slot: Slot) -> &mut Option<SlotState> {
+        match slot {
+            Slot::One => &mut self.slot1,
+            Slot::Two => &mut self.slot2,
+        }
+    }
+
+    /// Configuration type of `slot`
+    fn config(&self, slot: Slot) -> SlotConfig {
+        self.slot(slot).map_or(SlotConfig::Empty, SlotState::config)
+    }
+
+    /// Whether `slot` is locked by an access code
+    fn protected(&self, slot: Slot) -> bool {
+        self.slot(slot).is_some_and(|config| config.access_code.is_some())
+    }
+
+    /// Replaces the configuration of `slot` if `access_code` unlocks it
+    fn write(
+        &mut self,
+        slot: Slot,
+        config: Option<SlotState>,
+        access_code: Option<AccessCode>,
+    ) -> Result<()> {
+        if self.slot(slot).is_some_and(|current| !current.unlocked_by(access_code)) {
+            return Err(YkvcError::CommandRejected);
+        }
+        *self.slot_mut(slot) = config;
+        Ok(())
+    }
+}
+
+/// Where the emulated slots are kept
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum StateSource {
+    /// Fixed slot 2 secret and challenge mode, cannot be reprogrammed
+    Fixed(Vec<u8>, bool),
+    /// TOML file holding the slot configurations
+    File(PathBuf),
+}
+
+/// Backend that emulates the OTP slots in software
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmulatorBackend {
+    source: StateSource,
+    serial: Option<u32>,
+}
+
+impl EmulatorBackend {
+    /// Creates an emulator configured from the environment
+    ///
+    /// With `serial` set, the emulated token only answers if it has that serial.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the fixed secret is not valid hex or no state file
+    /// location can be determined
+    pub fn from_env(serial: Option<u32>) -> Result<Self> {
+        if let Ok(secret) = std::env::var(SECRET_ENV) {
+            let secret = hex::decode(secret.trim())
+                .map_err(|e| YkvcError::InvalidHex(format!("Invalid {SECRET_ENV}: {e}")))?;
+            return Ok(Self { serial, ..Self::with_secret(secret, false) });
+        }
+
+        let path = match std::env::var_os(FILE_ENV) {
+            Some(path) => PathBuf::from(path),
+            None => dirs::data_local_dir()
+                .ok_or_else(|| {
+                    YkvcError::BackendUnavailable(format!(
+                        "Cannot locate the local data directory, set {FILE_ENV}"
+                    ))
+                })?
+                .join("ykvc")
+                .join("emulator.toml"),
+        };
+
+        Ok(Self { serial, ..Self::with_file(path) })
+    }
+
+    /// Creates a read-only emulator holding `secret` in slot 2
+    ///
+    /// With `fixed_64` the slot hashes the full 64-byte challenge block, like a
+    /// slot programmed without `HMAC_LT64`.
+    #[must_use]
+    pub const fn with_secret(secret: Vec<u8>, fixed_64: bool) -> Self {
+        Self { source: StateSource::Fixed(secret, fixed_64), serial: None }
+    }
+
+    /// Creates an emulator keeping its slots in `path`
+    #[must_use]
+    pub const fn with_file(path: PathBuf) -> Self {
+        Self { source: StateSource::File(path), serial: None }
+    }
+
+    /// Reads the token state, failing unless it is the selected device
+    fn load(&self) -> Result<TokenState> {
+        let state = self.read()?;
+        match self.serial {
+            Some(serial) if serial != state.serial() => Err(YkvcError::SerialNotFound(serial)),
+            _ => Ok(state),
+        }
+    }
+
+    /// Reads the token state, whatever its serial
+    fn read(&self) -> Result<TokenState> {
+        match &self.source {
+            StateSource::Fixed(secret, fixed_64) => Ok(TokenState {
+                serial: None,
+                slot1: None,
+                slot2: Some(SlotState {
+                    kind: SlotKind::HmacSha1,
+                    secret: hex::encode(secret),
+                    require_touch: false,
+                    fixed_64: *fixed_64,
+                    access_code: None,
+                }),
+            }),
+            StateSource::File(path) => match fs::read_to_string(path) {
+                Ok(contents) => toml::from_str(&contents).map_err(|e| {
+                    YkvcError::FileError(format!("Invalid emulator state {}: {e}", path.display()))
+                }),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(TokenState::default()),
+                Err(e) => Err(YkvcError::FileError(format!(
+                    "Failed to read emulator state {}: {e}",
+                    path.display()
+                ))),
+            },
+        }
+    }
+
+    /// Writes the token state, readable by the owner only
+    fn store(&self, state: &TokenState) -> Result<()> {
+        let StateSource::File(path) = &self.source else {
+            return Err(YkvcError::Other(
+                "Emulated token holds a fixed secret and cannot be reprogrammed".to_string(),
+            ));
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                YkvcError::FileError(format!("Failed to create {}: {e}", parent.display()))
+            })?;
+        }
+
+        let contents = toml::to_string(state)
+            .map_err(|e| YkvcError::Other(format!("Failed to encode emulator state: {e}")))?;
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .map_err(|e| YkvcError::FileError(format!("Failed to open {}: {e}", path.display())))?;
+
+        file.write_all(contents.as_bytes())
+            .map_err(|e| YkvcError::FileError(format!("Failed to write emulator state: {e}")))
+    }
+
+    /// Applies `change` to the stored token state, unless it fails
+    fn update(&self, change: impl FnOnce(&mut TokenState) -> Result<()>) -> Result<()> {
+        let mut state = self.load()?;
+        change(&mut state)?;
+        self.store(&state)
+    }
+}
+
+impl TokenBackend for EmulatorBackend {
+    fn requires_dependencies(&self) -> bool {
+        false
+    }
+
+    fn uses_slots(&self) -> bool {
+        true
+    }
+
+    fn detect(&self) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn list(&self) -> Result<Vec<YubiKeyInfo>> {
+        Ok(vec![Self { serial: None, ..self.clone() }.info()?])
+    }
+
+    fn info(&self) -> Result<YubiKeyInfo> {
+        let state = self.load()?;
+
+        Ok(YubiKeyInfo {
+            serial: state.serial().to_string(),
+            firmware: FIRMWARE,
+            device_type: Some(DEVICE_TYPE.to_string()),
+            slot1_config: state.config(Slot::One),
+            slot2_config: state.config(Slot::Two),
+            slot1_protected: Some(state.protected(Slot::One)),
+            slot2_protected: Some(state.protected(Slot::Two)),
+            ..YubiKeyInfo::default()
+        })
+    }
+
+    fn slot_config(&self, slot: Slot) -> Result<SlotConfig> {
+        Ok(self.load()?.config(slot))
+    }
+
+    fn program(
+        &self,
+        slot: Slot,
+        secret: &[u8],
+        options: ProgramOptions,
+        access_code: Option<AccessCode>,
+    ) -> Result<()> {
+        let config = SlotState {
+            kind: SlotKind::HmacSha1,
+            secret: hex::encode(secret),
+            require_touch: options.require_touch,
+            fixed_64: options.fixed_64,
+            access_code: options.access_code.map(AccessCode::to_hex),
+        };
+        self.update(|state| state.write(slot, Some(config), access_code))
+    }
+
+    fn delete(&self, slot: Slot, access_code: Option<AccessCode>) -> Result<()> {
+        self.update(|state| state.write(slot, None, access_code))
+    }
+
+    fn swap(&self) -> Result<()> {
+        self.update(|state| {
+            // Like a device, which takes no access code for the swap
+            if Slot::ALL.into_iter().any(|slot| state.protected(slot)) {
+                return Err(YkvcError::CommandRejected);
+            }
+            std::mem::swap(&mut state.slot1, &mut state.slot2);
+            Ok(())
+        })
+    }
+
+    fn challenge(&self, slot: Slot, challenge: &[u8], touch_timeout: Duration) -> Result<Vec<u8>> {
+        let state = self.load()?;
+        let config = state.slot(slot).ok_or(YkvcError::SlotNotProgrammed(slot))?;
+        if config.kind != SlotKind::HmacSha1 {
+            return Err(YkvcError::SlotNotChallengeResponse(slot, config.config()));
+        }
+        if config.require_touch {
+            prompt::touch(touch_timeout);
+        }
+        let secret = hex::decode(&config.secret)
+            .map_err(|e| YkvcError::InvalidHex(format!("Invalid emulator secret: {e}")))?;
+
+        crypto::slot_response(&secret, challenge, config.fixed_64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::otp;
+
+    const TIMEOUT: Duration = Duration::from_secs(15);
+
+    #[test]
+    fn test_unprogrammed_slots() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = EmulatorBackend::with_file(dir.path().join("emulator.toml"));
+
+        assert!(backend.detect().unwrap());
+        assert!(!backend.slot_config(Slot::One).unwrap().is_programmed());
+        assert!(!backend.slot_config(Slot::Two).unwrap().is_programmed());
+        assert!(matches!(
+            backend.challenge(Slot::Two, b"abc", TIMEOUT),
+            Err(YkvcError::SlotNotProgrammed(Slot::Two))
+        ));
+    }
+
+    #[test]
+    fn test_program_and_challenge() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("emulator.toml");
+        let backend = EmulatorBackend::with_file(path.clone());
+
+        backend.program(Slot::Two, &[0x0b; 20], ProgramOptions::default(), None).unwrap();
+
+        let info = backend.info().unwrap();
+        assert_eq!(info.slot2_config, SlotConfig::HmacSha1 { require_touch: false });
+        assert_eq!(info.slot1_config, SlotConfig::Empty);
+        assert_eq!(
+            hex::encode(backend.challenge(Slot::Two, b"Hi There", TIMEOUT).unwrap()),
+            "b617318655057264e28bc0b6fb378c8ef146be00"
+        );
+
+        let mode = fs::metadata(&path).unwrap().permissions();
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_delete_and_swap() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = EmulatorBackend::with_file(dir.path().join("emulator.toml"));
+        backend.program(Slot::Two, &[0x0b; 20], ProgramOptions::default(), None).unwrap();
+
+        backend.swap().unwrap();
+        assert!(backend.slot_config(Slot::One).unwrap().is_programmed());
+        assert!(!backend.slot_config(Slot::Two).unwrap().is_programmed());
+        assert_eq!(
+            hex::encode(backend.challenge(Slot::One, b"Hi There", TIMEOUT).unwrap()),
+            "b617318655057264e28bc0b6fb378c8ef146be00"
+        );
+
+        backend.delete(Slot::One, None).unwrap();
+        assert!(!backend.slot_config(Slot::One).unwrap().is_programmed());
+    }
+
+    #[test]
+    fn test_require_touch_is_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("emulator.toml");
+        let backend = EmulatorBackend::with_file(path.clone());
+
+        backend
+            .program(
+                Slot::One,
+                &[0x0b; 20],
+                ProgramOptions { require_touch: true, ..ProgramOptions::default() },
+                None,
+            )
+            .unwrap();
+
+        assert!(fs::read_to_string(&path).unwrap().contains("require_touch = true"));
+        assert_eq!(
+            hex::encode(backend.challenge(Slot::One, b"Hi There", TIMEOUT).unwrap()),
+            "b617318655057264e28bc0b6fb378c8ef146be00"
+        );
+    }
+
+    #[test]
+    fn test_access_code_is_enforced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("emulator.toml");
+        let backend = EmulatorBackend::with_file(path.clone());
+        let code = AccessCode::from_hex("010203040506").unwrap();
+        let locked = ProgramOptions { access_code: Some(code), ..ProgramOptions::default() };
+
+        backend.program(Slot::Two, &[0x0b; 20], locked, None).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains("access_code = \"010203040506\""));
+        assert_eq!(backend.info().unwrap().slot_protected(Slot::Two), Some(true));
+        assert_eq!(backend.info().unwrap().slot_protected(Slot::One), Some(false));
+
+        assert!(matches!(
+            backend.program(Slot::Two, &[0u8; 20], ProgramOptions::default(), None),
+            Err(YkvcError::CommandRejected)
+        ));
+        assert!(matches!(backend.delete(Slot::Two, None), Err(YkvcError::CommandRejected)));
+        assert!(matches!(backend.swap(), Err(YkvcError::CommandRejected)));
+        assert_eq!(
+            hex::encode(backend.challenge(Slot::Two, b"Hi There", TIMEOUT).unwrap()),
+            "b617318655057264e28bc0b6fb378c8ef146be00"
+        );
+
+        backend.delete(Slot::Two, Some(code)).unwrap();
+        assert!(!backend.slot_config(Slot::Two).unwrap().is_programmed());
+    }
+
+    #[test]
+    fn test_state_file_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("emulator.toml");
+        fs::write(&path, format!("[slot1]\nsecret = \"{}\"\n", "0b".repeat(20))).unwrap();
+
+        let backend = EmulatorBackend::with_file(path);
+        assert!(backend.slot_config(Slot::One).unwrap().is_programmed());
+        assert!(!backend.slot_config(Slot::Two).unwrap().is_programmed());
+    }
+
+    #[test]
+    fn test_other_slot_kinds() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("emulator.toml");
+        let secret = "0b".repeat(20);
+        fs::write(
+            &path,
+            format!(
+                "[slot1]\nkind = \"static-password\"\nsecret = \"{secret}\"\n\
+                 [slot2]\nkind = \"hotp\"\nsecret = \"{secret}\"\n"
+            ),
+        )
+        .unwrap();
+
+        let backend = EmulatorBackend::with_file(path);
+        let info = backend.info().unwrap();
+        assert_eq!(info.slot1_config, SlotConfig::StaticPassword);
+        assert_eq!(info.slot2_config, SlotConfig::Hotp);
+        assert!(matches!(
+            backend.challenge(Slot::One, b"abc", TIMEOUT),
+            Err(YkvcError::SlotNotChallengeResponse(Slot::One, SlotConfig::StaticPassword))
+        ));
+
+        // Programming replaces the configuration with HMAC-SHA1
+        backend.program(Slot::One, &[0x0b; 20], ProgramOptions::default(), None).unwrap();
+        assert_eq!(
+            backend.slot_config(Slot::One).unwrap(),
+            SlotConfig::HmacSha1 { require_touch: false }
+        );
+    }
+
+    #[test]
+    fn test_fixed_secret_is_read_only() {
+        let backend = EmulatorBackend::with_secret(vec![0x0b; 20], false);
+
+        assert!(backend.slot_config(Slot::Two).unwrap().is_programmed());
+        assert!(!backend.slot_config(Slot::One).unwrap().is_programmed());
+        assert_eq!(
+            hex::encode(backend.challenge(Slot::Two, b"Hi There", TIMEOUT).unwrap()),
+            "b617318655057264e28bc0b6fb378c8ef146be00"
+        );
+        assert!(backend.program(Slot::Two, &[0u8; 20], ProgramOptions::default(), None).is_err());
+        assert!(backend.delete(Slot::Two, None).is_err());
+    }
+
+    #[test]
+    fn test_fixed_64_is_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("emulator.toml");
+        let backend = EmulatorBackend::with_file(path.clone());
+        let fixed = ProgramOptions { fixed_64: true, ..ProgramOptions::default() };
+
+        backend.program(Slot::Two, &[0x0b; 20], fixed, None).unwrap();
+
+        assert!(fs::read_to_string(&path).unwrap().contains("fixed_64 = true"));
+        let block = otp::pad_hmac_challenge(b"Hi There").unwrap();
+        assert_eq!(
+            backend.challenge(Slot::Two, b"Hi There", TIMEOUT).unwrap(),
+            crypto::hmac_sha1(&[0x0b; 20], &block)
+        );
+        assert_eq!(
+            EmulatorBackend::with_secret(vec![0x0b; 20], true)
+                .challenge(Slot::Two, b"Hi There", TIMEOUT)
+                .unwrap(),
+            crypto::hmac_sha1(&[0x0b; 20], &block)
+        );
+    }
+
+    #[test]
+    fn test_serial_selection() {
+        let selected = EmulatorBackend {
+            serial: Some(SERIAL),
+            ..EmulatorBackend::with_secret(vec![1; 20], false)
+        };
+        assert!(selected.slot_config(Slot::Two).unwrap().is_programmed());
+
+        let other = EmulatorBackend { serial: Some(42), ..selected };
+        assert!(matches!(other.info(), Err(YkvcError::SerialNotFound(42))));
+        assert!(matches!(
+            other.challenge(Slot::Two, b"abc", TIMEOUT),
+            Err(YkvcError::SerialNotFound(42))
+        ));
+        assert_eq!(other.list().unwrap()[0].serial, "0");
+    }
+
+    #[test]
+    fn test_serial_from_state_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("emulator.toml");
+        fs::write(&path, format!("serial = 42\n[slot2]\nsecret = \"{}\"\n", "0b".repeat(20)))
+            .unwrap();
+
+        let backend =
+            EmulatorBackend { serial: Some(42), ..EmulatorBackend::with_file(path.clone()) };
+        assert_eq!(backend.info().unwrap().serial, "42");
+        backend.swap().unwrap();
+        assert!(fs::read_to_string(&path).unwrap().starts_with("serial = 42\n"));
+
+        let other = EmulatorBackend { serial: Some(SERIAL), ..backend };
+        assert!(matches!(other.info(), Err(YkvcError::SerialNotFound(SERIAL))));
+        assert_eq!(other.list().unwrap()[0].serial, "42");
+    }
+
+    #[test]
+    fn test_invalid_state_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("emulator.toml");
+        fs::write(&path, "not toml [").unwrap();
+
+        let backend = EmulatorBackend::with_file(path);
+        assert!(matches!(backend.slot_config(Slot::Two), Err(YkvcError::FileError(_))));
+    }
+}
@@ -0,0 +1,714 @@
+//! FIDO2 `hmac-secret` backend
+//!
+//! Derives keyfiles from a FIDO2 credential instead of an OTP slot.
+//! `ykvc fido2 enroll` creates a credential with the `hmac-secret` extension
+//! and stores its ID with a random salt in the configuration file. A keyfile
+//! is then the `hmac-secret` output for SHA-256(salt || challenge): a 32-byte
+//! HMAC-SHA256 under a secret that never leaves the authenticator.
+//!
+//! Authenticators are reached over CTAPHID, 64-byte HID reports on the FIDO
+//! usage page. A message is split into an initialization packet (channel,
+//! command, length and 57 data bytes) and continuation packets (channel,
+//! sequence number and 59 data bytes). While the authenticator waits for a
+//! touch it sends keepalive packets. With `YKVC_FIDO2_AUTHENTICATOR` naming a
+//! state file, the [software authenticator](super::authenticator) answers
+//! instead of a device.
+
+use super::authenticator::{self, SoftAuthenticator};
+use super::ctap::{Client, Transport, SECRET_SIZE};
+use super::{select_device, TokenBackend};
+use crate::config::Fido2Credential;
+use crate::error::{Result, YkvcError};
+use crate::prompt;
+use crate::yubikey::{
+    AccessCode, ProgramOptions, Slot, SlotConfig, YubiKeyInfo, DEFAULT_TOUCH_TIMEOUT,
+};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Environment variable naming the state file of the software authenticator
+pub const AUTHENTICATOR_ENV: &str = "YKVC_FIDO2_AUTHENTICATOR";
+
+/// Relying party ID of ykvc credentials
+pub const RP_ID: &str = "ykvc";
+
+/// Size of a HID report
+const PACKET_SIZE: usize = 64;
+
+/// Data bytes in an initialization packet
+const INIT_DATA_SIZE: usize = PACKET_SIZE - 7;
+
+/// Data bytes in a continuation packet
+const CONT_DATA_SIZE: usize = PACKET_SIZE - 5;
+
+/// Largest message that fits 128 continuation packets
+const MAX_MESSAGE_SIZE: usize = INIT_DATA_SIZE + 128 * CONT_DATA_SIZE;
+
+/// Channel for allocating a channel
+const BROADCAST_CID: u32 = 0xffff_ffff;
+
+/// CTAPHID commands, with the initialization packet bit set
+const CTAPHID_INIT: u8 = 0x86;
+const CTAPHID_CBOR: u8 = 0x90;
+const CTAPHID_CANCEL: u8 = 0x91;
+const CTAPHID_KEEPALIVE: u8 = 0xbb;
+const CTAPHID_ERROR: u8 = 0xbf;
+
+/// Keepalive status: waiting for the user to touch the authenticator
+const KEEPALIVE_UP_NEEDED: u8 = 2;
+
+/// HID usage page and usage of FIDO authenticators
+const USAGE_PAGE_FIDO: u16 = 0xf1d0;
+const USAGE_CTAPHID: u16 = 0x01;
+
+/// Longest single wait for a packet, so the deadline is checked regularly
+const READ_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A single HID report
+pub type Packet = [u8; PACKET_SIZE];
+
+/// Raw report access to a FIDO HID interface
+pub trait PacketIo {
+    /// Write one report
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transport fails
+    fn write_packet(&mut self, packet: &Packet) -> Result<()>;
+
+    /// Read one report, `None` if none arrives within `timeout`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transport fails
+    fn read_packet(&mut self, timeout: Duration) -> Result<Option<Packet>>;
+}
+
+/// Splits a CTAPHID message into the reports that have to be sent
+///
+/// # Errors
+///
+/// Returns an error if the message is too long for CTAPHID
+pub fn encode_message(cid: u32, command: u8, data: &[u8]) -> Result<Vec<Packet>> {
+    let length = u16::try_from(data.len())
+        .ok()
+        .filter(|_| data.len() <= MAX_MESSAGE_SIZE)
+        .ok_or_else(|| {
+            YkvcError::Protocol(format!(
+                "CTAPHID message too long: {} bytes (maximum is {MAX_MESSAGE_SIZE})",
+                data.len()
+            ))
+        })?;
+
+    let (first, rest) = data.split_at(data.len().min(INIT_DATA_SIZE));
+    let mut packet = [0u8; PACKET_SIZE];
+    packet[..4].copy_from_slice(&cid.to_be_bytes());
+    packet[4] = command;
+    packet[5..7].copy_from_slice(&length.to_be_bytes());
+    packet[7..7 + first.len()].copy_from_slice(first);
+
+    let mut packets = vec![packet];
+    for (chunk, seq) in rest.chunks(CONT_DATA_SIZE).zip(0u8..) {
+        let mut packet = [0u8; PACKET_SIZE];
+        packet[..4].copy_from_slice(&cid.to_be_bytes());
+        packet[4] = seq;
+        packet[5..5 + chunk.len()].copy_from_slice(chunk);
+        packets.push(packet);
+    }
+    Ok(packets)
+}
+
+/// Channel a packet belongs to
+const fn channel(packet: &Packet) -> u32 {
+    u32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]])
+}
+
+/// CTAPHID framing on top of a report transport
+#[derive(Debug)]
+pub struct CtapHid<T> {
+    io: T,
+    cid: u32,
+    timeout: Duration,
+}
+
+impl<T: PacketIo> CtapHid<T> {
+    /// Allocates a channel on the authenticator
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transport fails or the authenticator does not answer
+    pub fn open(io: T) -> Result<Self> {
+        let mut hid = Self { io, cid: BROADCAST_CID, timeout: DEFAULT_TOUCH_TIMEOUT };
+        let nonce: [u8; 8] = rand::random();
+
+        loop {
+            let answer = hid.exchange(CTAPHID_INIT, &nonce)?;
+            // Answers to other hosts' INIT requests share the broadcast channel
+            if answer.len() >= 12 && answer[..8] == nonce {
+                hid.cid = u32::from_be_bytes([answer[8], answer[9], answer[10], answer[11]]);
+                return Ok(hid);
+            }
+        }
+    }
+
+    /// Sets how long to wait for an answer, including the time spent waiting for touch
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends a message on the channel and waits for the answer to it
+    fn exchange(&mut self, command: u8, data: &[u8]) -> Result<Vec<u8>> {
+        for packet in encode_message(self.cid, command, data)? {
+            self.io.write_packet(&packet)?;
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        let mut needs_touch = false;
+
+        loop {
+            let packet = self.next_packet(deadline, needs_touch)?;
+            match packet[4] {
+                CTAPHID_KEEPALIVE => {
+                    if packet[7] == KEEPALIVE_UP_NEEDED && !needs_touch {
+                        prompt::touch(self.timeout);
+                        needs_touch = true;
+                    }
+                }
+                CTAPHID_ERROR => {
+                    return Err(YkvcError::HidFailed(format!(
+                        "Authenticator reported CTAPHID error {:#04x}",
+                        packet[7]
+                    )));
+                }
+                answer if answer == command => {
+                    return self.read_message(&packet, deadline, needs_touch);
+                }
+                other => {
+                    return Err(YkvcError::Protocol(format!(
+                        "Unexpected CTAPHID command {other:#04x}"
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Collects the continuation packets of a message
+    fn read_message(
+        &mut self,
+        first: &Packet,
+        deadline: Instant,
+        needs_touch: bool,
+    ) -> Result<Vec<u8>> {
+        let len = usize::from(u16::from_be_bytes([first[5], first[6]]));
+        if len > MAX_MESSAGE_SIZE {
+            // More continuation packets than the sequence number can count
+            return Err(YkvcError::Protocol(format!("CTAPHID message of {len} bytes is too long")));
+        }
+        let mut data = first[7..].to_vec();
+
+        let mut seq = 0u8;
+        while data.len() < len {
+            let packet = self.next_packet(deadline, needs_touch)?;
+            if packet[4] != seq {
+                return Err(YkvcError::Protocol("CTAPHID packet out of sequence".to_string()));
+            }
+            data.extend_from_slice(&packet[5..]);
+            seq += 1;
+        }
+
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// Reads the next packet on the channel, cancelling the request at `deadline`
+    fn next_packet(&mut self, deadline: Instant, needs_touch: bool) -> Result<Packet> {
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                if let Ok(packets) = encode_message(self.cid, CTAPHID_CANCEL, &[]) {
+                    // The request is abandoned either way
+                    let _ = self.io.write_packet(&packets[0]);
+                }
+                if needs_touch {
+                    return Err(YkvcError::TouchTimeout(self.timeout.as_secs()));
+                }
+                return Err(YkvcError::HidFailed("Timed out waiting for response".to_string()));
+            }
+
+            if let Some(packet) = self.io.read_packet(remaining.min(READ_INTERVAL))? {
+                if channel(&packet) == self.cid {
+                    return Ok(packet);
+                }
+            }
+        }
+    }
+}
+
+impl<T: PacketIo> Transport for CtapHid<T> {
+    fn transact(&mut self, command: u8, request: &[u8]) -> Result<Vec<u8>> {
+        let message = [&[command][..], request].concat();
+        self.exchange(CTAPHID_CBOR, &message)
+    }
+}
+
+/// Report transport over a `hidapi` device
+struct HidPacketIo(hidapi::HidDevice);
+
+impl PacketIo for HidPacketIo {
+    fn write_packet(&mut self, packet: &Packet) -> Result<()> {
+        // First byte is the report ID (always 0 for FIDO interfaces)
+        let mut buf = [0u8; PACKET_SIZE + 1];
+        buf[1..].copy_from_slice(packet);
+        self.0
+            .write(&buf)
+            .map(|_| ())
+            .map_err(|e| YkvcError::HidFailed(format!("Failed to write report: {e}")))
+    }
+
+    fn read_packet(&mut self, timeout: Duration) -> Result<Option<Packet>> {
+        let mut packet = [0u8; PACKET_SIZE];
+        let millis = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        let read = self
+            .0
+            .read_timeout(&mut packet, millis)
+            .map_err(|e| YkvcError::HidFailed(format!("Failed to read report: {e}")))?;
+
+        match read {
+            0 => Ok(None),
+            PACKET_SIZE => Ok(Some(packet)),
+            _ => Err(YkvcError::HidFailed(format!("Short report: {read} bytes"))),
+        }
+    }
+}
+
+/// An authenticator ready for CTAP2 commands
+struct Authenticator {
+    name: String,
+    client: Client<Box<dyn Transport>>,
+}
+
+/// Where the authenticator is
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Device {
+    /// FIDO HID interface of a connected key
+    Hid,
+    /// Software authenticator keeping its state in this file
+    Software(PathBuf),
+}
+
+/// Backend deriving keyfiles from FIDO2 `hmac-secret`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fido2Backend {
+    device: Device,
+    serial: Option<u32>,
+    credential: Option<Fido2Credential>,
+}
+
+impl Fido2Backend {
+    /// Creates the backend, using the software authenticator if the environment names one
+    ///
+    /// `credential` is the enrolled credential, if any. FIDO2 authenticators do
+    /// not report a serial number, so `serial` only matches if it is unset.
+    #[must_use]
+    pub fn from_env(serial: Option<u32>, credential: Option<Fido2Credential>) -> Self {
+        let device = std::env::var_os(AUTHENTICATOR_ENV)
+            .map_or(Device::Hid, |path| Device::Software(PathBuf::from(path)));
+        Self { device, serial, credential }
+    }
+
+    /// Returns the FIDO interfaces of all connected authenticators
+    fn fido_interfaces(api: &hidapi::HidApi) -> Vec<&hidapi::DeviceInfo> {
+        api.device_list()
+            .filter(|d| d.usage_page() == USAGE_PAGE_FIDO && d.usage() == USAGE_CTAPHID)
+            .collect()
+    }
+
+    /// Connects to every authenticator
+    fn open_all(&self, timeout: Duration) -> Result<Vec<Authenticator>> {
+        let Device::Software(path) = &self.device else {
+            let api = hidapi::HidApi::new()
+                .map_err(|e| YkvcError::HidFailed(format!("Failed to initialize HID: {e}")))?;
+
+            return Self::fido_interfaces(&api)
+                .into_iter()
+                .map(|info| {
+                    let device = info.open_device(&api).map_err(|e| {
+                        YkvcError::HidFailed(format!("Failed to open authenticator: {e}"))
+                    })?;
+                    let hid = CtapHid::open(HidPacketIo(device))?.with_timeout(timeout);
+                    Ok(Authenticator {
+                        name: info.product_string().unwrap_or("FIDO2 authenticator").to_string(),
+                        client: Client::new(Box::new(hid)),
+                    })
+                })
+                .collect();
+        };
+
+        Ok(vec![Authenticator {
+            name: authenticator::NAME.to_string(),
+            client: Client::new(Box::new(SoftAuthenticator::open(path.clone(), timeout))),
+        }])
+    }
+
+    /// Connects to the selected authenticator
+    fn open(&self, timeout: Duration) -> Result<Authenticator> {
+        let devices = self.open_all(timeout)?.into_iter().map(|device| (None, device)).collect();
+        select_device(devices, self.serial)
+    }
+
+    /// Queries an authenticator
+    fn device_info(authenticator: &mut Authenticator) -> Result<YubiKeyInfo> {
+        let info = authenticator.client.info()?;
+        Ok(YubiKeyInfo {
+            serial: "Unknown".to_string(),
+            firmware: info.firmware.unwrap_or_default(),
+            device_type: Some(authenticator.name.clone()),
+            ..YubiKeyInfo::default()
+        })
+    }
+
+    /// Creates a credential with `hmac-secret` and a new random salt
+    ///
+    /// `read_pin` is only called if the authenticator has a PIN. The user has
+    /// to touch the authenticator within `touch_timeout`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the authenticator lacks `hmac-secret` (or resident
+    /// credentials when `resident` is set), the PIN is wrong, or creating the
+    /// credential fails
+    pub fn enroll(
+        &self,
+        resident: bool,
+        read_pin: impl FnOnce() -> Result<String>,
+        touch_timeout: Duration,
+    ) -> Result<Fido2Credential> {
+        let mut authenticator = self.open(touch_timeout)?;
+        let client = &mut authenticator.client;
+        let info = client.info()?;
+
+        if !info.supports_hmac_secret() {
+            return Err(YkvcError::Fido2Unsupported(
+                "the hmac-secret extension is not supported".to_string(),
+            ));
+        }
+        if resident && info.option("rk") != Some(true) {
+            return Err(YkvcError::Fido2Unsupported(
+                "resident credentials are not supported".to_string(),
+            ));
+        }
+
+        let pin_token = match info.option("clientPin") {
+            Some(true) => Some(client.pin_token(&read_pin()?)?),
+            _ => None,
+        };
+        let credential_id = client.make_credential(RP_ID, resident, pin_token.as_deref())?;
+
+        Ok(Fido2Credential {
+            credential_id,
+            salt: rand::random::<[u8; SECRET_SIZE]>().to_vec(),
+            rp_id: RP_ID.to_string(),
+            resident,
+        })
+    }
+
+    fn no_slots() -> YkvcError {
+        YkvcError::BackendUnavailable(
+            "the fido2 backend has no OTP slots, enroll a credential with 'ykvc fido2 enroll'"
+                .to_string(),
+        )
+    }
+}
+
+/// Salt sent to the authenticator for `challenge`
+///
+/// Binds the output to both the enrolled salt and the challenge phrase.
+#[must_use]
+pub fn hmac_secret_salt(salt: &[u8], challenge: &[u8]) -> [u8; SECRET_SIZE] {
+    let mut hash = Sha256::new();
+    hash.update(salt);
+    hash.update(challenge);
+    hash.finalize().into()
+}
+
+impl TokenBackend for Fido2Backend {
+    fn requires_dependencies(&self) -> bool {
+        false
+    }
+
+    fn uses_slots(&self) -> bool {
+        false
+    }
+
+    fn detect(&self) -> Result<bool> {
+        match &self.device {
+            Device::Software(_) => Ok(true),
+            Device::Hid => {
+                let api = hidapi::HidApi::new()
+                    .map_err(|e| YkvcError::HidFailed(format!("Failed to initialize HID: {e}")))?;
+                Ok(!Self::fido_interfaces(&api).is_empty())
+            }
+        }
+    }
+
+    fn list(&self) -> Result<Vec<YubiKeyInfo>> {
+        self.open_all(DEFAULT_TOUCH_TIMEOUT)?.iter_mut().map(Self::device_info).collect()
+    }
+
+    fn info(&self) -> Result<YubiKeyInfo> {
+        Self::device_info(&mut self.open(DEFAULT_TOUCH_TIMEOUT)?)
+    }
+
+    fn slot_config(&self, _slot: Slot) -> Result<SlotConfig> {
+        Ok(SlotConfig::Empty)
+    }
+
+    fn program(
+        &self,
+        _slot: Slot,
+        _secret: &[u8],
+        _options: ProgramOptions,
+        _access_code: Option<AccessCode>,
+    ) -> Result<()> {
+        Err(Self::no_slots())
+    }
+
+    fn delete(&self, _slot: Slot, _access_code: Option<AccessCode>) -> Result<()> {
+        Err(Self::no_slots())
+    }
+
+    fn swap(&self) -> Result<()> {
+        Err(Self::no_slots())
+    }
+
+    fn challenge(&self, _slot: Slot, challenge: &[u8], touch_timeout: Duration) -> Result<Vec<u8>> {
+        let credential = self.credential.as_ref().ok_or(YkvcError::Fido2NotEnrolled)?;
+        let salt = hmac_secret_salt(&credential.salt, challenge);
+
+        let output = self.open(touch_timeout)?.client.hmac_secret(
+            &credential.rp_id,
+            &credential.credential_id,
+            &salt,
+        )?;
+        Ok(output.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::ctap::ERR_NO_CREDENTIALS;
+    use std::collections::VecDeque;
+
+    const TIMEOUT: Duration = Duration::from_secs(15);
+
+    /// Replays recorded reports and records everything written
+    struct ScriptedIo {
+        reads: VecDeque<Packet>,
+        writes: Vec<Packet>,
+    }
+
+    impl ScriptedIo {
+        fn new(reads: Vec<Packet>) -> Self {
+            Self { reads: reads.into(), writes: Vec::new() }
+        }
+    }
+
+    impl PacketIo for &mut ScriptedIo {
+        fn write_packet(&mut self, packet: &Packet) -> Result<()> {
+            self.writes.push(*packet);
+            Ok(())
+        }
+
+        fn read_packet(&mut self, _timeout: Duration) -> Result<Option<Packet>> {
+            Ok(self.reads.pop_front())
+        }
+    }
+
+    /// Answer to an INIT request allocating channel `cid`
+    fn init_answer(nonce: &[u8], cid: u32) -> Packet {
+        let data = [nonce, &cid.to_be_bytes()[..], &[2, 5, 7, 1, 0x05]].concat();
+        encode_message(BROADCAST_CID, CTAPHID_INIT, &data).unwrap()[0]
+    }
+
+    fn backend(path: &std::path::Path, credential: Option<Fido2Credential>) -> Fido2Backend {
+        Fido2Backend { device: Device::Software(path.to_path_buf()), serial: None, credential }
+    }
+
+    #[test]
+    fn test_encode_message() {
+        let packets = encode_message(0x0102_0304, CTAPHID_CBOR, &[0xab; 100]).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0][..7], [1, 2, 3, 4, CTAPHID_CBOR, 0, 100]);
+        assert_eq!(packets[0][7..], [0xab; INIT_DATA_SIZE]);
+        assert_eq!(packets[1][..5], [1, 2, 3, 4, 0]);
+        assert_eq!(packets[1][5..5 + 43], [0xab; 43]);
+        assert_eq!(packets[1][5 + 43..], [0; CONT_DATA_SIZE - 43]);
+
+        assert_eq!(encode_message(1, CTAPHID_CANCEL, &[]).unwrap().len(), 1);
+        assert!(encode_message(1, CTAPHID_CBOR, &[0; MAX_MESSAGE_SIZE + 1]).is_err());
+        assert!(matches!(
+            encode_message(1, CTAPHID_CBOR, &vec![0; usize::from(u16::MAX) + 1]),
+            Err(YkvcError::Protocol(_))
+        ));
+    }
+
+    /// Answers INIT and CBOR requests like an authenticator, with some noise
+    struct RespondingIo {
+        cid: u32,
+        answer: Vec<u8>,
+        io: ScriptedIo,
+    }
+
+    impl PacketIo for RespondingIo {
+        fn write_packet(&mut self, packet: &Packet) -> Result<()> {
+            self.io.writes.push(*packet);
+            let replies = match packet[4] {
+                CTAPHID_INIT => vec![
+                    init_answer(&[0; 8], 0x0bad_0bad), // answer to another host
+                    init_answer(&packet[7..15], self.cid),
+                ],
+                CTAPHID_CBOR => {
+                    let mut replies = vec![
+                        encode_message(0x5555_5555, CTAPHID_CBOR, &[0x2e]).unwrap()[0],
+                        encode_message(self.cid, CTAPHID_KEEPALIVE, &[KEEPALIVE_UP_NEEDED])
+                            .unwrap()[0],
+                    ];
+                    replies.extend(encode_message(self.cid, CTAPHID_CBOR, &self.answer).unwrap());
+                    replies
+                }
+                _ => Vec::new(),
+            };
+            self.io.reads.extend(replies);
+            Ok(())
+        }
+
+        fn read_packet(&mut self, _timeout: Duration) -> Result<Option<Packet>> {
+            Ok(self.io.reads.pop_front())
+        }
+    }
+
+    #[test]
+    fn test_channel_allocation_and_transact() {
+        let answer = [vec![0x00], vec![0xcd; 80]].concat();
+        let io = RespondingIo {
+            cid: 0x1122_3344,
+            answer: answer.clone(),
+            io: ScriptedIo::new(Vec::new()),
+        };
+
+        let mut hid = CtapHid::open(io).unwrap();
+        assert_eq!(hid.cid, 0x1122_3344);
+        assert_eq!(hid.io.io.writes[0][..7], [0xff, 0xff, 0xff, 0xff, CTAPHID_INIT, 0, 8]);
+
+        assert_eq!(hid.transact(0x04, &[0xa0]).unwrap(), answer);
+        let request = hid.io.io.writes.last().unwrap();
+        assert_eq!(request[..9], [0x11, 0x22, 0x33, 0x44, CTAPHID_CBOR, 0, 2, 0x04, 0xa0]);
+    }
+
+    #[test]
+    fn test_ctaphid_error_and_timeout() {
+        let mut io = ScriptedIo::new(encode_message(7, CTAPHID_ERROR, &[0x06]).unwrap());
+        let mut hid = CtapHid { io: &mut io, cid: 7, timeout: TIMEOUT };
+        assert!(matches!(hid.transact(0x04, &[]), Err(YkvcError::HidFailed(_))));
+
+        let mut io =
+            ScriptedIo::new(encode_message(7, CTAPHID_KEEPALIVE, &[KEEPALIVE_UP_NEEDED]).unwrap());
+        let mut hid = CtapHid { io: &mut io, cid: 7, timeout: Duration::from_millis(50) };
+        assert!(matches!(hid.transact(0x02, &[]), Err(YkvcError::TouchTimeout(_))));
+        assert_eq!(io.writes.last().unwrap()[4], CTAPHID_CANCEL);
+    }
+
+    #[test]
+    fn test_out_of_sequence_packet() {
+        let mut packets = encode_message(7, CTAPHID_CBOR, &[0; 100]).unwrap();
+        packets[1][4] = 1;
+        let mut io = ScriptedIo::new(packets);
+        let mut hid = CtapHid { io: &mut io, cid: 7, timeout: TIMEOUT };
+        assert!(matches!(hid.transact(0x04, &[]), Err(YkvcError::Protocol(_))));
+    }
+
+    #[test]
+    fn test_oversized_message_length() {
+        let mut packets = encode_message(7, CTAPHID_CBOR, &[0; 10]).unwrap();
+        packets[0][5..7].copy_from_slice(&u16::MAX.to_be_bytes());
+        let mut io = ScriptedIo::new(packets);
+        let mut hid = CtapHid { io: &mut io, cid: 7, timeout: TIMEOUT };
+        assert!(matches!(hid.transact(0x04, &[]), Err(YkvcError::Protocol(_))));
+    }
+
+    #[test]
+    fn test_hmac_secret_salt() {
+        assert_eq!(hmac_secret_salt(b"salt", b"phrase"), hmac_secret_salt(b"saltphrase", b""));
+        assert_ne!(hmac_secret_salt(b"salt", b"phrase"), hmac_secret_salt(b"salt", b"phrase2"));
+        assert_eq!(
+            hex::encode(hmac_secret_salt(b"", b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_enroll_and_derive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fido2.toml");
+
+        let pin_not_needed = || -> Result<String> { panic!("no PIN is set") };
+        let credential = backend(&path, None).enroll(false, pin_not_needed, TIMEOUT).unwrap();
+        assert_eq!(credential.salt.len(), SECRET_SIZE);
+        assert_eq!(credential.rp_id, RP_ID);
+        assert!(!credential.resident);
+
+        let enrolled = backend(&path, Some(credential.clone()));
+        let keyfile = enrolled.challenge(Slot::Two, b"phrase", TIMEOUT).unwrap();
+        assert_eq!(keyfile.len(), 32);
+        assert_eq!(enrolled.challenge(Slot::One, b"phrase", TIMEOUT).unwrap(), keyfile);
+        assert_ne!(enrolled.challenge(Slot::Two, b"other", TIMEOUT).unwrap(), keyfile);
+
+        // A new salt gives unrelated keyfiles for the same credential
+        let resalted = Fido2Credential { salt: vec![0; SECRET_SIZE], ..credential.clone() };
+        assert_ne!(
+            backend(&path, Some(resalted)).challenge(Slot::Two, b"phrase", TIMEOUT).unwrap(),
+            keyfile
+        );
+
+        // Another authenticator does not know the credential
+        let other = backend(&dir.path().join("other.toml"), Some(credential));
+        assert!(matches!(
+            other.challenge(Slot::Two, b"phrase", TIMEOUT),
+            Err(YkvcError::Fido2Rejected { code: ERR_NO_CREDENTIALS, .. })
+        ));
+    }
+
+    #[test]
+    fn test_enroll_with_pin() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fido2.toml");
+        std::fs::write(&path, "pin = \"123456\"\n").unwrap();
+
+        let credential =
+            backend(&path, None).enroll(true, || Ok("123456".to_string()), TIMEOUT).unwrap();
+        assert!(credential.resident);
+        assert!(backend(&path, None).enroll(true, || Ok("000000".to_string()), TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn test_backend_without_credential() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = backend(&dir.path().join("fido2.toml"), None);
+
+        assert!(backend.detect().unwrap());
+        assert!(!backend.uses_slots());
+        assert_eq!(backend.info().unwrap().device_type.as_deref(), Some(authenticator::NAME));
+        assert!(matches!(
+            backend.challenge(Slot::Two, b"phrase", TIMEOUT),
+            Err(YkvcError::Fido2NotEnrolled)
+        ));
+        assert!(matches!(backend.swap(), Err(YkvcError::BackendUnavailable(_))));
+
+        let selected = Fido2Backend { serial: Some(1), ..backend };
+        assert!(matches!(selected.info(), Err(YkvcError::SerialNotFound(1))));
+    }
+}
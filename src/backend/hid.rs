@@ -0,0 +1,648 @@
+//! Native USB HID backend
+//!
+//! Talks to the OTP applet directly through HID feature reports, without any
+//! external tools. A slot command is sent as a 70-byte frame (64-byte payload,
+//! slot command, CRC16 and padding) split into 8-byte feature reports, each
+//! carrying 7 data bytes and a sequence byte. The answer is polled back the same
+//! way, using the status byte of each report to tell data, progress and
+//! touch-wait states apart.
+
+use super::otp::{self, Status};
+use super::{select_device, TokenBackend};
+use crate::error::{Result, YkvcError};
+use crate::prompt;
+use crate::yubikey::{AccessCode, Feature, ProgramOptions, Slot, SlotConfig, YubiKeyInfo};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Size of a feature report (without the report ID)
+const FEATURE_RPT_SIZE: usize = 8;
+
+/// Data bytes carried by a single feature report
+const FEATURE_RPT_DATA_SIZE: usize = FEATURE_RPT_SIZE - 1;
+
+/// Payload, slot command, CRC16 and 3 bytes of padding
+const FRAME_SIZE: usize = otp::SLOT_DATA_SIZE + 6;
+
+/// Status byte: device is still busy with the previous write
+const SLOT_WRITE_FLAG: u8 = 0x80;
+
+/// Status byte: report carries response data
+const RESP_PENDING_FLAG: u8 = 0x40;
+
+/// Status byte: device is waiting for the button to be touched
+const RESP_TIMEOUT_WAIT_FLAG: u8 = 0x20;
+
+/// Status byte: sequence number of a response report
+const SEQUENCE_MASK: u8 = 0x1f;
+
+/// Status byte written to reset the device's read state
+const DUMMY_REPORT_WRITE: u8 = 0x8f;
+
+/// Number of polls while waiting for the device to accept a report
+const WRITE_READY_ATTEMPTS: usize = 20;
+
+/// Maximum time to wait for a command to complete
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Yubico USB vendor ID
+const YUBICO_VENDOR_ID: u16 = 0x1050;
+
+/// HID usage page and usage of the OTP (keyboard) interface
+const USAGE_PAGE_GENERIC_DESKTOP: u16 = 0x01;
+const USAGE_KEYBOARD: u16 = 0x06;
+
+/// A single 8-byte feature report
+pub type Report = [u8; FEATURE_RPT_SIZE];
+
+/// Raw feature report access to the OTP interface
+pub trait FeatureReportIo {
+    /// Read one feature report
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transport fails
+    fn get_report(&mut self) -> Result<Report>;
+
+    /// Write one feature report
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transport fails
+    fn set_report(&mut self, report: Report) -> Result<()>;
+}
+
+/// Splits a slot command into the feature reports that have to be sent
+///
+/// All-zero reports are skipped, except for the first and the last one, as the
+/// device keeps zeroed buffers between reports.
+///
+/// # Errors
+///
+/// Returns an error if the payload is longer than 64 bytes
+pub fn encode_frame(command: u8, payload: &[u8]) -> Result<Vec<Report>> {
+    if payload.len() > otp::SLOT_DATA_SIZE {
+        return Err(YkvcError::Protocol(format!(
+            "Payload too long: {} bytes (maximum is {})",
+            payload.len(),
+            otp::SLOT_DATA_SIZE
+        )));
+    }
+
+    let mut frame = payload.to_vec();
+    frame.resize(otp::SLOT_DATA_SIZE, 0);
+    let crc = otp::crc16(&frame);
+    frame.push(command);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    frame.resize(FRAME_SIZE, 0);
+
+    let last = FRAME_SIZE / FEATURE_RPT_DATA_SIZE - 1;
+    let reports = frame
+        .chunks(FEATURE_RPT_DATA_SIZE)
+        .zip(0u8..)
+        .filter(|(chunk, seq)| {
+            *seq == 0 || usize::from(*seq) == last || chunk.iter().any(|b| *b != 0)
+        })
+        .map(|(chunk, seq)| {
+            let mut report = [0u8; FEATURE_RPT_SIZE];
+            report[..FEATURE_RPT_DATA_SIZE].copy_from_slice(chunk);
+            report[FEATURE_RPT_DATA_SIZE] = SLOT_WRITE_FLAG | seq;
+            report
+        })
+        .collect();
+
+    Ok(reports)
+}
+
+/// Answer to a slot command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// Response data (including its CRC, followed by padding)
+    Data(Vec<u8>),
+    /// Updated status, returned by configuration commands
+    Status(Status),
+}
+
+/// OTP frame protocol on top of a feature report transport
+#[derive(Debug)]
+pub struct OtpHid<T> {
+    io: T,
+    timeout: Duration,
+}
+
+impl<T: FeatureReportIo> OtpHid<T> {
+    /// Wraps a feature report transport
+    pub const fn new(io: T) -> Self {
+        Self { io, timeout: RESPONSE_TIMEOUT }
+    }
+
+    /// Sets how long to wait for an answer, including the time spent waiting for touch
+    #[must_use]
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Reads the current status block
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transport fails
+    pub fn status(&mut self) -> Result<Status> {
+        let report = self.io.get_report()?;
+        Status::from_bytes(&report[1..FEATURE_RPT_DATA_SIZE])
+    }
+
+    /// Sends a slot command and waits for its answer
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transport fails, the device rejects the command,
+    /// or no answer arrives in time
+    pub fn send_and_receive(&mut self, command: u8, payload: &[u8]) -> Result<Reply> {
+        let reports = encode_frame(command, payload)?;
+        let previous = self.status()?;
+
+        for report in &reports {
+            self.await_ready_to_write()?;
+            self.io.set_report(*report)?;
+        }
+
+        self.read_frame(previous)
+    }
+
+    /// Reads the device serial number
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the serial is not visible over the API or the
+    /// transport fails
+    pub fn serial(&mut self) -> Result<u32> {
+        match self.send_and_receive(otp::CMD_DEVICE_SERIAL, &[])? {
+            Reply::Data(data) => otp::parse_serial(&data),
+            Reply::Status(_) => {
+                Err(YkvcError::Protocol("Serial number is not readable".to_string()))
+            }
+        }
+    }
+
+    /// Reads form factor, applications and FIPS status
+    ///
+    /// Returns `None` if the device does not answer the command.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the answer is malformed or the transport fails
+    pub fn device_info(&mut self) -> Result<Option<otp::DeviceInfo>> {
+        match self.send_and_receive(otp::CMD_DEVICE_INFO, &[])? {
+            Reply::Data(data) => otp::parse_device_info(&data).map(Some),
+            Reply::Status(_) => Ok(None),
+        }
+    }
+
+    fn await_ready_to_write(&mut self) -> Result<()> {
+        for _ in 0..WRITE_READY_ATTEMPTS {
+            if self.io.get_report()?[FEATURE_RPT_DATA_SIZE] & SLOT_WRITE_FLAG == 0 {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        Err(YkvcError::HidFailed("Timeout waiting for YubiKey to become ready".to_string()))
+    }
+
+    fn read_frame(&mut self, previous: Status) -> Result<Reply> {
+        let started = Instant::now();
+        let deadline = started + self.timeout;
+        let mut response = Vec::new();
+        let mut seq = 0u8;
+        let mut needs_touch = false;
+
+        loop {
+            let report = self.io.get_report()?;
+            let status_byte = report[FEATURE_RPT_DATA_SIZE];
+
+            if status_byte & RESP_PENDING_FLAG != 0 {
+                if status_byte & SEQUENCE_MASK == seq {
+                    response.extend_from_slice(&report[..FEATURE_RPT_DATA_SIZE]);
+                    seq += 1;
+                } else if status_byte & SEQUENCE_MASK == 0 {
+                    // The device wrapped around: transmission complete
+                    self.reset_state()?;
+                    return Ok(Reply::Data(response));
+                }
+            } else if status_byte == 0 {
+                if !response.is_empty() {
+                    return Err(YkvcError::Protocol("Incomplete transfer".to_string()));
+                }
+
+                let status = Status::from_bytes(&report[1..FEATURE_RPT_DATA_SIZE])?;
+                if status.follows(previous) {
+                    return Ok(Reply::Status(status));
+                }
+                if needs_touch {
+                    // The device gave up waiting for the button on its own
+                    return Err(YkvcError::TouchTimeout(started.elapsed().as_secs()));
+                }
+                return Err(YkvcError::CommandRejected);
+            } else {
+                let delay = if status_byte & RESP_TIMEOUT_WAIT_FLAG == 0 {
+                    Duration::from_millis(20)
+                } else {
+                    if !needs_touch {
+                        prompt::touch(self.timeout);
+                    }
+                    needs_touch = true;
+                    Duration::from_millis(100)
+                };
+                if Instant::now() >= deadline {
+                    self.reset_state()?;
+                    if needs_touch {
+                        return Err(YkvcError::TouchTimeout(self.timeout.as_secs()));
+                    }
+                    return Err(YkvcError::HidFailed("Timed out waiting for response".to_string()));
+                }
+                thread::sleep(delay);
+            }
+        }
+    }
+
+    fn reset_state(&mut self) -> Result<()> {
+        let mut report = [0u8; FEATURE_RPT_SIZE];
+        report[FEATURE_RPT_DATA_SIZE] = DUMMY_REPORT_WRITE;
+        self.io.set_report(report)
+    }
+}
+
+/// Feature report transport over a `hidapi` device
+struct HidDeviceIo(hidapi::HidDevice);
+
+impl FeatureReportIo for HidDeviceIo {
+    fn get_report(&mut self) -> Result<Report> {
+        // First byte is the report ID (always 0 for the OTP interface)
+        let mut buf = [0u8; FEATURE_RPT_SIZE + 1];
+        let read = self
+            .0
+            .get_feature_report(&mut buf)
+            .map_err(|e| YkvcError::HidFailed(format!("Failed to read feature report: {e}")))?;
+
+        if read < FEATURE_RPT_SIZE {
+            return Err(YkvcError::HidFailed(format!("Short feature report: {read} bytes")));
+        }
+
+        let mut report = [0u8; FEATURE_RPT_SIZE];
+        report.copy_from_slice(&buf[1..]);
+        Ok(report)
+    }
+
+    fn set_report(&mut self, report: Report) -> Result<()> {
+        let mut buf = [0u8; FEATURE_RPT_SIZE + 1];
+        buf[1..].copy_from_slice(&report);
+        self.0
+            .send_feature_report(&buf)
+            .map_err(|e| YkvcError::HidFailed(format!("Failed to write feature report: {e}")))
+    }
+}
+
+/// Backend that speaks the OTP HID protocol natively
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HidBackend {
+    serial: Option<u32>,
+}
+
+impl HidBackend {
+    /// Creates a new native HID backend, optionally bound to one serial number
+    #[must_use]
+    pub const fn new(serial: Option<u32>) -> Self {
+        Self { serial }
+    }
+
+    /// Returns all OTP interfaces of connected `YubiKey` devices
+    fn otp_interfaces(api: &hidapi::HidApi) -> Vec<&hidapi::DeviceInfo> {
+        api.device_list()
+            .filter(|d| {
+                d.vendor_id() == YUBICO_VENDOR_ID
+                    && d.usage_page() == USAGE_PAGE_GENERIC_DESKTOP
+                    && d.usage() == USAGE_KEYBOARD
+            })
+            .collect()
+    }
+
+    /// Opens the OTP interface of every connected `YubiKey`
+    fn open_all() -> Result<Vec<OtpHid<HidDeviceIo>>> {
+        let api = hidapi::HidApi::new()
+            .map_err(|e| YkvcError::HidFailed(format!("Failed to initialize HID: {e}")))?;
+
+        Self::otp_interfaces(&api)
+            .into_iter()
+            .map(|info| {
+                info.open_device(&api)
+                    .map(|device| OtpHid::new(HidDeviceIo(device)))
+                    .map_err(|e| YkvcError::HidFailed(format!("Failed to open YubiKey: {e}")))
+            })
+            .collect()
+    }
+
+    /// Opens the OTP interface of the selected `YubiKey`
+    fn open(self) -> Result<OtpHid<HidDeviceIo>> {
+        let devices = Self::open_all()?
+            .into_iter()
+            .map(|mut device| (device.serial().ok(), device))
+            .collect();
+
+        select_device(devices, self.serial)
+    }
+
+    /// Queries serial, firmware and slot status of an opened device
+    ///
+    /// Form factor, applications and FIPS status are added where the firmware
+    /// reports them. They are only informational, so failing to read them
+    /// leaves the fields unset instead of failing the query.
+    fn device_info(device: &mut OtpHid<HidDeviceIo>) -> Result<YubiKeyInfo> {
+        let status = device.status()?;
+        let mut info = YubiKeyInfo {
+            serial: device.serial()?.to_string(),
+            firmware: status.firmware(),
+            slot1_config: status.slot_config(Slot::One),
+            slot2_config: status.slot_config(Slot::Two),
+            ..YubiKeyInfo::default()
+        };
+
+        if info.firmware.supports(Feature::DeviceInfo) {
+            if let Ok(Some(details)) = device.device_info() {
+                details.fill(&mut info);
+            }
+        }
+        Ok(info)
+    }
+
+    /// Sends a configuration command and checks that the device applied it
+    fn write_config(self, command: u8, payload: &[u8]) -> Result<()> {
+        match self.open()?.send_and_receive(command, payload)? {
+            Reply::Status(_) => Ok(()),
+            Reply::Data(_) => {
+                Err(YkvcError::Protocol("Unexpected data in reply to configuration".to_string()))
+            }
+        }
+    }
+}
+
+impl TokenBackend for HidBackend {
+    fn requires_dependencies(&self) -> bool {
+        false
+    }
+
+    fn uses_slots(&self) -> bool {
+        true
+    }
+
+    fn detect(&self) -> Result<bool> {
+        let api = hidapi::HidApi::new()
+            .map_err(|e| YkvcError::HidFailed(format!("Failed to initialize HID: {e}")))?;
+        Ok(!Self::otp_interfaces(&api).is_empty())
+    }
+
+    fn list(&self) -> Result<Vec<YubiKeyInfo>> {
+        Self::open_all()?.iter_mut().map(Self::device_info).collect()
+    }
+
+    fn info(&self) -> Result<YubiKeyInfo> {
+        Self::device_info(&mut self.open()?)
+    }
+
+    fn slot_config(&self, slot: Slot) -> Result<SlotConfig> {
+        Ok(self.open()?.status()?.slot_config(slot))
+    }
+
+    fn program(
+        &self,
+        slot: Slot,
+        secret: &[u8],
+        options: ProgramOptions,
+        access_code: Option<AccessCode>,
+    ) -> Result<()> {
+        let config = otp::hmac_config(secret, options, access_code)?;
+        self.write_config(otp::config_command(slot), &config)
+    }
+
+    fn delete(&self, slot: Slot, access_code: Option<AccessCode>) -> Result<()> {
+        self.write_config(otp::config_command(slot), &otp::delete_config(access_code))
+    }
+
+    fn swap(&self) -> Result<()> {
+        self.write_config(otp::CMD_SWAP, &[])
+    }
+
+    fn challenge(&self, slot: Slot, challenge: &[u8], touch_timeout: Duration) -> Result<Vec<u8>> {
+        let payload = otp::pad_hmac_challenge(challenge)?;
+        let mut device = self.open()?.with_timeout(touch_timeout);
+
+        match device.send_and_receive(otp::challenge_command(slot), &payload)? {
+            Reply::Data(data) => otp::parse_hmac_response(&data),
+            Reply::Status(_) => Err(YkvcError::SlotNotProgrammed(slot)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::yubikey::FirmwareVersion;
+    use std::collections::VecDeque;
+
+    /// Replays recorded device reports and records everything written
+    struct ScriptedIo {
+        reads: VecDeque<Report>,
+        writes: Vec<Report>,
+    }
+
+    impl ScriptedIo {
+        fn new(reads: &[&str]) -> Self {
+            Self { reads: reads.iter().map(|r| report(r)).collect(), writes: Vec::new() }
+        }
+    }
+
+    impl FeatureReportIo for &mut ScriptedIo {
+        fn get_report(&mut self) -> Result<Report> {
+            self.reads.pop_front().ok_or_else(|| YkvcError::HidFailed("trace exhausted".into()))
+        }
+
+        fn set_report(&mut self, report: Report) -> Result<()> {
+            self.writes.push(report);
+            Ok(())
+        }
+    }
+
+    fn report(hex_str: &str) -> Report {
+        hex::decode(hex_str).unwrap().try_into().unwrap()
+    }
+
+    fn hex_reports(reports: &[Report]) -> Vec<String> {
+        reports.iter().map(hex::encode).collect()
+    }
+
+    /// Status report: firmware 5.4.3, programming sequence 5, both slots valid
+    const STATUS: &str = "0005040305030000";
+
+    #[test]
+    fn test_encode_frame_hmac_challenge() {
+        let payload = otp::pad_hmac_challenge(b"Hi There").unwrap();
+        let reports = encode_frame(otp::CMD_CHALLENGE_HMAC_2, &payload).unwrap();
+
+        // Recorded from an HMAC challenge to slot 2: the zero reports 2..=8 are skipped
+        assert_eq!(
+            hex_reports(&reports),
+            ["4869205468657280", "6500000000000081", "00380bac00000089"]
+        );
+    }
+
+    #[test]
+    fn test_encode_frame_slot2_config() {
+        let config = otp::hmac_config(&[0x0b; 20], ProgramOptions::default(), None).unwrap();
+        let reports = encode_frame(otp::CMD_CONFIG_2, &config).unwrap();
+
+        assert_eq!(
+            hex_reports(&reports),
+            [
+                "0000000000000080",
+                "00000b0b0b0b0082",
+                "000b0b0b0b0b0b83",
+                "0b0b0b0b0b0b0b84",
+                "0b0b0b0000000085",
+                "0000000440260086",
+                "00e9780000000087",
+                "0003955600000089",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_encode_frame_slot1_delete() {
+        let reports =
+            encode_frame(otp::config_command(Slot::One), &otp::delete_config(None)).unwrap();
+
+        assert_eq!(hex_reports(&reports), ["0000000000000080", "00016b5b00000089"]);
+    }
+
+    #[test]
+    fn test_encode_frame_rejects_long_payload() {
+        assert!(encode_frame(otp::CMD_CHALLENGE_HMAC_2, &[0u8; 65]).is_err());
+    }
+
+    #[test]
+    fn test_hmac_challenge_trace() {
+        let mut io = ScriptedIo::new(&[
+            STATUS,
+            // Ready to write, once per report
+            STATUS,
+            STATUS,
+            STATUS,
+            // Device busy, then waiting for touch
+            "0000000000000020",
+            // Response: 20-byte HMAC + CRC16 in 4 reports, then wrap-around
+            "b617318655057240",
+            "64e28bc0b6fb3741",
+            "8c8ef146be005742",
+            "5400000000000043",
+            "0000000000000040",
+        ]);
+
+        let payload = otp::pad_hmac_challenge(b"Hi There").unwrap();
+        let reply = OtpHid::new(&mut io).send_and_receive(otp::CMD_CHALLENGE_HMAC_2, &payload);
+
+        let Ok(Reply::Data(data)) = reply else { panic!("Expected data, got {reply:?}") };
+        assert_eq!(
+            hex::encode(otp::parse_hmac_response(&data).unwrap()),
+            "b617318655057264e28bc0b6fb378c8ef146be00"
+        );
+        assert_eq!(
+            hex_reports(&io.writes),
+            ["4869205468657280", "6500000000000081", "00380bac00000089", "000000000000008f"]
+        );
+        assert!(io.reads.is_empty());
+    }
+
+    #[test]
+    fn test_config_write_trace() {
+        let mut reads = vec![STATUS; 9];
+        reads.push("0005040306030000");
+        let mut io = ScriptedIo::new(&reads);
+
+        let config = otp::hmac_config(&[0x0b; 20], ProgramOptions::default(), None).unwrap();
+        let reply = OtpHid::new(&mut io).send_and_receive(otp::CMD_CONFIG_2, &config).unwrap();
+
+        let Reply::Status(status) = reply else { panic!("Expected status") };
+        assert_eq!(status.prog_seq, 6);
+        assert_eq!(io.writes.len(), 8);
+    }
+
+    #[test]
+    fn test_config_write_rejected() {
+        // Programming sequence does not advance: the write was refused
+        let mut io = ScriptedIo::new(&[STATUS; 10]);
+
+        let config = otp::hmac_config(&[0x0b; 20], ProgramOptions::default(), None).unwrap();
+        let result = OtpHid::new(&mut io).send_and_receive(otp::CMD_CONFIG_2, &config);
+
+        assert!(matches!(result, Err(YkvcError::CommandRejected)));
+    }
+
+    #[test]
+    fn test_serial_trace() {
+        let mut io =
+            ScriptedIo::new(&[STATUS, STATUS, STATUS, "00bc614ec8040040", "0000000000000040"]);
+
+        assert_eq!(OtpHid::new(&mut io).serial().unwrap(), 12_345_678);
+        assert_eq!(
+            hex_reports(&io.writes),
+            ["0000000000000080", "00106b5b00000089", "000000000000008f"]
+        );
+    }
+
+    #[test]
+    fn test_status_report() {
+        let mut io = ScriptedIo::new(&[STATUS]);
+        let status = OtpHid::new(&mut io).status().unwrap();
+        assert_eq!(status.firmware(), FirmwareVersion::new(5, 4, 3));
+        assert!(status.slot_config(Slot::Two).is_programmed());
+    }
+
+    #[test]
+    fn test_touch_timeout_trace() {
+        let mut io = ScriptedIo::new(&[
+            STATUS,
+            STATUS,
+            STATUS,
+            STATUS,
+            // Waiting for touch until the device gives up
+            "0000000000000020",
+            "0000000000000020",
+            STATUS,
+        ]);
+
+        let payload = otp::pad_hmac_challenge(b"Hi There").unwrap();
+        let result = OtpHid::new(&mut io).send_and_receive(otp::CMD_CHALLENGE_HMAC_2, &payload);
+
+        assert!(matches!(result, Err(YkvcError::TouchTimeout(_))));
+    }
+
+    #[test]
+    fn test_touch_wait_exceeds_timeout() {
+        let mut io = ScriptedIo::new(&[STATUS, STATUS, STATUS, STATUS, "0000000000000020"]);
+
+        let payload = otp::pad_hmac_challenge(b"Hi There").unwrap();
+        let result = OtpHid::new(&mut io)
+            .with_timeout(Duration::ZERO)
+            .send_and_receive(otp::CMD_CHALLENGE_HMAC_2, &payload);
+
+        assert!(matches!(result, Err(YkvcError::TouchTimeout(0))));
+        assert_eq!(hex_reports(&io.writes[3..]), ["000000000000008f"]);
+    }
+
+    #[test]
+    fn test_busy_device_times_out() {
+        let mut io = ScriptedIo::new(&["0000000000000080"; 21]);
+        let result = OtpHid::new(&mut io).send_and_receive(otp::CMD_DEVICE_SERIAL, &[]);
+        assert!(matches!(result, Err(YkvcError::HidFailed(msg)) if msg.contains("ready")));
+    }
+}
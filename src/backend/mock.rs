@@ -0,0 +1,169 @@
+//! In-memory backend used by unit tests
+
+use super::TokenBackend;
+use crate::error::{Result, YkvcError};
+use crate::yubikey::{AccessCode, FirmwareVersion, ProgramOptions, Slot, SlotConfig, YubiKeyInfo};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Configuration recorded for a programmed slot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockSlot {
+    /// Programmed secret
+    pub secret: Vec<u8>,
+    /// Options the slot was programmed with
+    pub options: ProgramOptions,
+}
+
+/// Test double that records programmed secrets and answers challenges
+///
+/// The response is the secret followed by the challenge bytes, which is enough
+/// to check that handlers pass data through unchanged. Touch-triggered slots are
+/// touched immediately, and access codes are enforced like on a real device.
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    /// Whether a device is "connected"
+    pub connected: bool,
+    /// Firmware version the device reports
+    pub firmware: FirmwareVersion,
+    /// Configurations currently stored per slot
+    pub slots: RefCell<HashMap<Slot, MockSlot>>,
+}
+
+impl MockBackend {
+    /// Creates a connected mock with both slots empty
+    pub fn connected() -> Self {
+        Self::with_firmware(FirmwareVersion::new(5, 4, 3))
+    }
+
+    /// Creates a connected mock running `firmware` with both slots empty
+    pub fn with_firmware(firmware: FirmwareVersion) -> Self {
+        Self { connected: true, firmware, slots: RefCell::default() }
+    }
+
+    /// Creates a connected mock with slot 2 holding `secret`
+    pub fn programmed(secret: &[u8]) -> Self {
+        let backend = Self::connected();
+        backend.program(Slot::Two, secret, ProgramOptions::default(), None).unwrap();
+        backend
+    }
+
+    /// Secret stored in `slot`, if any
+    pub fn secret(&self, slot: Slot) -> Option<Vec<u8>> {
+        self.slots.borrow().get(&slot).map(|config| config.secret.clone())
+    }
+
+    /// Options `slot` was programmed with, if any
+    pub fn options(&self, slot: Slot) -> Option<ProgramOptions> {
+        self.slots.borrow().get(&slot).map(|config| config.options)
+    }
+
+    fn require_connected(&self) -> Result<()> {
+        if self.connected {
+            Ok(())
+        } else {
+            Err(YkvcError::YubiKeyNotFound)
+        }
+    }
+
+    /// Refuses writes to `slot` unless `access_code` matches the stored code
+    fn require_access(&self, slot: Slot, access_code: Option<AccessCode>) -> Result<()> {
+        let stored = self.options(slot).and_then(|options| options.access_code);
+        if stored.is_some() && stored != access_code {
+            return Err(YkvcError::CommandRejected);
+        }
+        Ok(())
+    }
+
+    /// Whether `slot` is locked by an access code
+    fn protected(&self, slot: Slot) -> bool {
+        self.options(slot).is_some_and(|options| options.access_code.is_some())
+    }
+}
+
+impl TokenBackend for MockBackend {
+    fn requires_dependencies(&self) -> bool {
+        false
+    }
+
+    fn uses_slots(&self) -> bool {
+        true
+    }
+
+    fn detect(&self) -> Result<bool> {
+        Ok(self.connected)
+    }
+
+    fn list(&self) -> Result<Vec<YubiKeyInfo>> {
+        if self.connected {
+            Ok(vec![self.info()?])
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn info(&self) -> Result<YubiKeyInfo> {
+        self.require_connected()?;
+        Ok(YubiKeyInfo {
+            serial: "12345678".to_string(),
+            firmware: self.firmware,
+            device_type: Some("YubiKey 5 NFC".to_string()),
+            slot1_config: self.slot_config(Slot::One)?,
+            slot2_config: self.slot_config(Slot::Two)?,
+            slot1_protected: Some(self.protected(Slot::One)),
+            slot2_protected: Some(self.protected(Slot::Two)),
+            ..YubiKeyInfo::default()
+        })
+    }
+
+    fn slot_config(&self, slot: Slot) -> Result<SlotConfig> {
+        self.require_connected()?;
+        Ok(self.options(slot).map_or(SlotConfig::Empty, |options| SlotConfig::HmacSha1 {
+            require_touch: options.require_touch,
+        }))
+    }
+
+    fn program(
+        &self,
+        slot: Slot,
+        secret: &[u8],
+        options: ProgramOptions,
+        access_code: Option<AccessCode>,
+    ) -> Result<()> {
+        self.require_connected()?;
+        self.require_access(slot, access_code)?;
+        self.slots.borrow_mut().insert(slot, MockSlot { secret: secret.to_vec(), options });
+        Ok(())
+    }
+
+    fn delete(&self, slot: Slot, access_code: Option<AccessCode>) -> Result<()> {
+        self.require_connected()?;
+        self.require_access(slot, access_code)?;
+        self.slots.borrow_mut().remove(&slot);
+        Ok(())
+    }
+
+    fn swap(&self) -> Result<()> {
+        self.require_connected()?;
+        if Slot::ALL.into_iter().any(|slot| self.protected(slot)) {
+            return Err(YkvcError::CommandRejected);
+        }
+        let mut slots = self.slots.borrow_mut();
+        let one = slots.remove(&Slot::One);
+        let two = slots.remove(&Slot::Two);
+        if let Some(config) = one {
+            slots.insert(Slot::Two, config);
+        }
+        if let Some(config) = two {
+            slots.insert(Slot::One, config);
+        }
+        Ok(())
+    }
+
+    fn challenge(&self, slot: Slot, challenge: &[u8], _touch_timeout: Duration) -> Result<Vec<u8>> {
+        self.require_connected()?;
+        let secret = self.secret(slot).ok_or(YkvcError::SlotNotProgrammed(slot))?;
+        Ok([secret.as_slice(), challenge].concat())
+    }
+}
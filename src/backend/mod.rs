@@ -0,0 +1,243 @@
+//! Token backends
+//!
+//! A [`TokenBackend`] is the transport used to talk to a `YubiKey`. The command
+//! handlers only ever see the trait, so alternative transports (or test doubles)
+//! can stand in for the device.
+
+pub mod apdu;
+pub mod authenticator;
+pub mod cbor;
+pub mod cli;
+pub mod ctap;
+pub mod emulator;
+pub mod fido2;
+pub mod hid;
+#[cfg(test)]
+pub mod mock;
+pub mod otp;
+#[cfg(any(feature = "pcsc", test))]
+pub mod pcsc;
+pub mod piv;
+pub mod piv_card;
+
+use crate::config::Config;
+use crate::error::{Result, YkvcError};
+use crate::platform::Toolchain;
+use crate::yubikey::{AccessCode, ProgramOptions, Slot, SlotConfig, YubiKeyInfo};
+use std::time::Duration;
+
+/// Time after which an unanswered challenge is assumed to wait for a touch
+pub const TOUCH_PROMPT_DELAY: Duration = Duration::from_millis(500);
+
+/// Available backends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum BackendKind {
+    /// External `ykman`, `ykpersonalize` and `ykchalresp` tools
+    #[default]
+    Cli,
+    /// Native USB HID access to the OTP applet
+    Hid,
+    /// OTP applet over PC/SC (CCID and NFC readers)
+    Pcsc,
+    /// Software token for CI and demos, never use it for real containers
+    Emulator,
+    /// FIDO2 `hmac-secret` of the credential enrolled with `ykvc fido2 enroll`
+    Fido2,
+    /// PIV ECDH with the key enrolled with `ykvc piv enroll`
+    Piv,
+}
+
+/// Creates the backend of the given kind
+///
+/// With `serial` set, every operation targets the `YubiKey` with that serial
+/// number. Otherwise exactly one device must be connected. `config` supplies
+/// what the fido2 and piv backends enrolled.
+///
+/// # Errors
+///
+/// Returns an error if the backend was not compiled into this build
+pub fn open(
+    kind: BackendKind,
+    serial: Option<u32>,
+    config: &Config,
+) -> Result<Box<dyn TokenBackend>> {
+    match kind {
+        BackendKind::Cli => Ok(Box::new(cli::CliBackend::new(serial, Toolchain::detect()))),
+        BackendKind::Hid => Ok(Box::new(hid::HidBackend::new(serial))),
+        #[cfg(feature = "pcsc")]
+        BackendKind::Pcsc => Ok(Box::new(pcsc::PcscBackend::new(serial))),
+        #[cfg(not(feature = "pcsc"))]
+        BackendKind::Pcsc => Err(YkvcError::BackendUnavailable(
+            "ykvc was built without PC/SC support (rebuild with `--features pcsc`)".to_string(),
+        )),
+        BackendKind::Emulator => Ok(Box::new(emulator::EmulatorBackend::from_env(serial)?)),
+        BackendKind::Fido2 => {
+            Ok(Box::new(fido2::Fido2Backend::from_env(serial, config.fido2.clone())))
+        }
+        BackendKind::Piv => Ok(Box::new(piv::PivBackend::from_env(serial, config.piv.clone()))),
+    }
+}
+
+/// Picks the device to use out of all connected ones
+///
+/// `devices` pairs each device with its serial number, if readable.
+///
+/// # Errors
+///
+/// Returns an error if the requested serial is not connected, or if no serial
+/// was requested and not exactly one device is connected
+pub fn select_device<T>(devices: Vec<(Option<u32>, T)>, serial: Option<u32>) -> Result<T> {
+    if let Some(serial) = serial {
+        return devices
+            .into_iter()
+            .find(|(s, _)| *s == Some(serial))
+            .map(|(_, device)| device)
+            .ok_or(YkvcError::SerialNotFound(serial));
+    }
+
+    let mut devices = devices.into_iter();
+    match (devices.next(), devices.next()) {
+        (Some((_, device)), None) => Ok(device),
+        (None, _) => Err(YkvcError::YubiKeyNotFound),
+        (Some(_), Some(_)) => Err(YkvcError::MultipleDevices),
+    }
+}
+
+/// Operations every `YubiKey` transport must provide
+pub trait TokenBackend {
+    /// Whether this backend needs the external `YubiKey` tools to be installed
+    fn requires_dependencies(&self) -> bool;
+
+    /// Whether keyfiles come from the HMAC-SHA1 slots
+    ///
+    /// Backends that derive keyfiles from another application enroll their
+    /// credential separately and ignore the slot passed to [`Self::challenge`].
+    fn uses_slots(&self) -> bool;
+
+    /// Check whether any device is connected
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if device enumeration fails
+    fn detect(&self) -> Result<bool>;
+
+    /// Retrieve information about every connected device, ignoring the selected serial
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if device enumeration fails or a device cannot be queried
+    fn list(&self) -> Result<Vec<YubiKeyInfo>>;
+
+    /// Retrieve information about the selected device
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the device is not connected or cannot be queried
+    fn info(&self) -> Result<YubiKeyInfo>;
+
+    /// Check what a slot is configured for
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the device is not connected or cannot be queried
+    fn slot_config(&self, slot: Slot) -> Result<SlotConfig>;
+
+    /// Program a slot with HMAC-SHA1 Challenge-Response using the given 20-byte secret
+    ///
+    /// `access_code` is the code currently protecting the slot, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`YkvcError::CommandRejected`] if the device refuses the write,
+    /// or another error if the device is not connected or programming fails
+    fn program(
+        &self,
+        slot: Slot,
+        secret: &[u8],
+        options: ProgramOptions,
+        access_code: Option<AccessCode>,
+    ) -> Result<()>;
+
+    /// Erase the configuration of a slot
+    ///
+    /// `access_code` is the code currently protecting the slot, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`YkvcError::CommandRejected`] if the device refuses the write,
+    /// or another error if the device is not connected or the slot cannot be erased
+    fn delete(&self, slot: Slot, access_code: Option<AccessCode>) -> Result<()>;
+
+    /// Swap the configurations of slots 1 and 2
+    ///
+    /// The swap takes no access code, so devices refuse it while either slot
+    /// is protected by one.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`YkvcError::CommandRejected`] if either slot is protected or the
+    /// device refuses the write, or another error if the device is not
+    /// connected or the swap fails
+    fn swap(&self) -> Result<()>;
+
+    /// Perform HMAC-SHA1 challenge-response on a slot
+    ///
+    /// `challenge` holds at most 64 bytes and is padded to the full block with
+    /// [`otp::pad_hmac_challenge`] before it is sent. If the device waits for a
+    /// touch, the user is prompted with [`prompt::touch`](crate::prompt::touch)
+    /// and the challenge is abandoned after `touch_timeout`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the device is not connected, the slot is not
+    /// programmed, the key is not touched in time, or the challenge-response fails
+    fn challenge(&self, slot: Slot, challenge: &[u8], touch_timeout: Duration) -> Result<Vec<u8>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_backends() {
+        let config = Config::default();
+        assert!(open(BackendKind::Cli, None, &config).unwrap().requires_dependencies());
+        assert!(!open(BackendKind::Hid, Some(1), &config).unwrap().requires_dependencies());
+        assert!(!open(BackendKind::Fido2, None, &config).unwrap().uses_slots());
+        assert!(!open(BackendKind::Piv, None, &config).unwrap().uses_slots());
+    }
+
+    #[test]
+    #[cfg(not(feature = "pcsc"))]
+    fn test_open_pcsc_without_feature() {
+        assert!(matches!(
+            open(BackendKind::Pcsc, None, &Config::default()),
+            Err(YkvcError::BackendUnavailable(_))
+        ));
+    }
+
+    #[test]
+    fn test_select_device_by_serial() {
+        let devices = vec![(Some(1), "first"), (None, "unreadable"), (Some(2), "second")];
+        assert_eq!(select_device(devices.clone(), Some(2)).unwrap(), "second");
+        assert!(matches!(select_device(devices, Some(3)), Err(YkvcError::SerialNotFound(3))));
+    }
+
+    #[test]
+    fn test_select_device_without_serial() {
+        assert_eq!(select_device(vec![(None, "only")], None).unwrap(), "only");
+        assert!(matches!(
+            select_device(Vec::<(Option<u32>, ())>::new(), None),
+            Err(YkvcError::YubiKeyNotFound)
+        ));
+        assert!(matches!(
+            select_device(vec![(Some(1), ()), (Some(2), ())], None),
+            Err(YkvcError::MultipleDevices)
+        ));
+    }
+
+    #[test]
+    fn test_default_backend_is_cli() {
+        assert_eq!(BackendKind::default(), BackendKind::Cli);
+    }
+}
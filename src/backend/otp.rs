@@ -0,0 +1,622 @@
+//! `YubiKey` OTP applet protocol
+//!
+//! Transport-independent pieces of the OTP applet protocol shared by the native
+//! backends: slot command codes, the 52-byte slot configuration structure, the
+//! status block and the CRC16 protecting configurations and responses.
+
+use crate::error::{Result, YkvcError};
+use crate::yubikey::{
+    AccessCode, Application, FirmwareVersion, FormFactor, ProgramOptions, Slot, SlotConfig,
+    YubiKeyInfo,
+};
+
+/// Size of the payload carried by a single slot command
+pub const SLOT_DATA_SIZE: usize = 64;
+
+/// Size of an HMAC-SHA1 response
+pub const HMAC_RESPONSE_SIZE: usize = 20;
+
+/// Write configuration to slot 1
+pub const CMD_CONFIG_1: u8 = 0x01;
+
+/// Write configuration to slot 2
+pub const CMD_CONFIG_2: u8 = 0x03;
+
+/// Swap the configurations of slots 1 and 2
+pub const CMD_SWAP: u8 = 0x06;
+
+/// Read the device serial number
+pub const CMD_DEVICE_SERIAL: u8 = 0x10;
+
+/// Read form factor, applications and FIPS status (firmware 4.1 and later)
+pub const CMD_DEVICE_INFO: u8 = 0x13;
+
+/// HMAC-SHA1 challenge-response on slot 1
+pub const CMD_CHALLENGE_HMAC_1: u8 = 0x30;
+
+/// HMAC-SHA1 challenge-response on slot 2
+pub const CMD_CHALLENGE_HMAC_2: u8 = 0x38;
+
+/// CRC16 of a block followed by its (inverted, little-endian) checksum
+const CRC_OK_RESIDUAL: u16 = 0xf0b8;
+
+/// Sizes of the fields in the slot configuration structure
+const FIXED_SIZE: usize = 16;
+const UID_SIZE: usize = 6;
+const KEY_SIZE: usize = 16;
+const ACC_CODE_SIZE: usize = crate::yubikey::ACCESS_CODE_SIZE;
+
+/// Ticket flag: slot answers challenges instead of emitting keystrokes
+const TKTFLAG_CHAL_RESP: u8 = 0x40;
+
+/// Configuration flag: HMAC-SHA1 challenge-response
+const CFGFLAG_CHAL_HMAC: u8 = 0x22;
+
+/// Configuration flag: HMAC challenges shorter than 64 bytes are allowed
+const CFGFLAG_HMAC_LT64: u8 = 0x04;
+
+/// Configuration flag: challenges wait for the button to be touched
+const CFGFLAG_CHAL_BTN_TRIG: u8 = 0x08;
+
+/// Extended flag: serial number readable through the API
+const EXTFLAG_SERIAL_API_VISIBLE: u8 = 0x04;
+
+/// Touch level bit set when slot 1 holds a valid configuration
+const CONFIG1_VALID: u16 = 0x01;
+
+/// Touch level bit set when slot 2 holds a valid configuration
+const CONFIG2_VALID: u16 = 0x02;
+
+/// Touch level bit set when slot 1 outputs on touch instead of answering challenges
+const CONFIG1_TOUCH: u16 = 0x04;
+
+/// Touch level bit set when slot 2 outputs on touch instead of answering challenges
+const CONFIG2_TOUCH: u16 = 0x08;
+
+/// Device information tags
+const TAG_USB_ENABLED: u8 = 0x03;
+const TAG_FORM_FACTOR: u8 = 0x04;
+const TAG_NFC_SUPPORTED: u8 = 0x0d;
+const TAG_NFC_ENABLED: u8 = 0x0e;
+const TAG_FIPS_CAPABLE: u8 = 0x14;
+
+/// Form factor byte: the device is a FIPS series model
+const FORM_FACTOR_FIPS: u8 = 0x80;
+
+/// Capability bit of each application in the device information
+const CAPABILITIES: [(u16, Application); 7] = [
+    (0x0001, Application::Otp),
+    (0x0002, Application::U2f),
+    (0x0200, Application::Fido2),
+    (0x0020, Application::Oath),
+    (0x0010, Application::Piv),
+    (0x0008, Application::OpenPgp),
+    (0x0100, Application::HsmAuth),
+];
+
+/// Size of the configuration structure including its CRC
+const CONFIG_SIZE: usize = 52;
+
+/// Command writing the configuration of `slot`
+#[must_use]
+pub const fn config_command(slot: Slot) -> u8 {
+    match slot {
+        Slot::One => CMD_CONFIG_1,
+        Slot::Two => CMD_CONFIG_2,
+    }
+}
+
+/// Command running HMAC-SHA1 challenge-response on `slot`
+#[must_use]
+pub const fn challenge_command(slot: Slot) -> u8 {
+    match slot {
+        Slot::One => CMD_CHALLENGE_HMAC_1,
+        Slot::Two => CMD_CHALLENGE_HMAC_2,
+    }
+}
+
+/// Computes the CRC16 (ISO 13239) used by the OTP applet
+#[must_use]
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for byte in data {
+        crc ^= u16::from(*byte);
+        for _ in 0..8 {
+            let carry = crc & 1;
+            crc >>= 1;
+            if carry == 1 {
+                crc ^= 0x8408;
+            }
+        }
+    }
+    crc
+}
+
+/// Checks a block that ends with its own checksum
+#[must_use]
+pub fn check_crc(data: &[u8]) -> bool {
+    crc16(data) == CRC_OK_RESIDUAL
+}
+
+/// Builds the slot write payload for HMAC-SHA1 challenge-response
+///
+/// The configuration mirrors what `ykpersonalize -ochal-resp -ochal-hmac
+/// -ohmac-lt64 -oserial-api-visible` writes, plus `-ochal-btn-trig` if
+/// `options` require touch and without `-ohmac-lt64` for fixed 64-byte
+/// challenges. The first 16 bytes of the secret
+/// go into the key field and the remaining 4 into the start of the UID field.
+/// The returned payload is the 52-byte configuration, carrying the new access
+/// code from `options`, followed by the current `access_code` (zeros if none).
+///
+/// # Errors
+///
+/// Returns an error if the secret is not exactly 20 bytes
+pub fn hmac_config(
+    secret: &[u8],
+    options: ProgramOptions,
+    access_code: Option<AccessCode>,
+) -> Result<Vec<u8>> {
+    if secret.len() != HMAC_RESPONSE_SIZE {
+        return Err(YkvcError::InvalidSecretLength(secret.len()));
+    }
+
+    let mut config = Vec::with_capacity(SLOT_DATA_SIZE);
+    config.extend_from_slice(&[0u8; FIXED_SIZE]);
+    config.extend_from_slice(&secret[KEY_SIZE..]);
+    config.resize(FIXED_SIZE + UID_SIZE, 0);
+    config.extend_from_slice(&secret[..KEY_SIZE]);
+    config.extend_from_slice(&access_code_bytes(options.access_code));
+    config.push(0); // Fixed data size
+    config.push(EXTFLAG_SERIAL_API_VISIBLE);
+    config.push(TKTFLAG_CHAL_RESP);
+    let touch = if options.require_touch { CFGFLAG_CHAL_BTN_TRIG } else { 0 };
+    let lt64 = if options.fixed_64 { 0 } else { CFGFLAG_HMAC_LT64 };
+    config.push(CFGFLAG_CHAL_HMAC | lt64 | touch);
+    config.extend_from_slice(&[0u8; 2]); // Reserved
+    let crc = !crc16(&config);
+    config.extend_from_slice(&crc.to_le_bytes());
+
+    config.extend_from_slice(&access_code_bytes(access_code));
+    Ok(config)
+}
+
+/// Builds the write payload that erases a slot
+///
+/// An all-zero configuration followed by the current `access_code` (zeros if
+/// none) tells the device to clear the slot.
+#[must_use]
+pub fn delete_config(access_code: Option<AccessCode>) -> Vec<u8> {
+    let mut config = vec![0u8; CONFIG_SIZE];
+    config.extend_from_slice(&access_code_bytes(access_code));
+    config
+}
+
+/// Access code field contents, all zeros standing for "no access code"
+fn access_code_bytes(access_code: Option<AccessCode>) -> [u8; ACC_CODE_SIZE] {
+    access_code.map_or([0u8; ACC_CODE_SIZE], AccessCode::bytes)
+}
+
+/// Pads an HMAC challenge to the 64-byte slot payload
+///
+/// A slot programmed with `HMAC_LT64` strips trailing bytes equal to the last
+/// byte of the payload, so the padding byte must differ from the challenge's
+/// last byte.
+///
+/// # Errors
+///
+/// Returns an error if the challenge is longer than 64 bytes
+pub fn pad_hmac_challenge(challenge: &[u8]) -> Result<Vec<u8>> {
+    if challenge.len() > SLOT_DATA_SIZE {
+        return Err(YkvcError::InvalidChallenge(format!(
+            "Challenge too long: {} bytes (maximum is {SLOT_DATA_SIZE})",
+            challenge.len()
+        )));
+    }
+
+    let pad = u8::from(challenge.last() == Some(&0));
+    let mut padded = challenge.to_vec();
+    padded.resize(SLOT_DATA_SIZE, pad);
+    Ok(padded)
+}
+
+/// Strips the padding from a 64-byte HMAC payload the way the device does
+///
+/// With `HMAC_LT64` set, the device drops every trailing byte equal to the last
+/// byte of the payload before computing the MAC.
+#[must_use]
+pub fn strip_hmac_padding(payload: &[u8]) -> &[u8] {
+    let len =
+        payload.last().map_or(0, |pad| payload.iter().rposition(|b| b != pad).map_or(0, |i| i + 1));
+    &payload[..len]
+}
+
+/// Extracts the HMAC-SHA1 value from a challenge-response answer
+///
+/// # Errors
+///
+/// Returns an error if the answer is truncated or its checksum is wrong
+pub fn parse_hmac_response(response: &[u8]) -> Result<Vec<u8>> {
+    let block = response
+        .get(..HMAC_RESPONSE_SIZE + 2)
+        .ok_or_else(|| YkvcError::Protocol("Truncated HMAC response".to_string()))?;
+
+    if !check_crc(block) {
+        return Err(YkvcError::Protocol("HMAC response checksum mismatch".to_string()));
+    }
+
+    Ok(block[..HMAC_RESPONSE_SIZE].to_vec())
+}
+
+/// Extracts the serial number from a device serial answer
+///
+/// # Errors
+///
+/// Returns an error if the answer is truncated or its checksum is wrong
+pub fn parse_serial(response: &[u8]) -> Result<u32> {
+    let block = response
+        .get(..6)
+        .ok_or_else(|| YkvcError::Protocol("Truncated serial number".to_string()))?;
+
+    if !check_crc(block) {
+        return Err(YkvcError::Protocol("Serial number checksum mismatch".to_string()));
+    }
+
+    Ok(u32::from_be_bytes([block[0], block[1], block[2], block[3]]))
+}
+
+/// Device details read with [`CMD_DEVICE_INFO`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Physical shape and connector, if the device reports a known one
+    pub form_factor: Option<FormFactor>,
+    /// Applications enabled over USB
+    pub usb_applications: Vec<Application>,
+    /// Applications enabled over NFC, `None` for keys without NFC
+    pub nfc_applications: Option<Vec<Application>>,
+    /// Whether the device is a FIPS series model
+    pub fips: bool,
+}
+
+impl DeviceInfo {
+    /// Copies these details into `info`
+    pub fn fill(self, info: &mut YubiKeyInfo) {
+        info.form_factor = self.form_factor;
+        info.usb_applications = Some(self.usb_applications);
+        info.nfc_applications = self.nfc_applications;
+        info.fips = Some(self.fips);
+    }
+}
+
+/// Parses a device information answer
+///
+/// The answer is a length byte, that many bytes of tag-length-value records and
+/// a checksum. Unknown tags are skipped.
+///
+/// # Errors
+///
+/// Returns an error if the answer is truncated or its checksum is wrong
+pub fn parse_device_info(response: &[u8]) -> Result<DeviceInfo> {
+    let truncated = || YkvcError::Protocol("Truncated device information".to_string());
+    let length = usize::from(*response.first().ok_or_else(truncated)?);
+    let block = response.get(..length + 3).ok_or_else(truncated)?;
+
+    if !check_crc(block) {
+        return Err(YkvcError::Protocol("Device information checksum mismatch".to_string()));
+    }
+
+    let mut info = DeviceInfo::default();
+    let mut nfc_supported = false;
+    let mut nfc_enabled = Vec::new();
+    let mut records = &block[1..=length];
+    while let [tag, size, rest @ ..] = records {
+        let value = rest.get(..usize::from(*size)).ok_or_else(truncated)?;
+        match *tag {
+            TAG_USB_ENABLED => info.usb_applications = applications(value),
+            TAG_FORM_FACTOR => {
+                let code = value.first().copied().unwrap_or_default();
+                info.form_factor = form_factor(code);
+                info.fips |= code & FORM_FACTOR_FIPS != 0;
+            }
+            TAG_NFC_SUPPORTED => nfc_supported = !applications(value).is_empty(),
+            TAG_NFC_ENABLED => nfc_enabled = applications(value),
+            TAG_FIPS_CAPABLE => info.fips |= value.iter().any(|byte| *byte != 0),
+            _ => {}
+        }
+        records = &rest[value.len()..];
+    }
+
+    if nfc_supported {
+        info.nfc_applications = Some(nfc_enabled);
+    }
+    Ok(info)
+}
+
+/// Applications whose capability bits are set in a big-endian bit field
+fn applications(value: &[u8]) -> Vec<Application> {
+    let bits = value.iter().fold(0u16, |bits, byte| (bits << 8) | u16::from(*byte));
+    CAPABILITIES
+        .into_iter()
+        .filter(|(bit, _)| bits & bit != 0)
+        .map(|(_, application)| application)
+        .collect()
+}
+
+/// Form factor stored in the low nibble of the form factor byte
+fn form_factor(code: u8) -> Option<FormFactor> {
+    FormFactor::ALL.get(usize::from(code & 0x0f).checked_sub(1)?).copied()
+}
+
+/// OTP applet status block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    /// Firmware version (major, minor, build)
+    pub version: (u8, u8, u8),
+    /// Programming sequence, incremented by every successful configuration write
+    pub prog_seq: u8,
+    /// Slot validity and touch flags
+    pub touch_level: u16,
+}
+
+impl Status {
+    /// Parses the 6-byte status block
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if fewer than 6 bytes are given
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        match data {
+            [major, minor, build, prog_seq, touch_lo, touch_hi, ..] => Ok(Self {
+                version: (*major, *minor, *build),
+                prog_seq: *prog_seq,
+                touch_level: u16::from_le_bytes([*touch_lo, *touch_hi]),
+            }),
+            _ => Err(YkvcError::Protocol("Truncated status block".to_string())),
+        }
+    }
+
+    /// Firmware version of the device
+    #[must_use]
+    pub const fn firmware(self) -> FirmwareVersion {
+        let (major, minor, build) = self.version;
+        FirmwareVersion::new(major, minor, build)
+    }
+
+    /// Configuration type of `slot`, as far as the status tells
+    ///
+    /// A valid slot is either touch-triggered (Yubico OTP, static password or
+    /// OATH-HOTP) or answers challenges; the exact type is not reported.
+    #[must_use]
+    pub const fn slot_config(self, slot: Slot) -> SlotConfig {
+        let (valid, touch) = match slot {
+            Slot::One => (CONFIG1_VALID, CONFIG1_TOUCH),
+            Slot::Two => (CONFIG2_VALID, CONFIG2_TOUCH),
+        };
+
+        if self.touch_level & valid == 0 {
+            SlotConfig::Empty
+        } else if self.touch_level & touch != 0 {
+            SlotConfig::TouchTriggered
+        } else {
+            SlotConfig::ChallengeResponse
+        }
+    }
+
+    /// Whether this status shows that a write made after `previous` was applied
+    ///
+    /// Every applied write bumps the programming sequence, except that it drops
+    /// back to 0 once no slot holds a configuration anymore.
+    #[must_use]
+    pub const fn follows(self, previous: Self) -> bool {
+        self.prog_seq == previous.prog_seq.wrapping_add(1)
+            || (previous.prog_seq > 0
+                && self.prog_seq == 0
+                && self.touch_level & (CONFIG1_VALID | CONFIG2_VALID) == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc16_residual() {
+        let data = hex::decode("b617318655057264e28bc0b6fb378c8ef146be00").unwrap();
+        let crc = !crc16(&data);
+        let mut block = data;
+        block.extend_from_slice(&crc.to_le_bytes());
+        assert_eq!(hex::encode(&block[20..]), "5754");
+        assert!(check_crc(&block));
+    }
+
+    #[test]
+    fn test_hmac_config_layout() {
+        let config = hmac_config(&[0x0b; 20], ProgramOptions::default(), None).unwrap();
+        assert_eq!(
+            hex::encode(&config),
+            "00000000000000000000000000000000\
+             0b0b0b0b0000\
+             0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b\
+             000000000000\
+             00044026\
+             0000\
+             e978\
+             000000000000"
+        );
+        assert!(check_crc(&config[..52]));
+    }
+
+    #[test]
+    fn test_hmac_config_require_touch() {
+        let options = ProgramOptions { require_touch: true, ..ProgramOptions::default() };
+        let config = hmac_config(&[0x0b; 20], options, None).unwrap();
+
+        assert_eq!(hex::encode(&config[44..48]), "0004402e");
+        assert!(check_crc(&config[..52]));
+    }
+
+    #[test]
+    fn test_hmac_config_fixed_64() {
+        let options = ProgramOptions { fixed_64: true, ..ProgramOptions::default() };
+        let config = hmac_config(&[0x0b; 20], options, None).unwrap();
+
+        assert_eq!(hex::encode(&config[44..48]), "00044022");
+        assert!(check_crc(&config[..52]));
+    }
+
+    #[test]
+    fn test_hmac_config_access_codes() {
+        let current = AccessCode::from_hex("010203040506").unwrap();
+        let new = AccessCode::from_hex("a1a2a3a4a5a6").unwrap();
+        let options = ProgramOptions { access_code: Some(new), ..ProgramOptions::default() };
+
+        let config = hmac_config(&[0x0b; 20], options, Some(current)).unwrap();
+
+        assert_eq!(hex::encode(&config[38..44]), "a1a2a3a4a5a6");
+        assert!(check_crc(&config[..52]));
+        assert_eq!(hex::encode(&config[52..]), "010203040506");
+
+        let config = delete_config(Some(current));
+        assert_eq!(config[..52], [0u8; 52]);
+        assert_eq!(hex::encode(&config[52..]), "010203040506");
+    }
+
+    #[test]
+    fn test_slot_commands() {
+        assert_eq!(config_command(Slot::One), 0x01);
+        assert_eq!(config_command(Slot::Two), 0x03);
+        assert_eq!(challenge_command(Slot::One), 0x30);
+        assert_eq!(challenge_command(Slot::Two), 0x38);
+        assert_eq!(delete_config(None), [0u8; 58]);
+    }
+
+    #[test]
+    fn test_status_follows() {
+        let before = Status { version: (5, 4, 3), prog_seq: 5, touch_level: 0x03 };
+
+        assert!(Status { prog_seq: 6, ..before }.follows(before));
+        assert!(!Status { prog_seq: 5, ..before }.follows(before));
+
+        // Deleting the last configured slot resets the sequence
+        assert!(Status { prog_seq: 0, touch_level: 0, ..before }.follows(before));
+        assert!(!Status { prog_seq: 0, ..before }.follows(before));
+    }
+
+    #[test]
+    fn test_hmac_config_rejects_wrong_length() {
+        assert!(matches!(
+            hmac_config(&[0u8; 16], ProgramOptions::default(), None),
+            Err(YkvcError::InvalidSecretLength(16))
+        ));
+    }
+
+    #[test]
+    fn test_pad_hmac_challenge() {
+        let padded = pad_hmac_challenge(b"abc").unwrap();
+        assert_eq!(padded.len(), 64);
+        assert_eq!(&padded[..3], b"abc");
+        assert!(padded[3..].iter().all(|b| *b == 0));
+
+        // A trailing zero byte must not be swallowed by zero padding
+        let padded = pad_hmac_challenge(b"ab\0").unwrap();
+        assert!(padded[3..].iter().all(|b| *b == 1));
+
+        assert!(pad_hmac_challenge(&[0u8; 65]).is_err());
+    }
+
+    #[test]
+    fn test_strip_hmac_padding() {
+        for challenge in [&b"abc"[..], b"ab\0", b"", &[0u8; 5]] {
+            let padded = pad_hmac_challenge(challenge).unwrap();
+            assert_eq!(strip_hmac_padding(&padded), challenge);
+        }
+        assert!(strip_hmac_padding(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_parse_hmac_response() {
+        let response =
+            hex::decode("b617318655057264e28bc0b6fb378c8ef146be0057540000000000").unwrap();
+        assert_eq!(
+            hex::encode(parse_hmac_response(&response).unwrap()),
+            "b617318655057264e28bc0b6fb378c8ef146be00"
+        );
+
+        let mut corrupted = response;
+        corrupted[0] ^= 1;
+        assert!(parse_hmac_response(&corrupted).is_err());
+        assert!(parse_hmac_response(&[0u8; 10]).is_err());
+    }
+
+    #[test]
+    fn test_parse_serial() {
+        let response = hex::decode("00bc614ec804").unwrap();
+        assert_eq!(parse_serial(&response).unwrap(), 12_345_678);
+        assert!(parse_serial(&response[..4]).is_err());
+    }
+
+    /// Prefixes TLV records with their length and appends the checksum
+    fn device_info_answer(records: &str) -> Vec<u8> {
+        let records = hex::decode(records.replace(' ', "")).unwrap();
+        let mut answer = vec![u8::try_from(records.len()).unwrap()];
+        answer.extend_from_slice(&records);
+        let crc = !crc16(&answer);
+        answer.extend_from_slice(&crc.to_le_bytes());
+        answer
+    }
+
+    #[test]
+    fn test_parse_device_info() {
+        // YubiKey 5 NFC: everything enabled over USB, only OATH over NFC
+        let answer = device_info_answer(
+            "0102023b 0302023b 020400bc614e 040101 0503050403 0d02023b 0e020020",
+        );
+        let info = parse_device_info(&answer).unwrap();
+        assert_eq!(info.form_factor, Some(FormFactor::KeychainUsbA));
+        assert_eq!(
+            info.usb_applications,
+            [
+                Application::Otp,
+                Application::U2f,
+                Application::Fido2,
+                Application::Oath,
+                Application::Piv,
+                Application::OpenPgp,
+            ]
+        );
+        assert_eq!(info.nfc_applications, Some(vec![Application::Oath]));
+        assert!(!info.fips);
+
+        // FIPS Nano without NFC
+        let info = parse_device_info(&device_info_answer("03020003 040182")).unwrap();
+        assert_eq!(info.form_factor, Some(FormFactor::NanoUsbA));
+        assert_eq!(info.usb_applications, [Application::Otp, Application::U2f]);
+        assert_eq!(info.nfc_applications, None);
+        assert!(info.fips);
+
+        let mut corrupted = answer.clone();
+        corrupted[3] ^= 1;
+        assert!(parse_device_info(&corrupted).is_err());
+        assert!(parse_device_info(&answer[..10]).is_err());
+        assert!(parse_device_info(&device_info_answer("0304023b")).is_err());
+    }
+
+    #[test]
+    fn test_status_from_bytes() {
+        let status = Status::from_bytes(&[5, 4, 3, 7, 0x03, 0x00]).unwrap();
+        assert_eq!(status.firmware(), FirmwareVersion::new(5, 4, 3));
+        assert_eq!(status.prog_seq, 7);
+        assert_eq!(status.slot_config(Slot::One), SlotConfig::ChallengeResponse);
+        assert_eq!(status.slot_config(Slot::Two), SlotConfig::ChallengeResponse);
+
+        let status = Status::from_bytes(&[5, 4, 3, 7, 0x01, 0x00]).unwrap();
+        assert_eq!(status.slot_config(Slot::One), SlotConfig::ChallengeResponse);
+        assert_eq!(status.slot_config(Slot::Two), SlotConfig::Empty);
+
+        // Factory Yubico OTP in slot 1, HMAC-SHA1 in slot 2
+        let status = Status::from_bytes(&[5, 4, 3, 7, 0x07, 0x00]).unwrap();
+        assert_eq!(status.slot_config(Slot::One), SlotConfig::TouchTriggered);
+        assert_eq!(status.slot_config(Slot::Two), SlotConfig::ChallengeResponse);
+
+        assert!(Status::from_bytes(&[5, 4]).is_err());
+    }
+}
@@ -0,0 +1,416 @@
+//! PC/SC (CCID) backend
+//!
+//! Reaches the OTP applet through a smart card reader instead of the HID
+//! keyboard interface, which also covers NFC readers. The applet is selected by
+//! AID and driven with `INS_CONFIG` APDUs whose P1 is the same slot command used
+//! by the HID frame protocol. Unlike HID, answers carry no CRC16.
+//!
+//! The PC/SC transport needs the `pcsc` cargo feature; the applet logic below is
+//! transport-independent.
+
+use super::apdu::{self, Apdu, CardTransport};
+use super::otp::{self, Status};
+use super::TOUCH_PROMPT_DELAY;
+use crate::error::{Result, YkvcError};
+use crate::prompt;
+use crate::yubikey::Slot;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// Application identifier of the OTP applet
+pub const OTP_AID: [u8; 7] = [0xa0, 0x00, 0x00, 0x05, 0x27, 0x20, 0x01];
+
+/// OTP applet instruction carrying a slot command in P1
+const INS_CONFIG: u8 = 0x01;
+
+/// Selected OTP applet on a smart card transport
+#[derive(Debug)]
+pub struct OtpApplet<T> {
+    card: T,
+    status: Status,
+}
+
+impl<T: CardTransport> OtpApplet<T> {
+    /// Selects the OTP applet and reads its status
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the card has no OTP applet or the transport fails
+    pub fn select(mut card: T) -> Result<Self> {
+        let response = card.send(&Apdu::select(&OTP_AID))?;
+
+        match response.sw {
+            apdu::SW_SUCCESS => Ok(Self { status: Status::from_bytes(&response.data)?, card }),
+            apdu::SW_FILE_NOT_FOUND => {
+                Err(YkvcError::Protocol("OTP applet not found on card".to_string()))
+            }
+            sw => Err(YkvcError::Protocol(format!("Selecting OTP applet failed: SW {sw:04X}"))),
+        }
+    }
+
+    /// Status read when the applet was selected (or after the last write)
+    #[must_use]
+    pub const fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Reads the device serial number
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the serial is not readable or the transport fails
+    pub fn serial(&mut self) -> Result<u32> {
+        let response = self.card.send(&Apdu::new(INS_CONFIG, otp::CMD_DEVICE_SERIAL, 0, &[]))?;
+
+        match response.data.as_slice() {
+            [a, b, c, d] if response.is_success() => Ok(u32::from_be_bytes([*a, *b, *c, *d])),
+            _ => Err(YkvcError::Protocol(format!(
+                "Unexpected serial number answer: {} bytes, SW {:04X}",
+                response.data.len(),
+                response.sw
+            ))),
+        }
+    }
+
+    /// Writes a slot configuration and checks that the device accepted it
+    ///
+    /// # Errors
+    ///
+    /// Returns [`YkvcError::CommandRejected`] if the device does not apply the
+    /// configuration, for example because the slot has a different access code
+    pub fn write_config(&mut self, command: u8, payload: &[u8]) -> Result<()> {
+        let response = self.card.send(&Apdu::new(INS_CONFIG, command, 0, payload))?;
+
+        if !response.is_success() {
+            return Err(YkvcError::Protocol(format!(
+                "Configuration rejected: SW {:04X}",
+                response.sw
+            )));
+        }
+
+        let previous = self.status;
+        self.status = Status::from_bytes(&response.data)?;
+
+        if !self.status.follows(previous) {
+            return Err(YkvcError::CommandRejected);
+        }
+
+        Ok(())
+    }
+
+    /// Sends an HMAC-SHA1 challenge to `slot` and returns the 20-byte response
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the slot is not programmed or the transport fails
+    pub fn challenge_hmac(&mut self, slot: Slot, challenge: &[u8]) -> Result<Vec<u8>> {
+        let payload = otp::pad_hmac_challenge(challenge)?;
+        let command = otp::challenge_command(slot);
+        let response = self.card.send(&Apdu::new(INS_CONFIG, command, 0, &payload))?;
+
+        match response.sw {
+            apdu::SW_SUCCESS if response.data.len() == otp::HMAC_RESPONSE_SIZE => Ok(response.data),
+            apdu::SW_SUCCESS | apdu::SW_CONDITIONS_NOT_SATISFIED => {
+                Err(YkvcError::SlotNotProgrammed(slot))
+            }
+            sw => Err(YkvcError::Protocol(format!("Challenge-response failed: SW {sw:04X}"))),
+        }
+    }
+}
+
+/// Runs a blocking card exchange that may wait for a touch
+///
+/// A card transmit cannot be interrupted, so the exchange runs on its own
+/// thread. If it has not finished after [`TOUCH_PROMPT_DELAY`] the user is
+/// asked to touch the key, and after `timeout` it is abandoned.
+///
+/// # Errors
+///
+/// Returns [`YkvcError::TouchTimeout`] if the exchange does not finish in time,
+/// or the error of the exchange itself
+pub fn await_touch<T, F>(timeout: Duration, exchange: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // The receiver is gone once the wait timed out
+        let _ = sender.send(exchange());
+    });
+
+    let result = match receiver.recv_timeout(TOUCH_PROMPT_DELAY.min(timeout)) {
+        Err(RecvTimeoutError::Timeout) => {
+            prompt::touch(timeout);
+            receiver.recv_timeout(timeout.saturating_sub(TOUCH_PROMPT_DELAY))
+        }
+        result => result,
+    };
+
+    match result {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(YkvcError::TouchTimeout(timeout.as_secs())),
+        Err(RecvTimeoutError::Disconnected) => {
+            Err(YkvcError::PcscFailed("Card exchange aborted".to_string()))
+        }
+    }
+}
+
+#[cfg(feature = "pcsc")]
+pub use reader::{cards, PcscBackend};
+
+#[cfg(feature = "pcsc")]
+mod reader {
+    //! PC/SC reader transport
+
+    use super::{await_touch, OtpApplet};
+    use crate::backend::apdu::CardTransport;
+    use crate::backend::{otp, select_device, TokenBackend};
+    use crate::error::{Result, YkvcError};
+    use crate::yubikey::{AccessCode, ProgramOptions, Slot, SlotConfig, YubiKeyInfo};
+    use std::time::Duration;
+
+    /// Card connected through a PC/SC reader
+    pub struct PcscCard(::pcsc::Card);
+
+    impl CardTransport for PcscCard {
+        fn transmit(&mut self, command: &[u8]) -> Result<Vec<u8>> {
+            let mut buf = [0u8; ::pcsc::MAX_BUFFER_SIZE];
+            self.0
+                .transmit(command, &mut buf)
+                .map(<[u8]>::to_vec)
+                .map_err(|e| YkvcError::PcscFailed(format!("Failed to transmit APDU: {e}")))
+        }
+    }
+
+    /// Backend that drives the OTP applet over PC/SC
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct PcscBackend {
+        serial: Option<u32>,
+    }
+
+    impl PcscBackend {
+        /// Creates a new PC/SC backend, optionally bound to one serial number
+        #[must_use]
+        pub const fn new(serial: Option<u32>) -> Self {
+            Self { serial }
+        }
+
+        /// Selects the OTP applet on every reader holding a `YubiKey`
+        fn open_all() -> Result<Vec<OtpApplet<PcscCard>>> {
+            Ok(cards()?.into_iter().filter_map(|card| OtpApplet::select(card).ok()).collect())
+        }
+
+        /// Selects the OTP applet of the selected `YubiKey`
+        fn open(self) -> Result<OtpApplet<PcscCard>> {
+            let applets = Self::open_all()?
+                .into_iter()
+                .map(|mut applet| (applet.serial().ok(), applet))
+                .collect();
+
+            select_device(applets, self.serial)
+        }
+
+        /// Queries serial, firmware and slot status of a selected applet
+        fn device_info(applet: &mut OtpApplet<PcscCard>) -> Result<YubiKeyInfo> {
+            let status = applet.status();
+
+            Ok(YubiKeyInfo {
+                serial: applet.serial()?.to_string(),
+                firmware: status.firmware(),
+                slot1_config: status.slot_config(Slot::One),
+                slot2_config: status.slot_config(Slot::Two),
+                ..YubiKeyInfo::default()
+            })
+        }
+    }
+
+    /// Connects to the card in every reader
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if PC/SC is not available or the readers cannot be listed
+    pub fn cards() -> Result<Vec<PcscCard>> {
+        let context = ::pcsc::Context::establish(::pcsc::Scope::User).map_err(|e| {
+            YkvcError::PcscFailed(format!("Failed to establish PC/SC context: {e}"))
+        })?;
+
+        let readers = match context.list_readers_owned() {
+            Ok(readers) => readers,
+            Err(::pcsc::Error::NoReadersAvailable) => return Ok(Vec::new()),
+            Err(e) => return Err(YkvcError::PcscFailed(format!("Failed to list readers: {e}"))),
+        };
+
+        Ok(readers
+            .iter()
+            .filter_map(|reader| {
+                context.connect(reader, ::pcsc::ShareMode::Shared, ::pcsc::Protocols::ANY).ok()
+            })
+            .map(PcscCard)
+            .collect())
+    }
+
+    impl TokenBackend for PcscBackend {
+        fn requires_dependencies(&self) -> bool {
+            false
+        }
+
+        fn uses_slots(&self) -> bool {
+            true
+        }
+
+        fn detect(&self) -> Result<bool> {
+            Ok(!Self::open_all()?.is_empty())
+        }
+
+        fn list(&self) -> Result<Vec<YubiKeyInfo>> {
+            Self::open_all()?.iter_mut().map(Self::device_info).collect()
+        }
+
+        fn info(&self) -> Result<YubiKeyInfo> {
+            Self::device_info(&mut self.open()?)
+        }
+
+        fn slot_config(&self, slot: Slot) -> Result<SlotConfig> {
+            Ok(self.open()?.status().slot_config(slot))
+        }
+
+        fn program(
+            &self,
+            slot: Slot,
+            secret: &[u8],
+            options: ProgramOptions,
+            access_code: Option<AccessCode>,
+        ) -> Result<()> {
+            let config = otp::hmac_config(secret, options, access_code)?;
+            self.open()?.write_config(otp::config_command(slot), &config)
+        }
+
+        fn delete(&self, slot: Slot, access_code: Option<AccessCode>) -> Result<()> {
+            let config = otp::delete_config(access_code);
+            self.open()?.write_config(otp::config_command(slot), &config)
+        }
+
+        fn swap(&self) -> Result<()> {
+            self.open()?.write_config(otp::CMD_SWAP, &[])
+        }
+
+        fn challenge(
+            &self,
+            slot: Slot,
+            challenge: &[u8],
+            touch_timeout: Duration,
+        ) -> Result<Vec<u8>> {
+            let mut applet = self.open()?;
+            let challenge = challenge.to_vec();
+            await_touch(touch_timeout, move || applet.challenge_hmac(slot, &challenge))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::apdu::scripted::ScriptedCard;
+    use crate::yubikey::{FirmwareVersion, ProgramOptions};
+
+    /// SELECT of the OTP applet
+    const SELECT: &str = "00a4040007a0000005272001";
+
+    /// Status answer: firmware 5.4.3, programming sequence 5, both slots valid
+    const SELECT_OK: &str = "0504030503009000";
+
+    fn applet(exchanges: &[(&str, &str)]) -> OtpApplet<ScriptedCard> {
+        let mut script = vec![(SELECT, SELECT_OK)];
+        script.extend_from_slice(exchanges);
+        OtpApplet::select(ScriptedCard::new(&script)).unwrap()
+    }
+
+    #[test]
+    fn test_select_reads_status() {
+        let applet = applet(&[]);
+        assert_eq!(applet.status().firmware(), FirmwareVersion::new(5, 4, 3));
+        assert_eq!(applet.status().prog_seq, 5);
+        assert!(applet.status().slot_config(Slot::Two).is_programmed());
+        assert!(applet.card.is_done());
+    }
+
+    #[test]
+    fn test_select_without_otp_applet() {
+        let card = ScriptedCard::new(&[(SELECT, "6a82")]);
+        let result = OtpApplet::select(card);
+        assert!(matches!(result, Err(YkvcError::Protocol(msg)) if msg.contains("not found")));
+    }
+
+    #[test]
+    fn test_serial() {
+        let mut applet = applet(&[("00011000", "00bc614e9000")]);
+        assert_eq!(applet.serial().unwrap(), 12_345_678);
+    }
+
+    #[test]
+    fn test_challenge_hmac() {
+        let challenge = format!("00013800404869205468657265{}", "00".repeat(56));
+        let mut applet = applet(&[(&challenge, "b617318655057264e28bc0b6fb378c8ef146be009000")]);
+
+        let response = applet.challenge_hmac(Slot::Two, b"Hi There").unwrap();
+
+        assert_eq!(hex::encode(response), "b617318655057264e28bc0b6fb378c8ef146be00");
+        assert!(applet.card.is_done());
+    }
+
+    #[test]
+    fn test_challenge_hmac_unprogrammed_slot() {
+        let challenge = format!("00013000406162{}", "00".repeat(62));
+        let mut applet = applet(&[(&challenge, "6985")]);
+
+        let result = applet.challenge_hmac(Slot::One, b"ab");
+
+        assert!(matches!(result, Err(YkvcError::SlotNotProgrammed(Slot::One))));
+    }
+
+    #[test]
+    fn test_write_config() {
+        let config = otp::hmac_config(&[0x0b; 20], ProgramOptions::default(), None).unwrap();
+        let command = format!("000103003a{}", hex::encode(&config));
+        let mut applet = applet(&[(&command, "0504030603009000")]);
+
+        applet.write_config(otp::CMD_CONFIG_2, &config).unwrap();
+
+        assert_eq!(applet.status().prog_seq, 6);
+    }
+
+    #[test]
+    fn test_delete_slot() {
+        let command = format!("000101003a{}", "00".repeat(58));
+        let mut applet = applet(&[(&command, "0504030602009000")]);
+
+        applet.write_config(otp::config_command(Slot::One), &otp::delete_config(None)).unwrap();
+
+        assert!(!applet.status().slot_config(Slot::One).is_programmed());
+        assert!(applet.status().slot_config(Slot::Two).is_programmed());
+    }
+
+    #[test]
+    fn test_await_touch() {
+        assert_eq!(await_touch(Duration::from_secs(1), || Ok(7)).unwrap(), 7);
+
+        let result = await_touch(Duration::from_secs(1), || {
+            thread::sleep(Duration::from_secs(3));
+            Ok(())
+        });
+        assert!(matches!(result, Err(YkvcError::TouchTimeout(1))));
+    }
+
+    #[test]
+    fn test_write_config_not_applied() {
+        let config = otp::hmac_config(&[0x0b; 20], ProgramOptions::default(), None).unwrap();
+        let command = format!("000103003a{}", hex::encode(&config));
+        let mut applet = applet(&[(&command, "0504030503009000")]);
+
+        let result = applet.write_config(otp::CMD_CONFIG_2, &config);
+
+        assert!(matches!(result, Err(YkvcError::CommandRejected)));
+    }
+}
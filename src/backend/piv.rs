@@ -0,0 +1,678 @@
+//! PIV key derivation backend
+//!
+//! Derives keyfiles from ECDH with a P-256 key in a PIV slot, for keys whose
+//! OTP slots are taken. `ykvc piv enroll` picks a random point on the curve
+//! (its scalar is thrown away at once) and records it. For every challenge the
+//! card multiplies that point with its private key in GENERAL AUTHENTICATE,
+//! and the shared secret is hashed with the challenge bytes. The private key
+//! never leaves the card, so only the card can derive the keyfiles again.
+//!
+//! The applet is reached over PC/SC, which needs the `pcsc` cargo feature, or
+//! through the software card named by `YKVC_PIV_CARD`.
+
+use super::apdu::{self, Apdu, CardTransport};
+use super::piv_card::SoftCard;
+use super::{select_device, TokenBackend};
+use crate::config::PivEnrollment;
+use crate::crypto;
+use crate::error::{Result, YkvcError};
+use crate::prompt;
+use crate::yubikey::{AccessCode, FirmwareVersion, ProgramOptions, Slot, SlotConfig, YubiKeyInfo};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Environment variable naming the state file of the software PIV card
+pub const CARD_ENV: &str = "YKVC_PIV_CARD";
+
+/// Application identifier of the PIV applet
+pub const PIV_AID: [u8; 5] = [0xa0, 0x00, 0x00, 0x03, 0x08];
+
+/// VERIFY instruction
+pub const INS_VERIFY: u8 = 0x20;
+
+/// GENERAL AUTHENTICATE instruction
+pub const INS_GENERAL_AUTHENTICATE: u8 = 0x87;
+
+/// Yubico extension: read the serial number
+pub const INS_GET_SERIAL: u8 = 0xf8;
+
+/// Yubico extension: read the firmware version
+pub const INS_GET_VERSION: u8 = 0xfd;
+
+/// Key reference of the PIV application PIN
+pub const PIN_REFERENCE: u8 = 0x80;
+
+/// Length PINs are padded to with `0xff`
+pub const PIN_SIZE: usize = 8;
+
+/// Algorithm identifier of ECC P-256 keys
+pub const ALG_ECC_P256: u8 = 0x11;
+
+/// Dynamic authentication template
+pub const TAG_DYNAMIC_AUTH: u8 = 0x7c;
+
+/// Response element of the template
+pub const TAG_RESPONSE: u8 = 0x82;
+
+/// Exponentiation element of the template, holding the peer point
+pub const TAG_EXPONENTIATION: u8 = 0x85;
+
+/// Size of the shared secret and of the derived keyfile
+pub const SECRET_SIZE: usize = 32;
+
+/// Status words `63CX`: wrong PIN, X attempts left
+const SW_WRONG_PIN: u16 = 0x63c0;
+
+/// PIV key slot holding the private key, such as 9d
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PivSlot(u8);
+
+impl PivSlot {
+    /// Key management slot, meant for key agreement
+    pub const KEY_MANAGEMENT: Self = Self(0x9d);
+
+    /// Key reference of the slot in GENERAL AUTHENTICATE
+    #[must_use]
+    pub const fn key_reference(self) -> u8 {
+        self.0
+    }
+}
+
+impl Default for PivSlot {
+    fn default() -> Self {
+        Self::KEY_MANAGEMENT
+    }
+}
+
+impl fmt::Display for PivSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}", self.0)
+    }
+}
+
+impl FromStr for PivSlot {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match u8::from_str_radix(s, 16) {
+            Ok(slot @ (0x9a | 0x9c | 0x9d | 0x9e | 0x82..=0x95)) if s.len() == 2 => Ok(Self(slot)),
+            _ => Err(format!("'{s}' is not a PIV key slot (9a, 9c, 9d, 9e or 82 to 95)")),
+        }
+    }
+}
+
+impl TryFrom<String> for PivSlot {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PivSlot> for String {
+    fn from(slot: PivSlot) -> Self {
+        slot.to_string()
+    }
+}
+
+/// Encodes a BER-TLV element with a one-byte tag
+#[must_use]
+pub fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match u8::try_from(value.len()) {
+        Ok(len @ 0..=0x7f) => out.push(len),
+        Ok(len) => out.extend_from_slice(&[0x81, len]),
+        Err(_) => {
+            out.push(0x82);
+            out.extend_from_slice(&u16::try_from(value.len()).unwrap_or(u16::MAX).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(value);
+    out
+}
+
+/// Splits the first BER-TLV element off `data`
+///
+/// Returns the tag, the value and the remaining bytes.
+///
+/// # Errors
+///
+/// Returns an error if the element is truncated or its length is malformed
+pub fn parse_tlv(data: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    let malformed = || YkvcError::Protocol("Malformed TLV data".to_string());
+
+    let (tag, length, rest) = match data {
+        [tag, 0x81, length, rest @ ..] | [tag, length @ 0..=0x7f, rest @ ..] => {
+            (*tag, usize::from(*length), rest)
+        }
+        [tag, 0x82, high, low, rest @ ..] => {
+            (*tag, usize::from(u16::from_be_bytes([*high, *low])), rest)
+        }
+        _ => return Err(malformed()),
+    };
+
+    if rest.len() < length {
+        return Err(malformed());
+    }
+    let (value, rest) = rest.split_at(length);
+    Ok((tag, value, rest))
+}
+
+/// Keyfile for `challenge`, keyed with the shared secret of the enrolled point
+#[must_use]
+pub fn derive(shared_secret: &[u8], challenge: &[u8]) -> [u8; SECRET_SIZE] {
+    crypto::hmac_sha256(shared_secret, challenge)
+}
+
+/// Selected PIV applet on a smart card transport
+#[derive(Debug)]
+pub struct PivApplet<T> {
+    card: T,
+}
+
+impl<T: CardTransport> PivApplet<T> {
+    /// Selects the PIV applet
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the card has no PIV applet or the transport fails
+    pub fn select(mut card: T) -> Result<Self> {
+        let response = card.send(&Apdu::select(&PIV_AID))?;
+
+        match response.sw {
+            apdu::SW_SUCCESS => Ok(Self { card }),
+            apdu::SW_FILE_NOT_FOUND => {
+                Err(YkvcError::Protocol("PIV applet not found on card".to_string()))
+            }
+            sw => Err(YkvcError::Protocol(format!("Selecting PIV applet failed: SW {sw:04X}"))),
+        }
+    }
+
+    /// Reads the device serial number
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the serial is not readable or the transport fails
+    pub fn serial(&mut self) -> Result<u32> {
+        let response = self.card.send(&Apdu::new(INS_GET_SERIAL, 0, 0, &[]))?;
+
+        match response.data.as_slice() {
+            [a, b, c, d] if response.is_success() => Ok(u32::from_be_bytes([*a, *b, *c, *d])),
+            _ => Err(YkvcError::Protocol(format!(
+                "Unexpected serial number answer: {} bytes, SW {:04X}",
+                response.data.len(),
+                response.sw
+            ))),
+        }
+    }
+
+    /// Reads the firmware version
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the version is not readable or the transport fails
+    pub fn version(&mut self) -> Result<FirmwareVersion> {
+        let response = self.card.send(&Apdu::new(INS_GET_VERSION, 0, 0, &[]))?;
+
+        match response.data.as_slice() {
+            [major, minor, patch] if response.is_success() => {
+                Ok(FirmwareVersion::new(*major, *minor, *patch))
+            }
+            _ => Err(YkvcError::Protocol(format!(
+                "Unexpected version answer: {} bytes, SW {:04X}",
+                response.data.len(),
+                response.sw
+            ))),
+        }
+    }
+
+    /// Verifies the PIN, which unlocks the keys for this session
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the PIN is malformed, wrong or blocked
+    pub fn verify_pin(&mut self, pin: &str) -> Result<()> {
+        if !(6..=PIN_SIZE).contains(&pin.len()) {
+            return Err(YkvcError::InvalidPivPin(format!(
+                "expected 6 to {PIN_SIZE} characters, got {}",
+                pin.len()
+            )));
+        }
+
+        let mut data = pin.as_bytes().to_vec();
+        data.resize(PIN_SIZE, 0xff);
+        let response = self.card.send(&Apdu::new(INS_VERIFY, 0, PIN_REFERENCE, &data))?;
+
+        match response.sw {
+            apdu::SW_SUCCESS => Ok(()),
+            apdu::SW_AUTH_METHOD_BLOCKED => Err(YkvcError::PivPinBlocked),
+            sw if sw & 0xfff0 == SW_WRONG_PIN => {
+                Err(YkvcError::PivPinIncorrect(u8::try_from(sw & 0x0f).unwrap_or_default()))
+            }
+            sw => Err(YkvcError::Protocol(format!("PIN verification failed: SW {sw:04X}"))),
+        }
+    }
+
+    /// Runs ECDH between the P-256 key in `slot` and `point`
+    ///
+    /// Returns the x coordinate of the shared point.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the slot holds no P-256 key, the PIN has not been
+    /// verified, or the transport fails
+    pub fn ecdh(&mut self, slot: PivSlot, point: &PublicKey) -> Result<[u8; SECRET_SIZE]> {
+        let template = tlv(
+            TAG_DYNAMIC_AUTH,
+            &[
+                tlv(TAG_RESPONSE, &[]),
+                tlv(TAG_EXPONENTIATION, point.to_encoded_point(false).as_bytes()),
+            ]
+            .concat(),
+        );
+        let response = self.card.send(&Apdu::new(
+            INS_GENERAL_AUTHENTICATE,
+            ALG_ECC_P256,
+            slot.key_reference(),
+            &template,
+        ))?;
+
+        match response.sw {
+            apdu::SW_SUCCESS => {}
+            apdu::SW_WRONG_DATA | apdu::SW_FILE_NOT_FOUND | apdu::SW_REFERENCE_NOT_FOUND => {
+                return Err(YkvcError::PivKeyMissing(slot))
+            }
+            apdu::SW_SECURITY_STATUS_NOT_SATISFIED => {
+                return Err(YkvcError::Protocol(format!(
+                    "PIV slot {slot} refused key agreement: PIN not verified"
+                )))
+            }
+            sw => {
+                return Err(YkvcError::Protocol(format!("PIV key agreement failed: SW {sw:04X}")))
+            }
+        }
+
+        let (tag, template, _) = parse_tlv(&response.data)?;
+        let (element, secret, _) = parse_tlv(template)?;
+        if tag != TAG_DYNAMIC_AUTH || element != TAG_RESPONSE {
+            return Err(YkvcError::Protocol("Unexpected key agreement answer".to_string()));
+        }
+        secret.try_into().map_err(|_| {
+            YkvcError::Protocol(format!("Unexpected shared secret length: {} bytes", secret.len()))
+        })
+    }
+}
+
+/// Card transport the applet is selected on
+type Card = Box<dyn CardTransport + Send>;
+
+/// Where the PIV applet is reached
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Device {
+    /// Smart card readers through PC/SC
+    Pcsc,
+    /// Software card keeping its state in this file
+    Software(PathBuf),
+}
+
+/// Backend deriving keyfiles from ECDH with a PIV key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PivBackend {
+    device: Device,
+    serial: Option<u32>,
+    enrollment: Option<PivEnrollment>,
+}
+
+impl PivBackend {
+    /// Creates the backend, using the software card if the environment names one
+    ///
+    /// `enrollment` is the enrolled slot and point, if any.
+    #[must_use]
+    pub fn from_env(serial: Option<u32>, enrollment: Option<PivEnrollment>) -> Self {
+        let device = std::env::var_os(CARD_ENV)
+            .map_or(Device::Pcsc, |path| Device::Software(PathBuf::from(path)));
+        Self { device, serial, enrollment }
+    }
+
+    /// Selects the PIV applet on every card
+    fn open_all(&self) -> Result<Vec<PivApplet<Card>>> {
+        let cards = match &self.device {
+            Device::Software(path) => vec![Box::new(SoftCard::open(path)?) as Card],
+            Device::Pcsc => pcsc_cards()?,
+        };
+        Ok(cards.into_iter().filter_map(|card| PivApplet::select(card).ok()).collect())
+    }
+
+    /// Selects the PIV applet of the selected `YubiKey`
+    fn open(&self) -> Result<PivApplet<Card>> {
+        let applets =
+            self.open_all()?.into_iter().map(|mut applet| (applet.serial().ok(), applet)).collect();
+        select_device(applets, self.serial)
+    }
+
+    /// Queries serial and firmware of a selected applet
+    fn device_info(applet: &mut PivApplet<Card>) -> Result<YubiKeyInfo> {
+        Ok(YubiKeyInfo {
+            serial: applet.serial().map_or_else(|_| "Unknown".to_string(), |s| s.to_string()),
+            firmware: applet.version()?,
+            ..YubiKeyInfo::default()
+        })
+    }
+
+    /// Checks that `slot` holds a P-256 key and picks the point to agree on
+    ///
+    /// `read_pin` is called once the card is found. The user has to touch the
+    /// key within `touch_timeout` if its touch policy asks for it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the PIN is wrong, the slot holds no P-256 key, or
+    /// the card cannot be reached
+    pub fn enroll(
+        &self,
+        slot: PivSlot,
+        read_pin: impl FnOnce() -> Result<String>,
+        touch_timeout: Duration,
+    ) -> Result<PivEnrollment> {
+        let point = SecretKey::random(&mut rand::rngs::OsRng).public_key();
+        self.shared_secret(slot, point, read_pin, touch_timeout)?;
+
+        Ok(PivEnrollment { slot, point: point.to_encoded_point(false).as_bytes().to_vec() })
+    }
+
+    /// Verifies the PIN and has the card run ECDH with `point`
+    fn shared_secret(
+        &self,
+        slot: PivSlot,
+        point: PublicKey,
+        read_pin: impl FnOnce() -> Result<String>,
+        touch_timeout: Duration,
+    ) -> Result<[u8; SECRET_SIZE]> {
+        let mut applet = self.open()?;
+        applet.verify_pin(&read_pin()?)?;
+        await_touch(touch_timeout, move || applet.ecdh(slot, &point))
+    }
+
+    fn no_slots() -> YkvcError {
+        YkvcError::BackendUnavailable(
+            "the piv backend has no OTP slots, enroll a PIV key with 'ykvc piv enroll'".to_string(),
+        )
+    }
+}
+
+/// Reads the PIV PIN with a hidden prompt
+///
+/// # Errors
+///
+/// Returns an error if reading fails
+pub fn read_pin() -> Result<String> {
+    prompt::password("PIV PIN").map_err(|e| YkvcError::Other(format!("Failed to read PIN: {e}")))
+}
+
+/// Connects to the card in every PC/SC reader
+#[cfg(feature = "pcsc")]
+fn pcsc_cards() -> Result<Vec<Card>> {
+    Ok(super::pcsc::cards()?.into_iter().map(|card| Box::new(card) as Card).collect())
+}
+
+/// Fails: readers are only reachable with the `pcsc` feature
+#[cfg(not(feature = "pcsc"))]
+fn pcsc_cards() -> Result<Vec<Card>> {
+    Err(YkvcError::BackendUnavailable(
+        "the piv backend needs PC/SC support (rebuild with `--features pcsc`)".to_string(),
+    ))
+}
+
+/// Runs a card exchange that may wait for a touch
+#[cfg(feature = "pcsc")]
+fn await_touch<T: Send + 'static>(
+    timeout: Duration,
+    exchange: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    super::pcsc::await_touch(timeout, exchange)
+}
+
+/// Runs a card exchange; without PC/SC only the software card, which never
+/// waits for a touch, is reachable
+#[cfg(not(feature = "pcsc"))]
+fn await_touch<T>(_timeout: Duration, exchange: impl FnOnce() -> Result<T>) -> Result<T> {
+    exchange()
+}
+
+impl TokenBackend for PivBackend {
+    fn requires_dependencies(&self) -> bool {
+        false
+    }
+
+    fn uses_slots(&self) -> bool {
+        false
+    }
+
+    fn detect(&self) -> Result<bool> {
+        Ok(!self.open_all()?.is_empty())
+    }
+
+    fn list(&self) -> Result<Vec<YubiKeyInfo>> {
+        self.open_all()?.iter_mut().map(Self::device_info).collect()
+    }
+
+    fn info(&self) -> Result<YubiKeyInfo> {
+        Self::device_info(&mut self.open()?)
+    }
+
+    fn slot_config(&self, _slot: Slot) -> Result<SlotConfig> {
+        Ok(SlotConfig::Empty)
+    }
+
+    fn program(
+        &self,
+        _slot: Slot,
+        _secret: &[u8],
+        _options: ProgramOptions,
+        _access_code: Option<AccessCode>,
+    ) -> Result<()> {
+        Err(Self::no_slots())
+    }
+
+    fn delete(&self, _slot: Slot, _access_code: Option<AccessCode>) -> Result<()> {
+        Err(Self::no_slots())
+    }
+
+    fn swap(&self) -> Result<()> {
+        Err(Self::no_slots())
+    }
+
+    fn challenge(&self, _slot: Slot, challenge: &[u8], touch_timeout: Duration) -> Result<Vec<u8>> {
+        let enrollment = self.enrollment.as_ref().ok_or(YkvcError::PivNotEnrolled)?;
+        let point = PublicKey::from_sec1_bytes(&enrollment.point)
+            .map_err(|_| YkvcError::Protocol("Enrolled PIV point is invalid".to_string()))?;
+
+        let shared_secret = self.shared_secret(enrollment.slot, point, read_pin, touch_timeout)?;
+        Ok(derive(&shared_secret, challenge).to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::apdu::scripted::ScriptedCard;
+
+    const TIMEOUT: Duration = Duration::from_secs(15);
+
+    /// NIST CAVS ECC CDH primitive test vector, P-256 count 0: peer point
+    const PEER_POINT: &str = "04700c48f77f56584c5cc632ca65640db91b6bacce3a4df6b42ce7cc838833d287\
+                              db71e509e3fd9b060ddb20ba5c51dcc5948d46fbf640dfe0441782cab85fa4ac";
+
+    /// Private key of the same vector
+    const PRIVATE_KEY: &str = "7d7dc5f71eb29ddaf80d6214632eeae03d9058af1fb6d22ed80badb62bc1a534";
+
+    /// Shared secret of the same vector
+    const SHARED_SECRET: &str = "46fc62106420ff012e54a434fbdd2d25ccc5852060561e68040dd7778997bd7b";
+
+    /// SELECT of the PIV applet
+    const SELECT: &str = "00a4040005a000000308";
+
+    fn peer_point() -> PublicKey {
+        PublicKey::from_sec1_bytes(&hex::decode(PEER_POINT).unwrap()).unwrap()
+    }
+
+    fn applet(exchanges: &[(&str, &str)]) -> PivApplet<ScriptedCard> {
+        let mut script = vec![(SELECT, "9000")];
+        script.extend_from_slice(exchanges);
+        PivApplet::select(ScriptedCard::new(&script)).unwrap()
+    }
+
+    /// Backend on a software card with the vector's key in slot 9d
+    fn backend(dir: &tempfile::TempDir) -> PivBackend {
+        let path = dir.path().join("card.toml");
+        std::fs::write(&path, format!("serial = 42\n[keys]\n9d = \"{PRIVATE_KEY}\"\n")).unwrap();
+        PivBackend { device: Device::Software(path), serial: None, enrollment: None }
+    }
+
+    fn pin() -> Result<String> {
+        Ok("123456".to_string())
+    }
+
+    #[test]
+    fn test_slot_parsing() {
+        assert_eq!("9d".parse::<PivSlot>().unwrap(), PivSlot::KEY_MANAGEMENT);
+        assert_eq!("9A".parse::<PivSlot>().unwrap().to_string(), "9a");
+        assert_eq!("82".parse::<PivSlot>().unwrap().key_reference(), 0x82);
+        for invalid in ["9b", "96", "0x9d", "9d9d", "", "zz"] {
+            assert!(invalid.parse::<PivSlot>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_tlv() {
+        assert_eq!(hex::encode(tlv(0x82, &[])), "8200");
+        assert_eq!(tlv(0x85, &[7; 0x80])[..3], [0x85, 0x81, 0x80]);
+        assert_eq!(tlv(0x85, &[7; 0x100])[..4], [0x85, 0x82, 0x01, 0x00]);
+
+        let data = [tlv(0x7c, &[1, 2, 3]), tlv(0x85, &[9; 200])].concat();
+        let (tag, value, rest) = parse_tlv(&data).unwrap();
+        assert_eq!((tag, value), (0x7c, &[1, 2, 3][..]));
+        let (tag, value, rest) = parse_tlv(rest).unwrap();
+        assert_eq!((tag, value.len(), rest.len()), (0x85, 200, 0));
+
+        assert!(parse_tlv(&[0x7c]).is_err());
+        assert!(parse_tlv(&[0x7c, 0x03, 1, 2]).is_err());
+        assert!(parse_tlv(&[0x7c, 0x83, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_ecdh_apdu() {
+        let command = format!("0087119d477c4582008541{PEER_POINT}");
+        let answer = format!("7c228220{SHARED_SECRET}9000");
+        let mut applet = applet(&[(&command, &answer)]);
+
+        let secret = applet.ecdh(PivSlot::KEY_MANAGEMENT, &peer_point()).unwrap();
+
+        assert_eq!(hex::encode(secret), SHARED_SECRET);
+        assert!(applet.card.is_done());
+    }
+
+    #[test]
+    fn test_ecdh_refused() {
+        let signature_slot = format!("0087119a477c4582008541{PEER_POINT}");
+        let key_management = format!("0087119d477c4582008541{PEER_POINT}");
+        let mut applet = applet(&[(&signature_slot, "6a88"), (&key_management, "6982")]);
+
+        let slot = "9a".parse().unwrap();
+        let result = applet.ecdh(slot, &peer_point());
+        assert!(matches!(result, Err(YkvcError::PivKeyMissing(s)) if s == slot));
+
+        let result = applet.ecdh(PivSlot::KEY_MANAGEMENT, &peer_point());
+        assert!(
+            matches!(result, Err(YkvcError::Protocol(msg)) if msg.contains("PIN not verified"))
+        );
+    }
+
+    #[test]
+    fn test_verify_pin() {
+        let mut applet = applet(&[
+            ("0020008008313233343536ffff", "9000"),
+            ("00200080083132333435363738", "63c1"),
+            ("002000800839393939393939ff", "6983"),
+        ]);
+
+        applet.verify_pin("123456").unwrap();
+        assert!(matches!(applet.verify_pin("12345678"), Err(YkvcError::PivPinIncorrect(1))));
+        assert!(matches!(applet.verify_pin("9999999"), Err(YkvcError::PivPinBlocked)));
+        assert!(matches!(applet.verify_pin("12345"), Err(YkvcError::InvalidPivPin(_))));
+        assert!(matches!(applet.verify_pin("123456789"), Err(YkvcError::InvalidPivPin(_))));
+        assert!(applet.card.is_done());
+    }
+
+    #[test]
+    fn test_serial_and_version() {
+        let mut applet = applet(&[("00f80000", "00bc614e9000"), ("00fd0000", "0504039000")]);
+        assert_eq!(applet.serial().unwrap(), 12_345_678);
+        assert_eq!(applet.version().unwrap(), FirmwareVersion::new(5, 4, 3));
+    }
+
+    #[test]
+    fn test_derive() {
+        let secret = hex::decode(SHARED_SECRET).unwrap();
+        assert_eq!(
+            hex::encode(derive(&secret, b"Hi There")),
+            "24924d3ba7802520152ea0d74a2025311d1e236285893ddeb469c1a953495f8e"
+        );
+    }
+
+    #[test]
+    fn test_software_card_vector() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = backend(&dir);
+
+        let secret =
+            backend.shared_secret(PivSlot::KEY_MANAGEMENT, peer_point(), pin, TIMEOUT).unwrap();
+        assert_eq!(hex::encode(secret), SHARED_SECRET);
+
+        assert_eq!(backend.info().unwrap().serial, "42");
+        assert!(!backend.uses_slots());
+    }
+
+    #[test]
+    fn test_enroll_and_derive() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut backend = backend(&dir);
+
+        let enrollment = backend.enroll(PivSlot::KEY_MANAGEMENT, pin, TIMEOUT).unwrap();
+        assert_eq!(enrollment.slot, PivSlot::KEY_MANAGEMENT);
+        assert_eq!(enrollment.point.len(), 65);
+
+        // The card's answer matches ECDH computed in software with its key
+        let key = SecretKey::from_slice(&hex::decode(PRIVATE_KEY).unwrap()).unwrap();
+        let point = PublicKey::from_sec1_bytes(&enrollment.point).unwrap();
+        let expected = p256::ecdh::diffie_hellman(key.to_nonzero_scalar(), point.as_affine());
+        let secret = backend.shared_secret(enrollment.slot, point, pin, TIMEOUT).unwrap();
+        assert_eq!(secret[..], expected.raw_secret_bytes()[..]);
+
+        // A second enrollment picks another point
+        let other = backend.enroll(PivSlot::KEY_MANAGEMENT, pin, TIMEOUT).unwrap();
+        assert_ne!(other.point, enrollment.point);
+
+        let result = backend.enroll("9a".parse().unwrap(), pin, TIMEOUT);
+        assert!(matches!(result, Err(YkvcError::PivKeyMissing(_))));
+
+        backend.enrollment = None;
+        let result = backend.challenge(Slot::Two, b"Hi There", TIMEOUT);
+        assert!(matches!(result, Err(YkvcError::PivNotEnrolled)));
+    }
+
+    #[test]
+    fn test_wrong_pin() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = backend(&dir);
+
+        let result = backend.enroll(PivSlot::KEY_MANAGEMENT, || Ok("654321".to_string()), TIMEOUT);
+        assert!(matches!(result, Err(YkvcError::PivPinIncorrect(_))));
+    }
+}
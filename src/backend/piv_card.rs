@@ -0,0 +1,292 @@
+//! Software PIV card
+//!
+//! Stands in for the PIV applet of a `YubiKey` in tests and demos. It answers
+//! raw APDUs through the same [`CardTransport`] as a card in a reader, and
+//! implements just what the piv backend needs: SELECT, the Yubico serial and
+//! version instructions, PIN verification and ECDH with P-256 keys through
+//! GENERAL AUTHENTICATE. Wrong PINs are refused but not counted, and no key
+//! has a touch policy.
+//!
+//! The state file is TOML holding the PIN (`123456`, the `YubiKey` default,
+//! if unset), an optional serial number and the private keys by slot:
+//!
+//! ```toml
+//! pin = "654321"
+//! serial = 12345678
+//!
+//! [keys]
+//! 9d = "<P-256 private key in hex>"
+//! ```
+//!
+//! The keys sit in the clear, so the card must never back real containers.
+
+use super::apdu::{self, CardTransport};
+use super::piv::{
+    self, PivSlot, ALG_ECC_P256, INS_GENERAL_AUTHENTICATE, INS_GET_SERIAL, INS_GET_VERSION,
+    INS_VERIFY, PIN_REFERENCE, PIN_SIZE, PIV_AID, TAG_DYNAMIC_AUTH, TAG_EXPONENTIATION,
+    TAG_RESPONSE,
+};
+use crate::error::{Result, YkvcError};
+use p256::{PublicKey, SecretKey};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Firmware version reported by GET VERSION
+const VERSION: [u8; 3] = [5, 7, 1];
+
+/// SELECT instruction
+const INS_SELECT: u8 = 0xa4;
+
+/// Status word answering a wrong PIN, as if two attempts were left
+const SW_WRONG_PIN: u16 = 0x63c2;
+
+/// Contents of the state file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CardState {
+    /// PIN
+    #[serde(default = "default_pin")]
+    pin: String,
+    /// Serial number, if the card reports one
+    serial: Option<u32>,
+    /// Private keys in hex, by slot
+    #[serde(default)]
+    keys: BTreeMap<PivSlot, String>,
+}
+
+fn default_pin() -> String {
+    "123456".to_string()
+}
+
+impl Default for CardState {
+    fn default() -> Self {
+        Self { pin: default_pin(), serial: None, keys: BTreeMap::new() }
+    }
+}
+
+/// Software PIV card reading its keys from a file
+#[derive(Debug)]
+pub struct SoftCard {
+    pin: String,
+    serial: Option<u32>,
+    keys: BTreeMap<PivSlot, SecretKey>,
+    selected: bool,
+    verified: bool,
+}
+
+impl SoftCard {
+    /// Inserts the card whose state is kept in `path`, which may be missing
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state file cannot be read or is invalid
+    pub fn open(path: &Path) -> Result<Self> {
+        let invalid = |message: String| {
+            YkvcError::FileError(format!("Invalid PIV card state {}: {message}", path.display()))
+        };
+
+        let state: CardState = match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| invalid(e.to_string()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => CardState::default(),
+            Err(e) => {
+                return Err(YkvcError::FileError(format!(
+                    "Failed to read PIV card state {}: {e}",
+                    path.display()
+                )))
+            }
+        };
+
+        let keys = state
+            .keys
+            .into_iter()
+            .map(|(slot, key)| {
+                hex::decode(&key)
+                    .ok()
+                    .and_then(|key| SecretKey::from_slice(&key).ok())
+                    .map(|key| (slot, key))
+                    .ok_or_else(|| invalid(format!("slot {slot} has no valid P-256 key")))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { pin: state.pin, serial: state.serial, keys, selected: false, verified: false })
+    }
+
+    /// Answers a command APDU with response data and status word
+    fn handle(&mut self, ins: u8, p1: u8, p2: u8, data: &[u8]) -> (Vec<u8>, u16) {
+        if ins == INS_SELECT {
+            self.selected = p1 == 0x04 && data == PIV_AID;
+            self.verified = false;
+            let sw = if self.selected { apdu::SW_SUCCESS } else { apdu::SW_FILE_NOT_FOUND };
+            return (Vec::new(), sw);
+        }
+        if !self.selected {
+            return (Vec::new(), apdu::SW_CONDITIONS_NOT_SATISFIED);
+        }
+
+        match ins {
+            INS_GET_SERIAL => self.serial.map_or((Vec::new(), apdu::SW_INS_NOT_SUPPORTED), |s| {
+                (s.to_be_bytes().to_vec(), apdu::SW_SUCCESS)
+            }),
+            INS_GET_VERSION => (VERSION.to_vec(), apdu::SW_SUCCESS),
+            INS_VERIFY => (Vec::new(), self.verify(p2, data)),
+            INS_GENERAL_AUTHENTICATE => {
+                self.ecdh(p1, p2, data).unwrap_or_else(|sw| (Vec::new(), sw))
+            }
+            _ => (Vec::new(), apdu::SW_INS_NOT_SUPPORTED),
+        }
+    }
+
+    fn verify(&mut self, reference: u8, data: &[u8]) -> u16 {
+        if reference != PIN_REFERENCE {
+            return apdu::SW_REFERENCE_NOT_FOUND;
+        }
+
+        let mut pin = self.pin.as_bytes().to_vec();
+        pin.resize(PIN_SIZE, 0xff);
+        self.verified = data == pin;
+        if self.verified {
+            apdu::SW_SUCCESS
+        } else {
+            SW_WRONG_PIN
+        }
+    }
+
+    fn ecdh(
+        &self,
+        algorithm: u8,
+        reference: u8,
+        data: &[u8],
+    ) -> std::result::Result<(Vec<u8>, u16), u16> {
+        if !self.verified {
+            return Err(apdu::SW_SECURITY_STATUS_NOT_SATISFIED);
+        }
+        let key = format!("{reference:02x}")
+            .parse::<PivSlot>()
+            .ok()
+            .and_then(|slot| self.keys.get(&slot))
+            .ok_or(apdu::SW_REFERENCE_NOT_FOUND)?;
+        if algorithm != ALG_ECC_P256 {
+            return Err(apdu::SW_WRONG_DATA);
+        }
+
+        let point = match piv::parse_tlv(data) {
+            Ok((TAG_DYNAMIC_AUTH, template, [])) => match piv::parse_tlv(template) {
+                Ok((TAG_RESPONSE, [], rest)) => match piv::parse_tlv(rest) {
+                    Ok((TAG_EXPONENTIATION, point, [])) => point,
+                    _ => return Err(apdu::SW_WRONG_DATA),
+                },
+                _ => return Err(apdu::SW_WRONG_DATA),
+            },
+            _ => return Err(apdu::SW_WRONG_DATA),
+        };
+        let point = PublicKey::from_sec1_bytes(point).map_err(|_| apdu::SW_WRONG_DATA)?;
+
+        let shared = p256::ecdh::diffie_hellman(key.to_nonzero_scalar(), point.as_affine());
+        let answer = piv::tlv(
+            TAG_DYNAMIC_AUTH,
+            &piv::tlv(TAG_RESPONSE, shared.raw_secret_bytes().as_slice()),
+        );
+        Ok((answer, apdu::SW_SUCCESS))
+    }
+}
+
+impl CardTransport for SoftCard {
+    fn transmit(&mut self, command: &[u8]) -> Result<Vec<u8>> {
+        let (ins, p1, p2, data) = match command {
+            [_, ins, p1, p2] => (*ins, *p1, *p2, &[][..]),
+            [_, ins, p1, p2, length, data @ ..] if data.len() == usize::from(*length) => {
+                (*ins, *p1, *p2, data)
+            }
+            _ => return Err(YkvcError::Protocol("Malformed command APDU".to_string())),
+        };
+
+        let (mut response, sw) = self.handle(ins, p1, p2, data);
+        response.extend_from_slice(&sw.to_be_bytes());
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::apdu::Apdu;
+
+    fn card(state: &str) -> (tempfile::TempDir, SoftCard) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("card.toml");
+        std::fs::write(&path, state).unwrap();
+        let card = SoftCard::open(&path).unwrap();
+        (dir, card)
+    }
+
+    fn send(card: &mut SoftCard, ins: u8, p1: u8, p2: u8, data: &[u8]) -> u16 {
+        card.send(&Apdu::new(ins, p1, p2, data)).unwrap().sw
+    }
+
+    #[test]
+    fn test_select_and_pin() {
+        let (_dir, mut card) = card("pin = \"654321\"\n");
+
+        assert_eq!(send(&mut card, INS_GET_VERSION, 0, 0, &[]), apdu::SW_CONDITIONS_NOT_SATISFIED);
+        assert_eq!(
+            send(&mut card, INS_SELECT, 0x04, 0, &[0xa0, 0, 0, 5, 0x27]),
+            apdu::SW_FILE_NOT_FOUND
+        );
+        assert_eq!(send(&mut card, INS_SELECT, 0x04, 0, &PIV_AID), apdu::SW_SUCCESS);
+        assert_eq!(send(&mut card, INS_GET_SERIAL, 0, 0, &[]), apdu::SW_INS_NOT_SUPPORTED);
+
+        assert_eq!(send(&mut card, INS_VERIFY, 0, PIN_REFERENCE, b"123456\xff\xff"), SW_WRONG_PIN);
+        assert_eq!(
+            send(&mut card, INS_VERIFY, 0, PIN_REFERENCE, b"654321\xff\xff"),
+            apdu::SW_SUCCESS
+        );
+        assert_eq!(
+            send(&mut card, INS_VERIFY, 0, 0x81, b"654321\xff\xff"),
+            apdu::SW_REFERENCE_NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_general_authenticate_checks() {
+        let key = "7d7dc5f71eb29ddaf80d6214632eeae03d9058af1fb6d22ed80badb62bc1a534";
+        let (_dir, mut card) = card(&format!("[keys]\n9d = \"{key}\"\n"));
+        send(&mut card, INS_SELECT, 0x04, 0, &PIV_AID);
+
+        let point = SecretKey::random(&mut rand::rngs::OsRng).public_key();
+        let template = piv::tlv(
+            TAG_DYNAMIC_AUTH,
+            &[piv::tlv(TAG_RESPONSE, &[]), piv::tlv(TAG_EXPONENTIATION, &point.to_sec1_bytes())]
+                .concat(),
+        );
+        let ecdh = |card: &mut SoftCard, algorithm, slot, data: &[u8]| {
+            send(card, INS_GENERAL_AUTHENTICATE, algorithm, slot, data)
+        };
+
+        assert_eq!(
+            ecdh(&mut card, ALG_ECC_P256, 0x9d, &template),
+            apdu::SW_SECURITY_STATUS_NOT_SATISFIED
+        );
+        send(&mut card, INS_VERIFY, 0, PIN_REFERENCE, b"123456\xff\xff");
+        assert_eq!(ecdh(&mut card, ALG_ECC_P256, 0x9a, &template), apdu::SW_REFERENCE_NOT_FOUND);
+        assert_eq!(ecdh(&mut card, 0x14, 0x9d, &template), apdu::SW_WRONG_DATA);
+        assert_eq!(ecdh(&mut card, ALG_ECC_P256, 0x9d, &template[..20]), apdu::SW_WRONG_DATA);
+        assert_eq!(ecdh(&mut card, ALG_ECC_P256, 0x9d, &template), apdu::SW_SUCCESS);
+    }
+
+    #[test]
+    fn test_invalid_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("card.toml");
+
+        assert!(SoftCard::open(&path).is_ok());
+
+        std::fs::write(&path, "[keys]\n9d = \"00\"\n").unwrap();
+        let err = SoftCard::open(&path).unwrap_err();
+        assert!(err.to_string().contains("slot 9d has no valid P-256 key"), "{err}");
+
+        std::fs::write(&path, "[keys]\n9b = \"00\"\n").unwrap();
+        assert!(matches!(SoftCard::open(&path), Err(YkvcError::FileError(_))));
+    }
+}
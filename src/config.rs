@@ -0,0 +1,433 @@
+//! Configuration file
+//!
+//! Settings are read from `ykvc/config.toml` in the user's configuration
+//! directory (`~/.config/ykvc/config.toml` on Linux), or from the file named by
+//! `YKVC_CONFIG`. A missing file leaves every setting at its default, and
+//! command-line options override the file:
+//!
+//! ```toml
+//! [timeouts]
+//! tool = 30       # seconds a device tool or dependency check may run
+//! install = 1800  # seconds a package installation may run
+//! ```
+//!
+//! Named profiles hold per-volume settings, such as the label mixed into
+//! every challenge and the Argon2id costs the phrase is stretched with, and
+//! are picked with `--profile`:
+//!
+//! ```toml
+//! [profiles.backup]
+//! label = "backup-2024"
+//!
+//! [profiles.backup.argon2]
+//! salt = "<16 random bytes in hex>"
+//! memory_kib = 65536
+//! time_cost = 3
+//! ```
+//!
+//! `ykvc fido2 enroll` records its credential in a `[fido2]` table,
+//! `ykvc piv enroll` its slot and point in a `[piv]` table, and `ykvc profile`
+//! edits the `[profiles]` table. Writing them keeps the other settings but
+//! drops comments from the file.
+
+use crate::backend::piv::PivSlot;
+use crate::derivation;
+use crate::error::{Result, YkvcError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Environment variable naming the configuration file
+pub const CONFIG_ENV: &str = "YKVC_CONFIG";
+
+/// Contents of the configuration file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Limits on external tool runs
+    pub timeouts: TimeoutSettings,
+    /// Credential of the fido2 backend, once enrolled
+    pub fido2: Option<Fido2Credential>,
+    /// Key of the piv backend, once enrolled
+    pub piv: Option<PivEnrollment>,
+    /// Per-volume settings, by profile name
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Timeouts in seconds, unset ones keep their defaults
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutSettings {
+    /// Device tools, dependency checks and secure deletion
+    pub tool: Option<u64>,
+    /// Package manager runs installing dependencies
+    pub install: Option<u64>,
+}
+
+/// FIDO2 credential whose `hmac-secret` derives the keyfiles
+///
+/// Without the credential ID and salt the keyfiles cannot be derived again,
+/// so this table has to be backed up along with the container.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fido2Credential {
+    /// Credential ID returned by the authenticator, in hex
+    #[serde(with = "hex_bytes")]
+    pub credential_id: Vec<u8>,
+    /// Random salt mixed into every challenge, in hex
+    #[serde(with = "hex_bytes")]
+    pub salt: Vec<u8>,
+    /// Relying party ID the credential is scoped to
+    pub rp_id: String,
+    /// Whether the credential is stored on the authenticator
+    #[serde(default)]
+    pub resident: bool,
+}
+
+/// PIV key and point whose ECDH derives the keyfiles
+///
+/// The private key stays on the card, but without the point the keyfiles
+/// cannot be derived again, so this table has to be backed up as well.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PivEnrollment {
+    /// Slot holding the P-256 private key
+    pub slot: PivSlot,
+    /// Uncompressed P-256 point the card multiplies with its key, in hex
+    #[serde(with = "hex_bytes")]
+    pub point: Vec<u8>,
+}
+
+/// Settings of one volume
+///
+/// Keyfiles derived with a profile depend on its settings, so they have to be
+/// backed up along with the container.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Volume label mixed into every challenge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Argon2id stretching of the phrase, combined with every response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub argon2: Option<Argon2Settings>,
+}
+
+/// Argon2id salt and costs a profile stretches the challenge phrase with
+///
+/// Argon2id always runs with one lane and 32 bytes of output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Argon2Settings {
+    /// Random salt of the profile, in hex
+    #[serde(with = "hex_bytes")]
+    pub salt: Vec<u8>,
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+    /// Number of passes over the memory
+    pub time_cost: u32,
+}
+
+/// Byte strings kept as hex in the file
+pub mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    /// Writes the bytes as a hex string
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    /// Reads bytes from a hex string
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        hex::decode(String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Config {
+    /// Reads the configuration file, if there is one
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or is invalid
+    pub fn load() -> Result<Self> {
+        path().map_or_else(|| Ok(Self::default()), |path| Self::from_file(&path))
+    }
+
+    /// Reads the configuration from `path`, which may be missing
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or is invalid
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let invalid =
+            |message: String| YkvcError::Config { path: path.display().to_string(), message };
+
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(invalid(e.to_string())),
+        };
+
+        let config: Self = toml::from_str(&text).map_err(|e| invalid(e.message().to_string()))?;
+        if config.timeouts.tool == Some(0) || config.timeouts.install == Some(0) {
+            return Err(invalid("timeouts must be at least 1 second".to_string()));
+        }
+        if config.fido2.as_ref().is_some_and(|c| c.credential_id.is_empty() || c.salt.len() != 32) {
+            return Err(invalid("fido2 needs a credential_id and a 32-byte salt".to_string()));
+        }
+        if config.piv.as_ref().is_some_and(|p| p256::PublicKey::from_sec1_bytes(&p.point).is_err())
+        {
+            return Err(invalid("piv point is not a P-256 point".to_string()));
+        }
+        if config.profiles.iter().any(|(name, p)| name.is_empty() || p.label.as_deref() == Some(""))
+        {
+            return Err(invalid("profile names and labels must not be empty".to_string()));
+        }
+        for (name, profile) in &config.profiles {
+            if let Some(settings) = &profile.argon2 {
+                derivation::check_argon2(settings)
+                    .map_err(|e| invalid(format!("profile '{name}': {e}")))?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Looks up the profile `name`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no such profile
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles.get(name).ok_or_else(|| YkvcError::ProfileNotFound(name.to_string()))
+    }
+}
+
+/// Records an enrolled FIDO2 credential, replacing any previous one
+///
+/// Returns the path of the configuration file.
+///
+/// # Errors
+///
+/// Returns an error if no configuration file location can be determined, or
+/// the file is invalid or cannot be written
+pub fn save_fido2_credential(credential: &Fido2Credential) -> Result<PathBuf> {
+    save_table("fido2", credential)
+}
+
+/// Records an enrolled PIV key, replacing any previous one
+///
+/// Returns the path of the configuration file.
+///
+/// # Errors
+///
+/// Returns an error if no configuration file location can be determined, or
+/// the file is invalid or cannot be written
+pub fn save_piv_enrollment(enrollment: &PivEnrollment) -> Result<PathBuf> {
+    save_table("piv", enrollment)
+}
+
+/// Replaces all profiles
+///
+/// Returns the path of the configuration file.
+///
+/// # Errors
+///
+/// Returns an error if no configuration file location can be determined, or
+/// the file is invalid or cannot be written
+pub fn save_profiles(profiles: &BTreeMap<String, Profile>) -> Result<PathBuf> {
+    save_table("profiles", profiles)
+}
+
+/// Sets the table `name` of the configuration file and returns its path
+fn save_table(name: &str, value: &impl Serialize) -> Result<PathBuf> {
+    let path = path().ok_or_else(|| {
+        YkvcError::Other(format!("Cannot locate the configuration directory, set {CONFIG_ENV}"))
+    })?;
+    write_table(&path, name, value)?;
+    Ok(path)
+}
+
+/// Sets the table `name` of the configuration file at `path`
+fn write_table(path: &Path, name: &str, value: &impl Serialize) -> Result<()> {
+    let invalid = |message: String| YkvcError::Config { path: path.display().to_string(), message };
+
+    let mut table = match fs::read_to_string(path) {
+        Ok(text) => text.parse::<toml::Table>().map_err(|e| invalid(e.message().to_string()))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => toml::Table::new(),
+        Err(e) => return Err(invalid(e.to_string())),
+    };
+    let value = toml::Value::try_from(value).map_err(|e| invalid(e.to_string()))?;
+    table.insert(name.to_string(), value);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| {
+            YkvcError::FileError(format!("Failed to create {}: {e}", parent.display()))
+        })?;
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| YkvcError::FileError(format!("Failed to open {}: {e}", path.display())))?;
+    file.write_all(table.to_string().as_bytes())
+        .map_err(|e| YkvcError::FileError(format!("Failed to write {}: {e}", path.display())))
+}
+
+/// Location of the configuration file, if one can be determined
+fn path() -> Option<PathBuf> {
+    std::env::var_os(CONFIG_ENV)
+        .map(PathBuf::from)
+        .or_else(|| dirs::config_dir().map(|dir| dir.join("ykvc").join("config.toml")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_file_is_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::from_file(&dir.path().join("config.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        std::fs::write(&path, "[timeouts]\ntool = 5\n").unwrap();
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.timeouts, TimeoutSettings { tool: Some(5), install: None });
+
+        std::fs::write(&path, "[timeouts]\ninstall = 0\n").unwrap();
+        let err = Config::from_file(&path).unwrap_err();
+        assert!(err.to_string().contains("at least 1 second"), "{err}");
+    }
+
+    #[test]
+    fn test_fido2_credential() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let salt = "ab".repeat(32);
+
+        std::fs::write(
+            &path,
+            format!("[fido2]\ncredential_id = \"0102\"\nsalt = \"{salt}\"\nrp_id = \"ykvc\"\n"),
+        )
+        .unwrap();
+        let credential = Config::from_file(&path).unwrap().fido2.unwrap();
+        assert_eq!(credential.credential_id, [1, 2]);
+        assert_eq!(credential.salt, [0xab; 32]);
+        assert!(!credential.resident);
+
+        std::fs::write(
+            &path,
+            "[fido2]\ncredential_id = \"0102\"\nsalt = \"abab\"\nrp_id = \"ykvc\"\n",
+        )
+        .unwrap();
+        assert!(Config::from_file(&path).unwrap_err().to_string().contains("32-byte salt"));
+
+        std::fs::write(
+            &path,
+            format!("[fido2]\ncredential_id = \"xyz\"\nsalt = \"{salt}\"\nrp_id = \"ykvc\"\n"),
+        )
+        .unwrap();
+        assert!(matches!(Config::from_file(&path), Err(YkvcError::Config { .. })));
+    }
+
+    #[test]
+    fn test_piv_enrollment() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let generator = "046b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296\
+                         4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5";
+
+        std::fs::write(&path, format!("[piv]\nslot = \"9d\"\npoint = \"{generator}\"\n")).unwrap();
+        let enrollment = Config::from_file(&path).unwrap().piv.unwrap();
+        assert_eq!(enrollment.slot, PivSlot::KEY_MANAGEMENT);
+        assert_eq!(hex::encode(&enrollment.point), generator);
+
+        write_table(&path, "piv", &enrollment).unwrap();
+        assert_eq!(Config::from_file(&path).unwrap().piv, Some(enrollment));
+
+        std::fs::write(&path, format!("[piv]\nslot = \"9b\"\npoint = \"{generator}\"\n")).unwrap();
+        assert!(matches!(Config::from_file(&path), Err(YkvcError::Config { .. })));
+
+        std::fs::write(&path, "[piv]\nslot = \"9d\"\npoint = \"0400\"\n").unwrap();
+        assert!(Config::from_file(&path).unwrap_err().to_string().contains("not a P-256 point"));
+    }
+
+    #[test]
+    fn test_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        std::fs::write(&path, "[profiles.backup]\nlabel = \"backup-2024\"\n").unwrap();
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.profile("backup").unwrap().label.as_deref(), Some("backup-2024"));
+        assert!(matches!(config.profile("other"), Err(YkvcError::ProfileNotFound(_))));
+
+        let mut profiles = config.profiles;
+        profiles.insert("empty".to_string(), Profile::default());
+        write_table(&path, "profiles", &profiles).unwrap();
+        assert_eq!(Config::from_file(&path).unwrap().profiles, profiles);
+
+        std::fs::write(&path, "[profiles.backup]\nlabel = \"\"\n").unwrap();
+        assert!(Config::from_file(&path).unwrap_err().to_string().contains("must not be empty"));
+
+        let argon2 = Argon2Settings { salt: vec![7; 16], memory_kib: 64, time_cost: 1 };
+        profiles.insert("stretched".to_string(), Profile { label: None, argon2: Some(argon2) });
+        write_table(&path, "profiles", &profiles).unwrap();
+        assert_eq!(Config::from_file(&path).unwrap().profiles, profiles);
+
+        std::fs::write(
+            &path,
+            "[profiles.backup.argon2]\nsalt = \"0707070707070707\"\nmemory_kib = 1\ntime_cost = 1\n",
+        )
+        .unwrap();
+        let err = Config::from_file(&path).unwrap_err().to_string();
+        assert!(err.contains("profile 'backup'"), "{err}");
+    }
+
+    #[test]
+    fn test_write_fido2_credential_keeps_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("config.toml");
+        let credential = Fido2Credential {
+            credential_id: vec![1, 2, 3],
+            salt: vec![7; 32],
+            rp_id: "ykvc".to_string(),
+            resident: true,
+        };
+
+        write_table(&path, "fido2", &credential).unwrap();
+        assert_eq!(Config::from_file(&path).unwrap().fido2.as_ref(), Some(&credential));
+
+        std::fs::write(&path, "[timeouts]\ntool = 5\n").unwrap();
+        write_table(&path, "fido2", &credential).unwrap();
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.timeouts.tool, Some(5));
+        assert_eq!(config.fido2, Some(credential.clone()));
+
+        std::fs::write(&path, "not toml [").unwrap();
+        assert!(write_table(&path, "fido2", &credential).is_err());
+    }
+
+    #[test]
+    fn test_rejects_unknown_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        std::fs::write(&path, "[timeouts]\ntoool = 5\n").unwrap();
+        let err = Config::from_file(&path).unwrap_err();
+        assert!(matches!(err, YkvcError::Config { .. }));
+        assert!(err.to_string().contains("toool"), "{err}");
+    }
+}
@@ -0,0 +1,124 @@
+//! Software HMAC
+//!
+//! Computes the same value a `YubiKey` slot programmed for HMAC-SHA1
+//! challenge-response returns, without a device. Used by the emulator backend
+//! and to verify freshly programmed slots. HMAC-SHA256 serves the FIDO2
+//! `hmac-secret` extension.
+
+use crate::backend::otp;
+use crate::error::{Result, YkvcError};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::Sha256;
+
+/// Computes HMAC-SHA1 of `message` under `key`
+#[must_use]
+pub fn hmac_sha1(key: &[u8], message: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length, so this cannot fail
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Computes HMAC-SHA256 of `message` under `key`
+#[must_use]
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+/// Computes the answer of an HMAC-SHA1 slot holding `secret` to `challenge`
+///
+/// Models the device end to end: the challenge is padded to the 64-byte slot
+/// payload exactly as it is sent over the wire. Unless the slot is `fixed_64`,
+/// the `HMAC_LT64` padding is stripped again before the MAC is computed.
+///
+/// # Errors
+///
+/// Returns an error if the secret is not 20 bytes or the challenge does not
+/// fit the slot payload
+pub fn slot_response(secret: &[u8], challenge: &[u8], fixed_64: bool) -> Result<Vec<u8>> {
+    if secret.len() != otp::HMAC_RESPONSE_SIZE {
+        return Err(YkvcError::InvalidSecretLength(secret.len()));
+    }
+
+    let payload = otp::pad_hmac_challenge(challenge)?;
+    let message = if fixed_64 { &payload[..] } else { otp::strip_hmac_padding(&payload) };
+    Ok(hmac_sha1(secret, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_sha1_rfc2202() {
+        // RFC 2202, test cases 1, 2 and 6
+        assert_eq!(
+            hex::encode(hmac_sha1(&[0x0b; 20], b"Hi There")),
+            "b617318655057264e28bc0b6fb378c8ef146be00"
+        );
+        assert_eq!(
+            hex::encode(hmac_sha1(b"Jefe", b"what do ya want for nothing?")),
+            "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"
+        );
+        assert_eq!(
+            hex::encode(hmac_sha1(
+                &[0xaa; 80],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "aa4ae5e15272d00e95705637ce8a3b55ed402112"
+        );
+    }
+
+    #[test]
+    fn test_hmac_sha256_rfc4231() {
+        // RFC 4231, test cases 1 and 2
+        assert_eq!(
+            hex::encode(hmac_sha256(&[0x0b; 20], b"Hi There")),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_slot_response_matches_plain_hmac() {
+        let secret = [0x0b; 20];
+        assert_eq!(
+            slot_response(&secret, b"Hi There", false).unwrap(),
+            hmac_sha1(&secret, b"Hi There")
+        );
+        assert_eq!(slot_response(&secret, b"", false).unwrap(), hmac_sha1(&secret, b""));
+    }
+
+    #[test]
+    fn test_slot_response_fixed_64() {
+        let secret = [0x0b; 20];
+        let block = otp::pad_hmac_challenge(b"Hi There").unwrap();
+
+        assert_eq!(slot_response(&secret, b"Hi There", true).unwrap(), hmac_sha1(&secret, &block));
+        assert_eq!(slot_response(&secret, &block, true).unwrap(), hmac_sha1(&secret, &block));
+    }
+
+    #[test]
+    fn test_slot_response_keeps_trailing_zero() {
+        let secret = [0x0b; 20];
+        assert_eq!(slot_response(&secret, b"ab\0", false).unwrap(), hmac_sha1(&secret, b"ab\0"));
+    }
+
+    #[test]
+    fn test_slot_response_rejects_bad_input() {
+        assert!(matches!(
+            slot_response(&[0u8; 16], b"x", false),
+            Err(YkvcError::InvalidSecretLength(16))
+        ));
+        assert!(matches!(
+            slot_response(&[0u8; 20], &[b'x'; 65], false),
+            Err(YkvcError::InvalidChallenge(_))
+        ));
+    }
+}
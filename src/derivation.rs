@@ -0,0 +1,344 @@
+//! Keyfile derivation schemes
+//!
+//! A scheme turns the token response into the bytes written to the keyfile.
+//! Schemes are named and versioned, and each is frozen once released: the
+//! same response always gives the same keyfile under the same scheme and
+//! size, and the test vectors below pin that down. Changes go into a new
+//! scheme instead.
+//!
+//! - `v1-raw` writes the response as it is: 20 bytes from HMAC-SHA1 slots,
+//!   32 bytes from the `fido2` and `piv` backends.
+//! - `v2-hkdf-sha256` expands the response with HKDF-SHA256 (RFC 5869) to any
+//!   size up to 1 MiB, 64 bytes unless another size is chosen. The response
+//!   is extracted with the salt `ykvc-v2-hkdf-sha256`, then expanded in
+//!   blocks of up to 8160 bytes, the most one HKDF expansion yields. Block
+//!   `i` uses the info `ykvc keyfile` followed by the keyfile size and `i`,
+//!   both as 32-bit big-endian numbers, so keyfiles of different sizes are
+//!   unrelated.
+//!
+//! Profiles may also stretch the challenge phrase with Argon2id (version
+//! 0x13, one lane, 32 bytes of output) under their own salt and costs. The
+//! response is then replaced by its HMAC-SHA256 under the stretched phrase
+//! before the scheme runs, so even with the slot secret every guess at the
+//! phrase costs a full Argon2id run.
+//!
+//! Combined keyfiles need the responses of two tokens. [`combine`] extracts
+//! them with HKDF-SHA256 under the salt `ykvc-2of2-hkdf-sha256`, each prefixed
+//! with its length as a 32-bit big-endian number, and expands 32 bytes with
+//! the info `ykvc combined response`. The result stands in for the response
+//! of a single token, so stretching and the scheme apply as usual.
+
+use crate::config::Argon2Settings;
+use crate::crypto;
+use crate::error::{Result, YkvcError};
+use argon2::{Algorithm, Argon2, Params, Version};
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+use std::fmt;
+
+/// Largest keyfile `VeraCrypt` reads in full, 1 MiB
+pub const MAX_KEYFILE_SIZE: usize = 1024 * 1024;
+
+/// Keyfile size of `v2-hkdf-sha256` unless another is chosen
+pub const DEFAULT_HKDF_SIZE: usize = 64;
+
+/// Salt of the HKDF extraction in `v2-hkdf-sha256`
+const HKDF_SALT: &[u8] = b"ykvc-v2-hkdf-sha256";
+
+/// Prefix of the HKDF info in `v2-hkdf-sha256`
+const HKDF_INFO: &[u8] = b"ykvc keyfile";
+
+/// Most bytes one HKDF-SHA256 expansion yields, 255 hash lengths
+const HKDF_BLOCK_SIZE: usize = 255 * 32;
+
+/// Argon2id memory cost of new profiles in KiB, 64 MiB
+pub const DEFAULT_ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+
+/// Argon2id passes of new profiles
+pub const DEFAULT_ARGON2_TIME_COST: u32 = 3;
+
+/// Size of the stretched phrase
+const STRETCHED_SIZE: usize = 32;
+
+/// Salt of the HKDF extraction in [`combine`]
+const COMBINE_SALT: &[u8] = b"ykvc-2of2-hkdf-sha256";
+
+/// HKDF info of [`combine`]
+const COMBINE_INFO: &[u8] = b"ykvc combined response";
+
+/// Size of the response [`combine`] makes of two
+const COMBINED_SIZE: usize = 32;
+
+/// Named, versioned way of turning a response into keyfile bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Scheme {
+    /// The response as it is
+    #[default]
+    #[value(name = "v1-raw")]
+    V1Raw,
+    /// The response expanded with HKDF-SHA256 to the chosen size
+    #[value(name = "v2-hkdf-sha256")]
+    V2HkdfSha256,
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::V1Raw => "v1-raw",
+            Self::V2HkdfSha256 => "v2-hkdf-sha256",
+        })
+    }
+}
+
+/// Scheme and keyfile size a keyfile is derived with, and the stretched
+/// phrase combined with the response, if any
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Derivation {
+    scheme: Scheme,
+    size: Option<usize>,
+    stretched: Option<[u8; STRETCHED_SIZE]>,
+}
+
+impl Derivation {
+    /// Checks that `scheme` can write keyfiles of `size` bytes
+    ///
+    /// `None` leaves the size to the scheme.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the size is given for `v1-raw`, which writes the
+    /// response as it is, or is not between 1 byte and [`MAX_KEYFILE_SIZE`]
+    pub fn new(scheme: Scheme, size: Option<usize>) -> Result<Self> {
+        match (scheme, size) {
+            (Scheme::V1Raw, Some(_)) => Err(YkvcError::InvalidKeyfileSize(
+                "v1-raw writes the response as it is, choose --scheme v2-hkdf-sha256 to set a size"
+                    .to_string(),
+            )),
+            (_, Some(size)) if !(1..=MAX_KEYFILE_SIZE).contains(&size) => {
+                Err(YkvcError::InvalidKeyfileSize(format!(
+                    "{size} bytes, must be between 1 and {MAX_KEYFILE_SIZE}"
+                )))
+            }
+            _ => Ok(Self { scheme, size, stretched: None }),
+        }
+    }
+
+    /// Scheme the keyfile is derived with
+    #[must_use]
+    pub const fn scheme(self) -> Scheme {
+        self.scheme
+    }
+
+    /// Combines every response with a phrase stretched by [`stretch_phrase`]
+    #[must_use]
+    pub const fn with_stretched_phrase(self, stretched: [u8; STRETCHED_SIZE]) -> Self {
+        Self { stretched: Some(stretched), ..self }
+    }
+
+    /// Derives the keyfile bytes from a token response
+    #[must_use]
+    pub fn keyfile(self, response: &[u8]) -> Vec<u8> {
+        let response = self.stretched.map_or_else(
+            || response.to_vec(),
+            |stretched| crypto::hmac_sha256(&stretched, response).to_vec(),
+        );
+        match self.scheme {
+            Scheme::V1Raw => response,
+            Scheme::V2HkdfSha256 => hkdf_sha256(&response, self.size.unwrap_or(DEFAULT_HKDF_SIZE)),
+        }
+    }
+}
+
+/// Creates Argon2id settings with the given costs and a fresh random salt
+///
+/// # Errors
+///
+/// Returns an error if Argon2id does not accept the costs
+pub fn new_argon2(memory_kib: u32, time_cost: u32) -> Result<Argon2Settings> {
+    let mut salt = vec![0; argon2::RECOMMENDED_SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let settings = Argon2Settings { salt, memory_kib, time_cost };
+    check_argon2(&settings)?;
+    Ok(settings)
+}
+
+/// Checks that Argon2id accepts the salt and costs
+///
+/// # Errors
+///
+/// Returns an error if the salt is shorter than 8 bytes or a cost is out of range
+pub fn check_argon2(settings: &Argon2Settings) -> Result<()> {
+    argon2(settings).map(|_| ())
+}
+
+/// Argon2id instance for the settings
+fn argon2(settings: &Argon2Settings) -> Result<Argon2<'static>> {
+    if settings.salt.len() < argon2::MIN_SALT_LEN {
+        return Err(YkvcError::InvalidArgon2(format!(
+            "the salt must be at least {} bytes",
+            argon2::MIN_SALT_LEN
+        )));
+    }
+    let params = Params::new(settings.memory_kib, settings.time_cost, 1, Some(STRETCHED_SIZE))
+        .map_err(|e| YkvcError::InvalidArgon2(e.to_string()))?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+/// Stretches the challenge phrase with Argon2id
+///
+/// # Errors
+///
+/// Returns an error if the settings are invalid or the memory cannot be allocated
+pub fn stretch_phrase(settings: &Argon2Settings, phrase: &str) -> Result<[u8; STRETCHED_SIZE]> {
+    let mut stretched = [0; STRETCHED_SIZE];
+    argon2(settings)?
+        .hash_password_into(phrase.as_bytes(), &settings.salt, &mut stretched)
+        .map_err(|e| YkvcError::InvalidArgon2(e.to_string()))?;
+    Ok(stretched)
+}
+
+/// Binds the responses of two tokens into one
+///
+/// The order matters: swapping the tokens gives another response.
+#[must_use]
+pub fn combine(first: &[u8], second: &[u8]) -> [u8; COMBINED_SIZE] {
+    let mut input = Vec::with_capacity(8 + first.len() + second.len());
+    for response in [first, second] {
+        // Responses are at most 32 bytes
+        let length = u32::try_from(response.len()).expect("response size fits in 32 bits");
+        input.extend_from_slice(&length.to_be_bytes());
+        input.extend_from_slice(response);
+    }
+
+    let mut combined = [0; COMBINED_SIZE];
+    Hkdf::<Sha256>::new(Some(COMBINE_SALT), &input)
+        .expand(COMBINE_INFO, &mut combined)
+        .expect("32 bytes are within the HKDF output limit");
+    combined
+}
+
+/// Expands `response` to `size` bytes as `v2-hkdf-sha256` does
+fn hkdf_sha256(response: &[u8], size: usize) -> Vec<u8> {
+    let hkdf = Hkdf::<Sha256>::new(Some(HKDF_SALT), response);
+    // Sizes are capped at 1 MiB, so they and the block numbers fit in 32 bits
+    let size_field = u32::try_from(size).expect("keyfile size fits in 32 bits").to_be_bytes();
+
+    let mut keyfile = vec![0; size];
+    for (index, block) in keyfile.chunks_mut(HKDF_BLOCK_SIZE).enumerate() {
+        let index = u32::try_from(index).expect("block number fits in 32 bits").to_be_bytes();
+        hkdf.expand_multi_info(&[HKDF_INFO, &size_field, &index], block)
+            .expect("blocks are within the HKDF output limit");
+    }
+    keyfile
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// HMAC-SHA1 response of an RFC 2202 key (`0b` x 20) to "Hi There"
+    const RESPONSE: &str = "b617318655057264e28bc0b6fb378c8ef146be00";
+
+    fn keyfile(scheme: Scheme, size: Option<usize>, response: &str) -> Vec<u8> {
+        Derivation::new(scheme, size).unwrap().keyfile(&hex::decode(response).unwrap())
+    }
+
+    #[test]
+    fn test_scheme_names() {
+        assert_eq!(Scheme::default(), Scheme::V1Raw);
+        assert_eq!(Scheme::V1Raw.to_string(), "v1-raw");
+        assert_eq!(Scheme::V2HkdfSha256.to_string(), "v2-hkdf-sha256");
+    }
+
+    #[test]
+    fn test_size_validation() {
+        assert!(Derivation::new(Scheme::V1Raw, None).is_ok());
+        assert!(matches!(
+            Derivation::new(Scheme::V1Raw, Some(20)),
+            Err(YkvcError::InvalidKeyfileSize(_))
+        ));
+        assert!(Derivation::new(Scheme::V2HkdfSha256, Some(0)).is_err());
+        assert!(Derivation::new(Scheme::V2HkdfSha256, Some(MAX_KEYFILE_SIZE + 1)).is_err());
+        assert!(Derivation::new(Scheme::V2HkdfSha256, Some(MAX_KEYFILE_SIZE)).is_ok());
+    }
+
+    #[test]
+    fn test_v1_raw_vector() {
+        assert_eq!(hex::encode(keyfile(Scheme::V1Raw, None, RESPONSE)), RESPONSE);
+    }
+
+    #[test]
+    fn test_v2_hkdf_sha256_vectors() {
+        assert_eq!(
+            hex::encode(keyfile(Scheme::V2HkdfSha256, None, RESPONSE)),
+            "42707f175b05e8ef92e3559551528ab17e71f86f0dbd618adde78904f81ebce6b4159c24fa4ab60a74c9e5044f9fc9407df9ca3ead8ddc496adb565e38aa3ba1"
+        );
+        assert_eq!(
+            hex::encode(keyfile(Scheme::V2HkdfSha256, Some(32), RESPONSE)),
+            "b72a41a020ffeeaa410f7de2b6361817c62745283c89e18057dda82f87439742"
+        );
+    }
+
+    #[test]
+    fn test_v2_hkdf_sha256_large() {
+        // Crosses the 8160-byte block boundary
+        let large = keyfile(Scheme::V2HkdfSha256, Some(10_000), RESPONSE);
+        assert_eq!(large.len(), 10_000);
+        assert_eq!(hex::encode(&large[8128..8192]), "b519000f0e7257098b1e46fa8ce3ae9c74963cb7ac105d353c1c24449f710d7a19b165062845cf179311e1430da190379bc02d05be98fde1d2395f5a2057c824");
+        assert_eq!(
+            keyfile(Scheme::V2HkdfSha256, Some(MAX_KEYFILE_SIZE), RESPONSE).len(),
+            MAX_KEYFILE_SIZE
+        );
+    }
+
+    fn argon2_settings(memory_kib: u32, time_cost: u32) -> Argon2Settings {
+        Argon2Settings { salt: b"ykvc-test-salt!!".to_vec(), memory_kib, time_cost }
+    }
+
+    #[test]
+    fn test_argon2_validation() {
+        assert!(check_argon2(&argon2_settings(64, 1)).is_ok());
+        assert!(matches!(check_argon2(&argon2_settings(4, 1)), Err(YkvcError::InvalidArgon2(_))));
+        assert!(check_argon2(&argon2_settings(64, 0)).is_err());
+        let short_salt = Argon2Settings { salt: vec![0; 7], ..argon2_settings(64, 1) };
+        assert!(check_argon2(&short_salt).is_err());
+
+        let settings = new_argon2(64, 1).unwrap();
+        assert_eq!(settings.salt.len(), argon2::RECOMMENDED_SALT_LEN);
+        assert_ne!(settings.salt, new_argon2(64, 1).unwrap().salt);
+        assert!(new_argon2(0, 1).is_err());
+    }
+
+    #[test]
+    fn test_stretched_phrase_vector() {
+        let stretched = stretch_phrase(&argon2_settings(64, 1), "correct horse").unwrap();
+        assert_eq!(
+            hex::encode(stretched),
+            "9d24e40091cf82429a58ae46fccb0c4bbf2d8b9666e7c069fd390394a4648aa5"
+        );
+        assert_ne!(stretch_phrase(&argon2_settings(64, 2), "correct horse").unwrap(), stretched);
+        assert_ne!(stretch_phrase(&argon2_settings(64, 1), "correct horsf").unwrap(), stretched);
+
+        let response = hex::decode(RESPONSE).unwrap();
+        let derivation = Derivation::new(Scheme::V1Raw, None).unwrap();
+        let combined = derivation.with_stretched_phrase(stretched).keyfile(&response);
+        assert_eq!(combined, crypto::hmac_sha256(&stretched, &response).to_vec());
+    }
+
+    #[test]
+    fn test_combine_vector() {
+        let first = hex::decode(RESPONSE).unwrap();
+        // HMAC-SHA1 response of the key `0c` x 20 to "Hi There"
+        let second = hex::decode("c9ac7b70d8c7c3a619e8d09f03405913d425319e").unwrap();
+
+        assert_eq!(
+            hex::encode(combine(&first, &second)),
+            "399d1b6eb2bef7e12dca468694fd8bd69426bc21a3b9c87e3b82e5df443e1c64"
+        );
+        assert_eq!(
+            hex::encode(combine(&second, &first)),
+            "8f8fa6ec8d860f6e6ecb9dc963982f8b81ef1f7e91036f9764ad555812b9028b"
+        );
+    }
+}
@@ -1,10 +1,15 @@
 //! Custom error types for YKVC
 
+use crate::backend::piv::PivSlot;
+use crate::yubikey::{Feature, FirmwareVersion, Slot, SlotConfig};
 use thiserror::Error;
 
 /// Result type alias for YKVC operations
 pub type Result<T> = std::result::Result<T, YkvcError>;
 
+/// Exit code when `--wait` runs out before a `YubiKey` is connected
+pub const EXIT_WAIT_TIMEOUT: i32 = 3;
+
 /// Main error type for YKVC operations
 #[derive(Error, Debug)]
 #[allow(dead_code)] // Phase 1: Will be used in later phases
@@ -13,9 +18,63 @@ pub enum YkvcError {
     #[error("YubiKey not found. Please connect your YubiKey device.")]
     YubiKeyNotFound,
 
-    /// `YubiKey` slot 2 is not programmed with HMAC-SHA1
-    #[error("Slot 2 is not programmed. Run 'ykvc slot2 program' first.")]
-    Slot2NotProgrammed,
+    /// No connected `YubiKey` has the requested serial number
+    #[error("No YubiKey with serial number {0} is connected")]
+    SerialNotFound(u32),
+
+    /// Several `YubiKey` devices are connected and none was selected
+    #[error("Multiple YubiKeys connected. Select one with --serial (see 'ykvc list').")]
+    MultipleDevices,
+
+    /// `YubiKey` slot is not programmed with HMAC-SHA1
+    #[error("Slot {0} is not programmed. Run 'ykvc slot {0} program' first.")]
+    SlotNotProgrammed(Slot),
+
+    /// `YubiKey` slot holds a configuration of another type than HMAC-SHA1
+    #[error(
+        "Slot {0} is not set up for HMAC-SHA1 challenge-response (found: {1}). Run 'ykvc slot {0} program' to replace it."
+    )]
+    SlotNotChallengeResponse(Slot, SlotConfig),
+
+    /// The `YubiKey` was not touched in time for a touch-triggered challenge
+    #[error("Timed out after {0} seconds waiting for the YubiKey to be touched")]
+    TouchTimeout(u64),
+
+    /// An external tool did not finish in time and was killed
+    #[error("'{tool}' did not finish within {seconds} seconds and was stopped")]
+    ToolTimeout {
+        /// The tool that was killed
+        tool: String,
+        /// Timeout it ran into
+        seconds: u64,
+    },
+
+    /// No matching `YubiKey` was connected before `--wait` ran out
+    #[error("Timed out after {0} seconds waiting for a YubiKey to be connected")]
+    WaitTimeout(u64),
+
+    /// The firmware of the `YubiKey` cannot do what was asked for
+    #[error("{feature} needs firmware {} or later, this YubiKey runs {firmware}", .feature.min_firmware())]
+    FirmwareTooOld {
+        /// The missing feature
+        feature: Feature,
+        /// Firmware of the device
+        firmware: FirmwareVersion,
+    },
+
+    /// A slot write was refused because of the slot's access code
+    #[error("Slot {0} is protected by an access code, and the code was not given or is wrong")]
+    AccessCodeRejected(Slot),
+
+    /// A freshly programmed slot does not answer with the programmed secret
+    #[error(
+        "Slot {0} failed verification: it does not answer with the programmed secret. Do not use it for keyfiles and program it again."
+    )]
+    VerificationFailed(Slot),
+
+    /// Access code is malformed
+    #[error("Invalid access code: {0}")]
+    InvalidAccessCode(String),
 
     /// Required system dependency is missing
     #[error("Required dependency '{0}' is not installed")]
@@ -54,6 +113,99 @@ pub enum YkvcError {
     #[error("ykchalresp command failed: {0}")]
     YkchalrespFailed(String),
 
+    /// Native HID transport failed
+    #[error("HID communication failed: {0}")]
+    HidFailed(String),
+
+    /// PC/SC transport failed
+    #[error("PC/SC communication failed: {0}")]
+    PcscFailed(String),
+
+    /// Selected backend is not available in this build
+    #[error("Backend not available: {0}")]
+    BackendUnavailable(String),
+
+    /// FIDO2 authenticator answered with a CTAP2 error
+    #[error("FIDO2 authenticator refused the request: {message} (CTAP2 error {code:#04x})")]
+    Fido2Rejected {
+        /// CTAP2 status code
+        code: u8,
+        /// What the status code means
+        message: &'static str,
+    },
+
+    /// FIDO2 authenticator lacks something ykvc needs
+    #[error("FIDO2 authenticator cannot be used: {0}")]
+    Fido2Unsupported(String),
+
+    /// No FIDO2 credential in the configuration file
+    #[error("No FIDO2 credential is enrolled. Run 'ykvc fido2 enroll' first.")]
+    Fido2NotEnrolled,
+
+    /// The PIV applet rejected the PIN
+    #[error("Wrong PIV PIN ({0} attempts left)")]
+    PivPinIncorrect(u8),
+
+    /// The PIV PIN has no attempts left
+    #[error("The PIV PIN is blocked. Unblock it with the PUK: 'ykman piv access unblock-pin'")]
+    PivPinBlocked,
+
+    /// A PIV PIN that cannot be valid
+    #[error("Invalid PIV PIN: {0}")]
+    InvalidPivPin(String),
+
+    /// The PIV slot has no key usable for ECDH
+    #[error(
+        "PIV slot {0} holds no P-256 key. Generate one with 'ykman piv keys generate -a ECCP256 {0} <public-key-file>'."
+    )]
+    PivKeyMissing(PivSlot),
+
+    /// No PIV key in the configuration file
+    #[error("No PIV key is enrolled. Run 'ykvc piv enroll' first.")]
+    PivNotEnrolled,
+
+    /// No profile of that name in the configuration file
+    #[error("Profile '{0}' not found. Create it with 'ykvc profile set {0} --label <LABEL>'.")]
+    ProfileNotFound(String),
+
+    /// Device refused to apply a command
+    #[error("Command rejected by YubiKey")]
+    CommandRejected,
+
+    /// Device answered with malformed or unexpected data
+    #[error("Device protocol error: {0}")]
+    Protocol(String),
+
+    /// Challenge cannot be sent to the device
+    #[error("Invalid challenge: {0}")]
+    InvalidChallenge(String),
+
+    /// Argon2id salt or costs out of range
+    #[error("Invalid Argon2id settings: {0}")]
+    InvalidArgon2(String),
+
+    /// Keyfile size the derivation scheme cannot produce
+    #[error("Invalid keyfile size: {0}")]
+    InvalidKeyfileSize(String),
+
+    /// Keys or options a combined keyfile cannot be generated with
+    #[error("Invalid combined keyfile: {0}")]
+    InvalidCombination(String),
+
+    /// Threshold or enrolled keys a threshold keyfile cannot work with
+    #[error("Invalid threshold: {0}")]
+    InvalidThreshold(String),
+
+    /// Share file that cannot be read or does not rebuild its keyfile
+    #[error("Invalid share file: {0}")]
+    InvalidShareFile(String),
+
+    /// Share that does not decrypt with the response of its key
+    #[error(
+        "The share of YubiKey {0} failed its integrity check. Either the share file was altered or the key answered with another slot or secret."
+    )]
+    ShareRejected(u32),
+
     /// File operation error
     #[error("File operation failed: {0}")]
     FileError(String),
@@ -62,6 +214,15 @@ pub enum YkvcError {
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 
+    /// Configuration file cannot be read or parsed
+    #[error("Invalid configuration file {path}: {message}")]
+    Config {
+        /// Path of the configuration file
+        path: String,
+        /// What is wrong with it
+        message: String,
+    },
+
     /// Unsupported operating system
     #[error("Unsupported operating system: {0}")]
     UnsupportedOS(String),
@@ -75,6 +236,20 @@ pub enum YkvcError {
     Other(String),
 }
 
+impl YkvcError {
+    /// Exit code of the process when it fails with this error
+    ///
+    /// A `--wait` timeout gets its own code so scripts can tell a missing key
+    /// from other failures.
+    #[must_use]
+    pub const fn exit_code(&self) -> i32 {
+        match self {
+            Self::WaitTimeout(_) => EXIT_WAIT_TIMEOUT,
+            _ => 1,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -86,9 +261,90 @@ mod tests {
     }
 
     #[test]
-    fn test_slot2_not_programmed() {
-        let err = YkvcError::Slot2NotProgrammed;
-        assert_eq!(err.to_string(), "Slot 2 is not programmed. Run 'ykvc slot2 program' first.");
+    fn test_serial_not_found() {
+        let err = YkvcError::SerialNotFound(12_345_678);
+        assert_eq!(err.to_string(), "No YubiKey with serial number 12345678 is connected");
+    }
+
+    #[test]
+    fn test_multiple_devices() {
+        let err = YkvcError::MultipleDevices;
+        assert!(err.to_string().contains("--serial"));
+    }
+
+    #[test]
+    fn test_touch_timeout() {
+        let err = YkvcError::TouchTimeout(15);
+        assert_eq!(
+            err.to_string(),
+            "Timed out after 15 seconds waiting for the YubiKey to be touched"
+        );
+    }
+
+    #[test]
+    fn test_tool_timeout() {
+        let err = YkvcError::ToolTimeout { tool: "ykman".to_string(), seconds: 30 };
+        assert_eq!(err.to_string(), "'ykman' did not finish within 30 seconds and was stopped");
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let err = YkvcError::WaitTimeout(30);
+        assert_eq!(
+            err.to_string(),
+            "Timed out after 30 seconds waiting for a YubiKey to be connected"
+        );
+        assert_eq!(err.exit_code(), EXIT_WAIT_TIMEOUT);
+        assert_eq!(YkvcError::YubiKeyNotFound.exit_code(), 1);
+    }
+
+    #[test]
+    fn test_slot_not_programmed() {
+        let err = YkvcError::SlotNotProgrammed(Slot::Two);
+        assert_eq!(err.to_string(), "Slot 2 is not programmed. Run 'ykvc slot 2 program' first.");
+
+        let err = YkvcError::SlotNotProgrammed(Slot::One);
+        assert_eq!(err.to_string(), "Slot 1 is not programmed. Run 'ykvc slot 1 program' first.");
+    }
+
+    #[test]
+    fn test_firmware_too_old() {
+        let err = YkvcError::FirmwareTooOld {
+            feature: Feature::SlotSwap,
+            firmware: FirmwareVersion::new(2, 2, 3),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Swapping slots needs firmware 2.3.0 or later, this YubiKey runs 2.2.3"
+        );
+    }
+
+    #[test]
+    fn test_slot_not_challenge_response() {
+        let err = YkvcError::SlotNotChallengeResponse(Slot::One, SlotConfig::StaticPassword);
+        assert_eq!(
+            err.to_string(),
+            "Slot 1 is not set up for HMAC-SHA1 challenge-response (found: Static password). Run 'ykvc slot 1 program' to replace it."
+        );
+    }
+
+    #[test]
+    fn test_access_code_errors() {
+        let err = YkvcError::AccessCodeRejected(Slot::Two);
+        assert_eq!(
+            err.to_string(),
+            "Slot 2 is protected by an access code, and the code was not given or is wrong"
+        );
+
+        let err = YkvcError::InvalidAccessCode("expected 6 bytes, got 4".to_string());
+        assert_eq!(err.to_string(), "Invalid access code: expected 6 bytes, got 4");
+    }
+
+    #[test]
+    fn test_verification_failed() {
+        let err = YkvcError::VerificationFailed(Slot::Two);
+        assert!(err.to_string().starts_with("Slot 2 failed verification"));
+        assert!(err.to_string().contains("program it again"));
     }
 
     #[test]
@@ -145,6 +401,93 @@ mod tests {
         assert_eq!(err.to_string(), "ykchalresp command failed: device error");
     }
 
+    #[test]
+    fn test_hid_failed() {
+        let err = YkvcError::HidFailed("device busy".to_string());
+        assert_eq!(err.to_string(), "HID communication failed: device busy");
+    }
+
+    #[test]
+    fn test_pcsc_failed() {
+        let err = YkvcError::PcscFailed("no service".to_string());
+        assert_eq!(err.to_string(), "PC/SC communication failed: no service");
+    }
+
+    #[test]
+    fn test_backend_unavailable() {
+        let err = YkvcError::BackendUnavailable("not built".to_string());
+        assert_eq!(err.to_string(), "Backend not available: not built");
+    }
+
+    #[test]
+    fn test_protocol() {
+        let err = YkvcError::Protocol("bad checksum".to_string());
+        assert_eq!(err.to_string(), "Device protocol error: bad checksum");
+    }
+
+    #[test]
+    fn test_fido2_rejected() {
+        let err = YkvcError::Fido2Rejected { code: 0x31, message: "wrong PIN" };
+        assert_eq!(
+            err.to_string(),
+            "FIDO2 authenticator refused the request: wrong PIN (CTAP2 error 0x31)"
+        );
+        assert!(YkvcError::Fido2NotEnrolled.to_string().contains("ykvc fido2 enroll"));
+    }
+
+    #[test]
+    fn test_piv_errors() {
+        assert_eq!(YkvcError::PivPinIncorrect(2).to_string(), "Wrong PIV PIN (2 attempts left)");
+        let err = YkvcError::PivKeyMissing(PivSlot::KEY_MANAGEMENT);
+        assert!(err.to_string().starts_with("PIV slot 9d holds no P-256 key"), "{err}");
+        assert!(YkvcError::PivNotEnrolled.to_string().contains("ykvc piv enroll"));
+    }
+
+    #[test]
+    fn test_profile_not_found() {
+        let err = YkvcError::ProfileNotFound("backup".to_string());
+        assert!(err.to_string().contains("ykvc profile set backup --label"), "{err}");
+    }
+
+    #[test]
+    fn test_command_rejected() {
+        assert_eq!(YkvcError::CommandRejected.to_string(), "Command rejected by YubiKey");
+    }
+
+    #[test]
+    fn test_invalid_challenge() {
+        let err = YkvcError::InvalidChallenge("too long".to_string());
+        assert_eq!(err.to_string(), "Invalid challenge: too long");
+    }
+
+    #[test]
+    fn test_invalid_argon2() {
+        let err = YkvcError::InvalidArgon2("memory cost is too small".to_string());
+        assert_eq!(err.to_string(), "Invalid Argon2id settings: memory cost is too small");
+    }
+
+    #[test]
+    fn test_invalid_combination() {
+        let err = YkvcError::InvalidCombination("the two keys must differ".to_string());
+        assert_eq!(err.to_string(), "Invalid combined keyfile: the two keys must differ");
+    }
+
+    #[test]
+    fn test_threshold_errors() {
+        let err = YkvcError::InvalidThreshold("3 of 2 keys".to_string());
+        assert_eq!(err.to_string(), "Invalid threshold: 3 of 2 keys");
+        let err = YkvcError::InvalidShareFile("unsupported version 2".to_string());
+        assert_eq!(err.to_string(), "Invalid share file: unsupported version 2");
+        let err = YkvcError::ShareRejected(12_345_678);
+        assert!(err.to_string().starts_with("The share of YubiKey 12345678 failed"));
+    }
+
+    #[test]
+    fn test_invalid_keyfile_size() {
+        let err = YkvcError::InvalidKeyfileSize("0 bytes".to_string());
+        assert_eq!(err.to_string(), "Invalid keyfile size: 0 bytes");
+    }
+
     #[test]
     fn test_file_error() {
         let err = YkvcError::FileError("permission denied".to_string());
@@ -3,25 +3,33 @@
 //! This module provides functions for generating cryptographic keyfiles using
 //! `YubiKey` HMAC-SHA1 challenge-response and securely deleting them afterward.
 
+use crate::backend::emulator::EmulatorBackend;
+use crate::backend::TokenBackend;
+use crate::derivation::Derivation;
 use crate::error::{Result, YkvcError};
 use crate::platform;
-use crate::yubikey;
+use crate::yubikey::{self, Slot, DEFAULT_TOUCH_TIMEOUT};
 use colored::Colorize;
 use std::fs::File;
 use std::io::Write;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Generate keyfile from challenge phrase using `YubiKey` HMAC-SHA1 challenge-response
 ///
-/// This function sends the challenge phrase to the `YubiKey` slot 2 and writes
-/// the resulting HMAC-SHA1 response (20 bytes) to a keyfile.
+/// This function sends the encoded challenge to the `YubiKey` slot and writes
+/// the keyfile `derivation` makes of the response. The HMAC-SHA1 response is
+/// 20 bytes, and the `fido2` and `piv` backends answer with 32 bytes instead.
 ///
 /// # Arguments
 ///
-/// * `challenge` - The challenge phrase (password/passphrase) to send to `YubiKey`
+/// * `backend` - The backend used to talk to the `YubiKey`
+/// * `slot` - The HMAC-SHA1 slot answering the challenge
+/// * `challenge` - The challenge bytes (at most 64) encoded from the user's phrase
 /// * `output_path` - Optional path for the keyfile. If `None`, uses `ykvc_keyfile_<timestamp>.key` in current directory
+/// * `touch_timeout` - How long to wait for the user to touch a touch-triggered slot
+/// * `derivation` - Scheme and size turning the response into the keyfile
 ///
 /// # Returns
 ///
@@ -30,15 +38,41 @@ use std::time::{SystemTime, UNIX_EPOCH};
 /// # Errors
 ///
 /// Returns an error if:
-/// - `YubiKey` challenge-response fails
+/// - `YubiKey` challenge-response fails or the key is not touched in time
 /// - File creation or writing fails
 /// - Setting file permissions fails
-pub fn generate_keyfile(challenge: &str, output_path: Option<PathBuf>) -> Result<PathBuf> {
+pub fn generate_keyfile(
+    backend: &dyn TokenBackend,
+    slot: Slot,
+    challenge: &[u8],
+    output_path: Option<PathBuf>,
+    touch_timeout: Duration,
+    derivation: Derivation,
+) -> Result<PathBuf> {
     println!("{} Generating keyfile...", "[INFO]".blue().bold());
 
     // Get response from YubiKey
-    let response_bytes = yubikey::challenge_response(challenge)?;
+    let response_bytes = yubikey::challenge_response(backend, slot, challenge, touch_timeout)?;
+    write_keyfile(&derivation.keyfile(&response_bytes), output_path)
+}
 
+/// Write keyfile bytes, readable by the owner only
+///
+/// # Arguments
+///
+/// * `keyfile_bytes` - The derived keyfile contents
+/// * `output_path` - Optional path for the keyfile, see [`generate_keyfile`]
+///
+/// # Returns
+///
+/// Returns the path to the written keyfile
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - File creation or writing fails
+/// - Setting file permissions fails
+pub fn write_keyfile(keyfile_bytes: &[u8], output_path: Option<PathBuf>) -> Result<PathBuf> {
     // Determine output path
     let path = if let Some(p) = output_path {
         p
@@ -55,7 +89,7 @@ pub fn generate_keyfile(challenge: &str, output_path: Option<PathBuf>) -> Result
     let mut file = File::create(&path)
         .map_err(|e| YkvcError::FileError(format!("Failed to create keyfile: {e}")))?;
 
-    file.write_all(&response_bytes)
+    file.write_all(keyfile_bytes)
         .map_err(|e| YkvcError::FileError(format!("Failed to write keyfile: {e}")))?;
 
     file.sync_all().map_err(|e| YkvcError::FileError(format!("Failed to sync keyfile: {e}")))?;
@@ -74,6 +108,41 @@ pub fn generate_keyfile(challenge: &str, output_path: Option<PathBuf>) -> Result
     Ok(path)
 }
 
+/// Generate keyfile from a slot backup secret, without a `YubiKey`
+///
+/// Computes the HMAC-SHA1 response in software, exactly as a slot programmed
+/// with `secret` would, and writes it through [`generate_keyfile`]. The result
+/// is byte for byte the keyfile the device produces for the same challenge.
+///
+/// # Arguments
+///
+/// * `secret` - The 20-byte secret printed by `ykvc slot <1|2> program`
+/// * `challenge` - The challenge bytes used with the original `YubiKey`
+/// * `fixed_64` - Whether the slot was programmed for fixed 64-byte challenges
+/// * `output_path` - Optional path for the keyfile, see [`generate_keyfile`]
+/// * `derivation` - Scheme and size the original keyfile was derived with
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - The secret is not exactly 20 bytes
+/// - File creation or writing fails
+/// - Setting file permissions fails
+pub fn recover_keyfile(
+    secret: &[u8],
+    challenge: &[u8],
+    fixed_64: bool,
+    output_path: Option<PathBuf>,
+    derivation: Derivation,
+) -> Result<PathBuf> {
+    if secret.len() != 20 {
+        return Err(YkvcError::InvalidSecretLength(secret.len()));
+    }
+
+    let backend = EmulatorBackend::with_secret(secret.to_vec(), fixed_64);
+    generate_keyfile(&backend, Slot::Two, challenge, output_path, DEFAULT_TOUCH_TIMEOUT, derivation)
+}
+
 /// Securely delete a keyfile
 ///
 /// This function uses platform-specific methods to securely delete a keyfile:
@@ -117,6 +186,8 @@ pub fn secure_delete(path: &Path) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::derivation::Scheme;
+    use crate::yubikey::ProgramOptions;
 
     #[test]
     fn test_generate_keyfile_path_with_timestamp() {
@@ -158,6 +229,109 @@ mod tests {
         assert_eq!(path.extension().and_then(|s| s.to_str()), Some("key"));
     }
 
+    /// Known answers: (secret, challenge, keyfile contents)
+    ///
+    /// The device answers with plain HMAC-SHA1 of the challenge, so the first
+    /// entry is RFC 2202 test case 1 and the rest were computed independently.
+    const KNOWN_ANSWERS: [(&str, &str, &str); 4] = [
+        (
+            "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b",
+            "Hi There",
+            "b617318655057264e28bc0b6fb378c8ef146be00",
+        ),
+        (
+            "0102030405060708090a0b0c0d0e0f1011121314",
+            "correct horse battery staple",
+            "c8cbc527dbfa969ca05733b9e90bafd62078967f",
+        ),
+        (
+            "0102030405060708090a0b0c0d0e0f1011121314",
+            "",
+            "8fa52a5547edc2eccec12c4c53bc807d8282084a",
+        ),
+        // A full 64-byte challenge still has its trailing run stripped by a
+        // slot programmed with HMAC_LT64, so this answers like the empty one
+        (
+            "0102030405060708090a0b0c0d0e0f1011121314",
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+            "8fa52a5547edc2eccec12c4c53bc807d8282084a",
+        ),
+    ];
+
+    #[test]
+    fn test_recover_keyfile_known_answers() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for (i, (secret, challenge, expected)) in KNOWN_ANSWERS.iter().enumerate() {
+            let path = dir.path().join(format!("recovered_{i}.key"));
+            let secret = hex::decode(secret).unwrap();
+
+            let written = recover_keyfile(
+                &secret,
+                challenge.as_bytes(),
+                false,
+                Some(path.clone()),
+                Derivation::default(),
+            )
+            .unwrap();
+
+            assert_eq!(written, path);
+            assert_eq!(hex::encode(std::fs::read(&path).unwrap()), *expected);
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_recover_keyfile_matches_device_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = [0x5a; 20];
+        let device = EmulatorBackend::with_file(dir.path().join("slot"));
+        device.program(Slot::Two, &secret, ProgramOptions::default(), None).unwrap();
+
+        for derivation in
+            [Derivation::default(), Derivation::new(Scheme::V2HkdfSha256, Some(1000)).unwrap()]
+        {
+            let from_device = generate_keyfile(
+                &device,
+                Slot::Two,
+                b"phrase",
+                Some(dir.path().join("device.key")),
+                DEFAULT_TOUCH_TIMEOUT,
+                derivation,
+            )
+            .unwrap();
+            let recovered = recover_keyfile(
+                &secret,
+                b"phrase",
+                false,
+                Some(dir.path().join("recovered.key")),
+                derivation,
+            )
+            .unwrap();
+
+            assert_eq!(std::fs::read(from_device).unwrap(), std::fs::read(recovered).unwrap());
+        }
+        assert_eq!(std::fs::metadata(dir.path().join("device.key")).unwrap().len(), 1000);
+    }
+
+    #[test]
+    fn test_recover_keyfile_rejects_wrong_secret_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recovered.key");
+
+        let result = recover_keyfile(
+            &[0u8; 19],
+            b"phrase",
+            false,
+            Some(path.clone()),
+            Derivation::default(),
+        );
+
+        assert!(matches!(result, Err(YkvcError::InvalidSecretLength(19))));
+        assert!(!path.exists());
+    }
+
     // Note: Full integration tests require either:
     // 1. Mock YubiKey challenge_response function
     // 2. Actual YubiKey hardware
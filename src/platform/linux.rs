@@ -1,8 +1,9 @@
 //! Linux-specific platform implementation (Ubuntu/Debian)
 
+use super::Toolchain;
 use crate::error::{Result, YkvcError};
+use crate::process::Tool;
 use colored::Colorize;
-use std::process::Command;
 
 /// Checks if a command exists in the system PATH
 ///
@@ -14,33 +15,27 @@ use std::process::Command;
 ///
 /// Returns an error if the command check fails
 pub fn check_command(cmd: &str) -> Result<bool> {
-    let output = Command::new("command").arg("-v").arg(cmd).output().map_err(|e| {
-        YkvcError::CommandFailed { command: format!("command -v {cmd}"), message: e.to_string() }
-    })?;
-
-    Ok(output.status.success())
+    Ok(Tool::new("command").arg("-v").arg(cmd).run()?.status.success())
 }
 
-/// Installs `YubiKey` tools via apt
+/// Installs the packages of the `YubiKey` toolchain via apt
 ///
 /// # Errors
 ///
 /// Returns an error if installation fails
-pub fn install_yubikey_tools() -> Result<()> {
-    println!(
-        "{} Installing YubiKey tools (yubikey-manager, yubikey-personalization)...",
-        "[INFO]".blue().bold()
-    );
+pub fn install_yubikey_tools(toolchain: Toolchain) -> Result<()> {
+    let packages: &[&str] = match toolchain {
+        Toolchain::Ykman => &["yubikey-manager"],
+        Toolchain::Personalization => &["yubikey-manager", "yubikey-personalization"],
+    };
+    println!("{} Installing YubiKey tools ({})...", "[INFO]".blue().bold(), packages.join(", "));
     println!("{} This will require sudo privileges.", "[INFO]".blue().bold());
 
     // Update apt cache
     println!("{} Updating package lists...", "[INFO]".blue().bold());
-    let update_output =
-        Command::new("sudo").arg("apt-get").arg("update").status().map_err(|e| {
-            YkvcError::InstallationFailed(format!("Failed to update apt cache: {e}"))
-        })?;
+    let update_output = Tool::installer("sudo").args(["apt-get", "update"]).run()?;
 
-    if !update_output.success() {
+    if !update_output.status.success() {
         return Err(YkvcError::InstallationFailed(
             "Failed to update apt cache. Check your sudo permissions.".to_string(),
         ));
@@ -48,18 +43,10 @@ pub fn install_yubikey_tools() -> Result<()> {
 
     // Install packages
     println!("{} Installing packages...", "[INFO]".blue().bold());
-    let install_output = Command::new("sudo")
-        .arg("apt-get")
-        .arg("install")
-        .arg("-y")
-        .arg("yubikey-manager")
-        .arg("yubikey-personalization")
-        .status()
-        .map_err(|e| {
-            YkvcError::InstallationFailed(format!("Failed to install YubiKey tools: {e}"))
-        })?;
-
-    if !install_output.success() {
+    let install_output =
+        Tool::installer("sudo").args(["apt-get", "install", "-y"]).args(packages).run()?;
+
+    if !install_output.status.success() {
         return Err(YkvcError::InstallationFailed(
             "Failed to install YubiKey tools via apt-get".to_string(),
         ));
@@ -101,8 +88,9 @@ pub fn secure_delete(path: &std::path::Path) -> Result<()> {
     }
 
     // Run shred with 10 passes, verbose, force, zero final pass, and delete
-    // Use .status() instead of .output() to show progress to user
-    let status = Command::new("shred")
+    // Run interactively to show progress to user
+    let output = Tool::new("shred")
+        .interactive()
         .arg("-v") // Verbose - show progress
         .arg("-f") // Force - change permissions if needed
         .arg("-z") // Zero - final overwrite with zeros
@@ -110,13 +98,9 @@ pub fn secure_delete(path: &std::path::Path) -> Result<()> {
         .arg("10") // 10 passes
         .arg("-u") // Remove file after overwriting
         .arg(path)
-        .status()
-        .map_err(|e| YkvcError::CommandFailed {
-            command: format!("shred -v -f -z -n 10 -u {}", path.display()),
-            message: e.to_string(),
-        })?;
+        .run()?;
 
-    if !status.success() {
+    if !output.status.success() {
         return Err(YkvcError::CommandFailed {
             command: format!("shred -v -f -z -n 10 -u {}", path.display()),
             message: "shred failed".to_string(),
@@ -182,10 +166,11 @@ mod tests {
             let _ = std::fs::remove_file(&path);
         }
 
-        // Result should either succeed or fail with CommandFailed (shred not found)
+        // Result should either succeed or fail because shred is missing or failed
         if let Err(e) = result {
             assert!(
                 matches!(e, YkvcError::CommandFailed { .. })
+                    || matches!(e, YkvcError::DependencyMissing(_))
                     || matches!(e, YkvcError::FileError(_))
             );
         }
@@ -1,8 +1,9 @@
 //! macOS-specific platform implementation
 
+use super::Toolchain;
 use crate::error::{Result, YkvcError};
+use crate::process::Tool;
 use colored::Colorize;
-use std::process::Command;
 
 /// Checks if a command exists in the system PATH
 ///
@@ -14,11 +15,7 @@ use std::process::Command;
 ///
 /// Returns an error if the command check fails
 pub fn check_command(cmd: &str) -> Result<bool> {
-    let output = Command::new("command").arg("-v").arg(cmd).output().map_err(|e| {
-        YkvcError::CommandFailed { command: format!("command -v {cmd}"), message: e.to_string() }
-    })?;
-
-    Ok(output.status.success())
+    Ok(Tool::new("command").arg("-v").arg(cmd).run()?.status.success())
 }
 
 /// Checks if Homebrew is installed
@@ -42,15 +39,12 @@ pub fn install_homebrew() -> Result<()> {
         "[INFO]".blue().bold()
     );
 
-    let output = Command::new("/bin/bash")
+    let output = Tool::installer("/bin/bash")
         .arg("-c")
         .arg(r"$(curl -fsSL https://raw.githubusercontent.com/Homebrew/install/HEAD/install.sh)")
-        .status()
-        .map_err(|e| {
-            YkvcError::InstallationFailed(format!("Failed to start Homebrew installer: {e}"))
-        })?;
+        .run()?;
 
-    if !output.success() {
+    if !output.status.success() {
         return Err(YkvcError::InstallationFailed(
             "Homebrew installation failed. Please install manually: https://brew.sh".to_string(),
         ));
@@ -65,43 +59,39 @@ pub fn install_homebrew() -> Result<()> {
 /// # Errors
 ///
 /// Returns an error if installation fails
-pub fn install_yubikey_tools() -> Result<()> {
-    println!("{} Installing YubiKey tools (ykpers, yubikey-manager)...", "[INFO]".blue().bold());
+pub fn install_yubikey_tools(toolchain: Toolchain) -> Result<()> {
+    let tools = match toolchain {
+        Toolchain::Ykman => "yubikey-manager",
+        Toolchain::Personalization => "ykpers, yubikey-manager",
+    };
+    println!("{} Installing YubiKey tools ({tools})...", "[INFO]".blue().bold());
 
     // Update brew first
     println!("{} Updating Homebrew...", "[INFO]".blue().bold());
-    let update_output = Command::new("brew")
-        .arg("update")
-        .status()
-        .map_err(|e| YkvcError::InstallationFailed(format!("Failed to update Homebrew: {e}")))?;
+    let update_output = Tool::installer("brew").arg("update").run()?;
 
-    if !update_output.success() {
+    if !update_output.status.success() {
         println!("{} Homebrew update failed, continuing anyway...", "[WARNING]".yellow().bold());
     }
 
-    // Install ykpers (formula)
-    println!("{} Installing ykpers...", "[INFO]".blue().bold());
-    let ykpers_output = Command::new("brew")
-        .arg("install")
-        .arg("ykpers")
-        .status()
-        .map_err(|e| YkvcError::InstallationFailed(format!("Failed to install ykpers: {e}")))?;
+    // Install ykpers (formula), only needed by the yubikey-personalization toolchain
+    if toolchain == Toolchain::Personalization {
+        println!("{} Installing ykpers...", "[INFO]".blue().bold());
+        let ykpers_output = Tool::installer("brew").args(["install", "ykpers"]).run()?;
 
-    if !ykpers_output.success() {
-        return Err(YkvcError::InstallationFailed(
-            "Failed to install ykpers via Homebrew. Try manually: brew install ykpers".to_string(),
-        ));
+        if !ykpers_output.status.success() {
+            return Err(YkvcError::InstallationFailed(
+                "Failed to install ykpers via Homebrew. Try manually: brew install ykpers"
+                    .to_string(),
+            ));
+        }
     }
 
     // Install ykman (formula)
     println!("{} Installing ykman (yubikey-manager)...", "[INFO]".blue().bold());
-    let ykman_output = Command::new("brew")
-        .arg("install")
-        .arg("ykman")
-        .status()
-        .map_err(|e| YkvcError::InstallationFailed(format!("Failed to install ykman: {e}")))?;
+    let ykman_output = Tool::installer("brew").args(["install", "ykman"]).run()?;
 
-    if !ykman_output.success() {
+    if !ykman_output.status.success() {
         return Err(YkvcError::InstallationFailed(
             "Failed to install ykman via Homebrew. Try manually: brew install ykman".to_string(),
         ));
@@ -109,12 +99,9 @@ pub fn install_yubikey_tools() -> Result<()> {
 
     // Install coreutils (for gshred - secure file deletion)
     println!("{} Installing coreutils (for secure file deletion)...", "[INFO]".blue().bold());
-    let coreutils_output =
-        Command::new("brew").arg("install").arg("coreutils").status().map_err(|e| {
-            YkvcError::InstallationFailed(format!("Failed to install coreutils: {e}"))
-        })?;
+    let coreutils_output = Tool::installer("brew").args(["install", "coreutils"]).run()?;
 
-    if !coreutils_output.success() {
+    if !coreutils_output.status.success() {
         return Err(YkvcError::InstallationFailed(
             "Failed to install coreutils via Homebrew. Try manually: brew install coreutils"
                 .to_string(),
@@ -157,8 +144,9 @@ pub fn secure_delete(path: &std::path::Path) -> Result<()> {
     }
 
     // Run gshred with 10 passes, verbose, force, zero final pass, and delete
-    // Use .status() instead of .output() to show progress to user
-    let status = Command::new("gshred")
+    // Run interactively to show progress to user
+    let output = Tool::new("gshred")
+        .interactive()
         .arg("-v") // Verbose - show progress
         .arg("-f") // Force - change permissions if needed
         .arg("-z") // Zero - final overwrite with zeros
@@ -166,13 +154,9 @@ pub fn secure_delete(path: &std::path::Path) -> Result<()> {
         .arg("10") // 10 passes
         .arg("-u") // Remove file after overwriting
         .arg(path)
-        .status()
-        .map_err(|e| YkvcError::CommandFailed {
-            command: format!("gshred -v -f -z -n 10 -u {}", path.display()),
-            message: e.to_string(),
-        })?;
+        .run()?;
 
-    if !status.success() {
+    if !output.status.success() {
         return Err(YkvcError::CommandFailed {
             command: format!("gshred -v -f -z -n 10 -u {}", path.display()),
             message: "gshred failed".to_string(),
@@ -245,10 +229,11 @@ mod tests {
             let _ = std::fs::remove_file(&path);
         }
 
-        // Result should either succeed or fail with CommandFailed (gshred not found)
+        // Result should either succeed or fail because gshred is missing or failed
         if let Err(e) = result {
             assert!(
                 matches!(e, YkvcError::CommandFailed { .. })
+                    || matches!(e, YkvcError::DependencyMissing(_))
                     || matches!(e, YkvcError::FileError(_))
             );
         }
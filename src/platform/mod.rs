@@ -1,4 +1,9 @@
-//! Platform-specific functionality and OS detection
+//! Platform-specific functionality, OS detection and the external tools
+//!
+//! The command-line tool backend needs `ykman`, and either programs and
+//! queries the slots through `ykman otp` as well or, when the deprecated
+//! yubikey-personalization tools are installed, through `ykpersonalize` and
+//! `ykchalresp`. Only the commands of the detected [`Toolchain`] are required.
 
 pub mod linux;
 pub mod macos;
@@ -8,8 +13,11 @@ use crate::error::Result;
 use crate::error::YkvcError;
 use colored::Colorize;
 
-/// Required command-line dependencies (common for all platforms)
-const REQUIRED_COMMANDS: &[&str] = &["ykman", "ykpersonalize", "ykchalresp"];
+/// Commands the ykman-only toolchain needs
+const YKMAN_COMMANDS: &[&str] = &["ykman"];
+
+/// Commands the yubikey-personalization toolchain needs
+const PERSONALIZATION_COMMANDS: &[&str] = &["ykman", "ykpersonalize", "ykchalresp"];
 
 /// macOS-specific required commands
 const REQUIRED_COMMANDS_MACOS: &[&str] = &["gshred"];
@@ -35,6 +43,43 @@ impl OS {
     }
 }
 
+/// External tools the command-line tool backend drives the `YubiKey` with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Toolchain {
+    /// `ykman` alone, through `ykman otp chalresp` and `ykman otp calculate`
+    Ykman,
+    /// `ykman` with `ykpersonalize` and `ykchalresp` from yubikey-personalization
+    Personalization,
+}
+
+impl Toolchain {
+    /// Picks the yubikey-personalization tools when both are on `PATH`, and
+    /// `ykman` alone otherwise
+    #[must_use]
+    pub fn detect() -> Self {
+        if on_path("ykpersonalize") && on_path("ykchalresp") {
+            Self::Personalization
+        } else {
+            Self::Ykman
+        }
+    }
+
+    /// Commands that must be installed for this toolchain
+    #[must_use]
+    pub const fn commands(self) -> &'static [&'static str] {
+        match self {
+            Self::Ykman => YKMAN_COMMANDS,
+            Self::Personalization => PERSONALIZATION_COMMANDS,
+        }
+    }
+}
+
+/// Returns `true` if an executable named `program` is in one of the `PATH` directories
+fn on_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
+
 /// Detects the current operating system
 ///
 /// # Errors
@@ -70,20 +115,21 @@ pub fn detect_os() -> Result<OS> {
     }
 }
 
-/// Checks if all required dependencies are installed
+/// Checks if all dependencies of the toolchain are installed
 ///
 /// # Arguments
 ///
 /// * `os` - The detected operating system
+/// * `toolchain` - The toolchain whose commands are required
 ///
 /// # Errors
 ///
 /// Returns an error if dependency checking fails
-pub fn check_dependencies(os: OS) -> Result<Vec<String>> {
+pub fn check_dependencies(os: OS, toolchain: Toolchain) -> Result<Vec<String>> {
     let mut missing = Vec::new();
 
     // Check common dependencies
-    for cmd in REQUIRED_COMMANDS {
+    for cmd in toolchain.commands() {
         let exists = match os {
             OS::MacOS => macos::check_command(cmd)?,
             OS::Ubuntu => linux::check_command(cmd)?,
@@ -112,11 +158,12 @@ pub fn check_dependencies(os: OS) -> Result<Vec<String>> {
 /// # Arguments
 ///
 /// * `os` - The detected operating system
+/// * `toolchain` - The toolchain whose packages are installed
 ///
 /// # Errors
 ///
 /// Returns an error if installation fails
-pub fn install_dependencies(os: OS) -> Result<()> {
+pub fn install_dependencies(os: OS, toolchain: Toolchain) -> Result<()> {
     match os {
         OS::MacOS => {
             // Check if Homebrew is installed
@@ -126,11 +173,11 @@ pub fn install_dependencies(os: OS) -> Result<()> {
             }
 
             // Install YubiKey tools
-            macos::install_yubikey_tools()?;
+            macos::install_yubikey_tools(toolchain)?;
         }
         OS::Ubuntu => {
             // Install YubiKey tools
-            linux::install_yubikey_tools()?;
+            linux::install_yubikey_tools(toolchain)?;
         }
     }
 
@@ -191,10 +238,17 @@ mod tests {
 
     #[test]
     fn test_required_commands_constants() {
-        assert!(REQUIRED_COMMANDS.contains(&"ykman"));
-        assert!(REQUIRED_COMMANDS.contains(&"ykpersonalize"));
-        assert!(REQUIRED_COMMANDS.contains(&"ykchalresp"));
-        assert_eq!(REQUIRED_COMMANDS.len(), 3);
+        assert_eq!(Toolchain::Ykman.commands(), ["ykman"]);
+        assert!(PERSONALIZATION_COMMANDS.contains(&"ykman"));
+        assert!(PERSONALIZATION_COMMANDS.contains(&"ykpersonalize"));
+        assert!(PERSONALIZATION_COMMANDS.contains(&"ykchalresp"));
+        assert_eq!(Toolchain::Personalization.commands().len(), 3);
+    }
+
+    #[test]
+    fn test_on_path() {
+        assert!(on_path("sh"));
+        assert!(!on_path("ykvc-no-such-tool"));
     }
 
     #[test]
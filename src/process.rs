@@ -0,0 +1,381 @@
+//! Running external tools
+//!
+//! Every external tool ykvc starts goes through [`Tool`]. A run that outlives
+//! its timeout is killed, and so is one ykvc stops waiting for early. Device
+//! tools are run again with backoff when they fail with a USB error that
+//! usually clears up on its own, such as a busy device.
+//!
+//! Timeouts come from [`configure`], which `main` calls once with the values
+//! from the command line and the configuration file.
+
+use crate::backend::TOUCH_PROMPT_DELAY;
+use crate::error::{Result, YkvcError};
+use crate::prompt;
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::OnceLock;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Default time a device tool or quick check may run
+pub const DEFAULT_TOOL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default time a package installation may run
+pub const DEFAULT_INSTALL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Interval at which a running tool is polled
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs of a device tool, including the first, before a transient error is final
+const ATTEMPTS: u32 = 3;
+
+/// Wait before the first retry, doubled for every further one
+const BACKOFF: Duration = Duration::from_millis(250);
+
+/// Lowercase stderr fragments of USB errors worth retrying
+const TRANSIENT_ERRORS: &[&str] =
+    &["device busy", "resource busy", "temporarily unavailable", "usb error", "write error"];
+
+/// How long external tools may run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Device tools, dependency checks and secure deletion
+    pub tool: Duration,
+    /// Package manager runs installing dependencies
+    pub install: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self { tool: DEFAULT_TOOL_TIMEOUT, install: DEFAULT_INSTALL_TIMEOUT }
+    }
+}
+
+static TIMEOUTS: OnceLock<Timeouts> = OnceLock::new();
+
+/// Sets the timeouts of every later tool run
+///
+/// Only the first call has an effect.
+pub fn configure(timeouts: Timeouts) {
+    let _ = TIMEOUTS.set(timeouts);
+}
+
+/// Timeouts set by [`configure`], or the defaults
+fn timeouts() -> Timeouts {
+    TIMEOUTS.get().copied().unwrap_or_default()
+}
+
+/// An external tool invocation
+///
+/// By default the tool gets no stdin, its output is captured and it is killed
+/// after the configured tool timeout.
+#[derive(Debug)]
+pub struct Tool<'a> {
+    command: Command,
+    input: Option<&'a str>,
+    timeout: Duration,
+    touch: bool,
+    interactive: bool,
+    retry: bool,
+}
+
+impl<'a> Tool<'a> {
+    /// Prepares a run of `program`
+    pub fn new(program: &str) -> Self {
+        Self {
+            command: Command::new(program),
+            input: None,
+            timeout: timeouts().tool,
+            touch: false,
+            interactive: false,
+            retry: false,
+        }
+    }
+
+    /// Prepares a package manager run, which talks to the terminal directly
+    ///
+    /// Installers may ask for a password and show progress, so they share the
+    /// terminal and run under the install timeout.
+    pub fn installer(program: &str) -> Self {
+        Self { timeout: timeouts().install, ..Self::new(program).interactive() }
+    }
+
+    /// Adds an argument
+    #[must_use]
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.command.arg(arg);
+        self
+    }
+
+    /// Adds arguments
+    #[must_use]
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.command.args(args);
+        self
+    }
+
+    /// Writes `input` to the tool's stdin, the only way secrets reach a tool
+    #[must_use]
+    pub const fn input(mut self, input: &'a str) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    /// Lets the tool share the terminal instead of capturing its output
+    #[must_use]
+    pub const fn interactive(mut self) -> Self {
+        self.interactive = true;
+        self
+    }
+
+    /// Treats the run as waiting for a touch
+    ///
+    /// Asks for the touch once the tool has not finished within
+    /// [`TOUCH_PROMPT_DELAY`], and reports a run killed after `timeout` as
+    /// [`YkvcError::TouchTimeout`].
+    #[must_use]
+    pub const fn wait_for_touch(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.touch = true;
+        self
+    }
+
+    /// Runs the tool again with backoff if it fails with a transient USB error
+    #[must_use]
+    pub const fn retry_transient(mut self) -> Self {
+        self.retry = true;
+        self
+    }
+
+    /// Runs the tool to completion
+    ///
+    /// A tool that exits with a failure status is not an error here; the
+    /// caller reads its status and stderr.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The tool is not installed ([`YkvcError::DependencyMissing`])
+    /// - The tool does not finish in time ([`YkvcError::ToolTimeout`], or
+    ///   [`YkvcError::TouchTimeout`] for runs waiting for a touch)
+    /// - Starting or waiting for the tool fails
+    pub fn run(mut self) -> Result<Output> {
+        let mut attempt = 1;
+        loop {
+            let output = self.run_once()?;
+            if output.status.success()
+                || !self.retry
+                || attempt >= ATTEMPTS
+                || !is_transient(&output.stderr)
+            {
+                return Ok(output);
+            }
+
+            thread::sleep(BACKOFF * 2u32.pow(attempt - 1));
+            attempt += 1;
+        }
+    }
+
+    /// Name of the program, for error messages
+    fn program(&self) -> String {
+        self.command.get_program().to_string_lossy().into_owned()
+    }
+
+    fn run_once(&mut self) -> Result<Output> {
+        let captured = || if self.interactive { Stdio::inherit() } else { Stdio::piped() };
+        let stdin = match self.input {
+            Some(_) => Stdio::piped(),
+            None if self.interactive => Stdio::inherit(),
+            None => Stdio::null(),
+        };
+        self.command.stdin(stdin).stdout(captured()).stderr(captured());
+
+        let mut child = Running(self.command.spawn().map_err(|e| self.failure(&e))?);
+        if let (Some(input), Some(mut stdin)) = (self.input, child.0.stdin.take()) {
+            // A tool that exits without reading its input is reported by its own status
+            match stdin.write_all(input.as_bytes()) {
+                Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(self.failure(&e)),
+                _ => {}
+            }
+            // Dropping stdin closes the pipe, so the tool sees the end of its input
+        }
+
+        // Pipes are drained while the tool runs, so a chatty tool cannot block on them
+        let stdout = child.0.stdout.take().map(drain);
+        let stderr = child.0.stderr.take().map(drain);
+
+        let started = Instant::now();
+        let mut prompted = false;
+        let status = loop {
+            if let Some(status) = child.0.try_wait().map_err(|e| self.failure(&e))? {
+                break status;
+            }
+
+            let elapsed = started.elapsed();
+            if elapsed >= self.timeout {
+                child.kill();
+                return Err(if self.touch {
+                    YkvcError::TouchTimeout(self.timeout.as_secs())
+                } else {
+                    YkvcError::ToolTimeout { tool: self.program(), seconds: self.timeout.as_secs() }
+                });
+            }
+
+            if self.touch && !prompted && elapsed >= TOUCH_PROMPT_DELAY {
+                prompt::touch(self.timeout);
+                prompted = true;
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        Ok(Output { status, stdout: collect(stdout), stderr: collect(stderr) })
+    }
+
+    /// Maps an error starting or waiting for the tool
+    fn failure(&self, error: &io::Error) -> YkvcError {
+        if error.kind() == io::ErrorKind::NotFound {
+            return YkvcError::DependencyMissing(self.program());
+        }
+        YkvcError::CommandFailed { command: self.program(), message: error.to_string() }
+    }
+}
+
+/// A started tool, killed if ykvc stops waiting for it
+struct Running(Child);
+
+impl Running {
+    fn kill(&mut self) {
+        // Errors mean the tool has already exited
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if matches!(self.0.try_wait(), Ok(None)) {
+            self.kill();
+        }
+    }
+}
+
+/// Reads a pipe to its end on a separate thread
+fn drain(mut pipe: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut data = Vec::new();
+        let _ = pipe.read_to_end(&mut data);
+        data
+    })
+}
+
+/// Everything a drained pipe delivered
+fn collect(pipe: Option<JoinHandle<Vec<u8>>>) -> Vec<u8> {
+    pipe.and_then(|pipe| pipe.join().ok()).unwrap_or_default()
+}
+
+/// Whether `stderr` reports a USB error that may clear up on its own
+fn is_transient(stderr: &[u8]) -> bool {
+    let stderr = String::from_utf8_lossy(stderr).to_lowercase();
+    TRANSIENT_ERRORS.iter().any(|error| stderr.contains(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_and_output() {
+        let output = Tool::new("cat").input("secret\n").run().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"secret\n");
+
+        // Without input the tool sees an empty stdin instead of the terminal
+        let output = Tool::new("cat").run().unwrap();
+        assert!(output.stdout.is_empty());
+    }
+
+    #[test]
+    fn test_ignores_unread_input() {
+        let output = Tool::new("true").input(&"x".repeat(1 << 20)).run().unwrap();
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn test_failure_status_is_returned() {
+        let output = Tool::new("sh").args(["-c", "echo oops >&2; exit 3"]).run().unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stderr, b"oops\n");
+    }
+
+    #[test]
+    fn test_large_output() {
+        let output = Tool::new("head").args(["-c", "1000000", "/dev/zero"]).run().unwrap();
+        assert_eq!(output.stdout.len(), 1_000_000);
+    }
+
+    #[test]
+    fn test_timeout_kills_tool() {
+        let mut tool = Tool::new("sleep").arg("5");
+        tool.timeout = Duration::from_millis(200);
+
+        let started = Instant::now();
+        let result = tool.run();
+        assert!(matches!(result, Err(YkvcError::ToolTimeout { ref tool, .. }) if tool == "sleep"));
+        assert!(started.elapsed() < Duration::from_secs(3));
+
+        let result = Tool::new("sleep").arg("5").wait_for_touch(Duration::from_secs(1)).run();
+        assert!(matches!(result, Err(YkvcError::TouchTimeout(1))));
+    }
+
+    #[test]
+    fn test_missing_tool() {
+        let result = Tool::new("ykvc-no-such-tool").run();
+        assert!(
+            matches!(result, Err(YkvcError::DependencyMissing(ref tool)) if tool == "ykvc-no-such-tool")
+        );
+    }
+
+    #[test]
+    fn test_retries_transient_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let runs = dir.path().join("runs");
+        // Fails with a busy device on the first run only
+        let script = format!(
+            "echo run >> {0}; [ $(wc -l < {0}) -gt 1 ] && echo ok || {{ echo 'USB error: device busy' >&2; exit 1; }}",
+            runs.display()
+        );
+
+        let output = Tool::new("sh").args(["-c", &script]).retry_transient().run().unwrap();
+        assert!(output.status.success());
+        assert_eq!(std::fs::read_to_string(&runs).unwrap().lines().count(), 2);
+
+        std::fs::remove_file(&runs).unwrap();
+        let output = Tool::new("sh").args(["-c", &script]).run().unwrap();
+        assert!(!output.status.success());
+        assert_eq!(std::fs::read_to_string(&runs).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_gives_up_after_attempts() {
+        let dir = tempfile::tempdir().unwrap();
+        let runs = dir.path().join("runs");
+        let script = format!("echo run >> {}; echo 'write error' >&2; exit 1", runs.display());
+
+        let output = Tool::new("sh").args(["-c", &script]).retry_transient().run().unwrap();
+        assert!(!output.status.success());
+        assert_eq!(std::fs::read_to_string(&runs).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(b"Error: USB error: LIBUSB_ERROR_BUSY: Resource busy"));
+        assert!(is_transient(b"ykpersonalize: write error"));
+        assert!(!is_transient(b"Error: No YubiKey detected!"));
+    }
+}
@@ -0,0 +1,110 @@
+//! Interactive prompts
+//!
+//! Uses `dialoguer` when stdin is a terminal. Otherwise answers are read line by
+//! line from stdin, so the CLI can be scripted and driven by integration tests.
+
+use colored::Colorize;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::time::Duration;
+
+/// Asks a yes/no question, defaulting to "no"
+///
+/// # Errors
+///
+/// Returns an error if reading the answer fails
+pub fn confirm(prompt: &str) -> io::Result<bool> {
+    if io::stdin().is_terminal() {
+        return dialoguer::Confirm::new()
+            .with_prompt(prompt)
+            .default(false)
+            .interact()
+            .map_err(into_io);
+    }
+
+    let answer = read_line(&format!("{prompt} [y/N]"))?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Reads a secret without echoing it
+///
+/// # Errors
+///
+/// Returns an error if reading the secret fails
+pub fn password(prompt: &str) -> io::Result<String> {
+    if io::stdin().is_terminal() {
+        return dialoguer::Password::new().with_prompt(prompt).interact().map_err(into_io);
+    }
+
+    read_line(prompt)
+}
+
+/// Waits until the user presses Enter
+///
+/// # Errors
+///
+/// Returns an error if reading stdin fails
+pub fn wait_for_enter(prompt: &str) -> io::Result<()> {
+    if io::stdin().is_terminal() {
+        dialoguer::Input::<String>::new()
+            .with_prompt(prompt)
+            .allow_empty(true)
+            .interact_text()
+            .map_err(into_io)?;
+        return Ok(());
+    }
+
+    read_line(prompt).map(|_| ())
+}
+
+/// Asks the user to touch the `YubiKey` for a touch-triggered challenge
+///
+/// Printed to stderr, so it also shows when stdout is redirected.
+pub fn touch(timeout: Duration) {
+    eprintln!(
+        "{} Touch your YubiKey now (waiting up to {} seconds)...",
+        "[TOUCH]".magenta().bold(),
+        timeout.as_secs()
+    );
+}
+
+/// Shows that ykvc is waiting for `device` to be connected
+///
+/// On a terminal the line is redrawn in place with the seconds left, and
+/// [`finish_waiting`] ends it. Otherwise only the `first` call prints.
+pub fn waiting_for_device(device: &str, remaining: Option<Duration>, first: bool) {
+    let left = remaining.map_or_else(String::new, |remaining| {
+        format!(" ({} seconds left)", remaining.as_millis().div_ceil(1000))
+    });
+
+    if io::stderr().is_terminal() {
+        eprint!("\r\x1B[K{} Waiting for {device} to be connected{left}...", "[WAIT]".blue().bold());
+    } else if first {
+        eprintln!("{} Waiting for {device} to be connected{left}...", "[WAIT]".blue().bold());
+    }
+}
+
+/// Ends the line redrawn by [`waiting_for_device`]
+pub fn finish_waiting() {
+    if io::stderr().is_terminal() {
+        eprintln!();
+    }
+}
+
+/// Unwraps the I/O error behind a `dialoguer` error
+fn into_io(error: dialoguer::Error) -> io::Error {
+    let dialoguer::Error::IO(e) = error;
+    e
+}
+
+/// Prints the prompt and reads one line from stdin without its line ending
+fn read_line(prompt: &str) -> io::Result<String> {
+    eprint!("{prompt}: ");
+    io::stderr().flush()?;
+
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    eprintln!();
+
+    let trimmed = line.strip_suffix('\n').unwrap_or(&line);
+    Ok(trimmed.strip_suffix('\r').unwrap_or(trimmed).to_string())
+}
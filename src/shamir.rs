@@ -0,0 +1,200 @@
+//! Shamir's secret sharing over GF(256)
+//!
+//! Every byte of the secret is the constant term of its own polynomial of
+//! degree `threshold - 1`. The other coefficients are drawn uniformly from all
+//! of GF(256), zero included, so fewer than `threshold` shares are independent
+//! of the secret. The field is the one AES uses, reduced by
+//! x^8 + x^4 + x^3 + x + 1, and the arithmetic does not branch on its operands.
+
+use crate::error::{Result, YkvcError};
+use rand::RngCore;
+use std::collections::BTreeSet;
+
+/// Random polynomials sharing a secret, one per secret byte
+pub struct Dealer {
+    /// Coefficients of each polynomial, constant term first
+    polynomials: Vec<Vec<u8>>,
+}
+
+impl Dealer {
+    /// Draws the polynomials sharing `secret` among `threshold` shares
+    #[must_use]
+    pub fn new(secret: &[u8], threshold: u8) -> Self {
+        Self::with_rng(&mut rand::rngs::OsRng, secret, threshold)
+    }
+
+    /// Draws the polynomials from `rng`
+    fn with_rng<R: RngCore>(rng: &mut R, secret: &[u8], threshold: u8) -> Self {
+        let degree = usize::from(threshold.max(1) - 1);
+        let polynomials = secret
+            .iter()
+            .map(|&byte| {
+                let mut coefficients = vec![0; degree + 1];
+                coefficients[0] = byte;
+                rng.fill_bytes(&mut coefficients[1..]);
+                coefficients
+            })
+            .collect();
+        Self { polynomials }
+    }
+
+    /// Share at `x`, which must not be 0: the share at 0 is the secret
+    #[must_use]
+    pub fn share(&self, x: u8) -> Vec<u8> {
+        debug_assert_ne!(x, 0, "the share at 0 is the secret itself");
+        self.polynomials
+            .iter()
+            .map(|coefficients| {
+                coefficients.iter().rev().fold(0, |value, &coefficient| mul(value, x) ^ coefficient)
+            })
+            .collect()
+    }
+}
+
+/// Value at `x` of the polynomials through the shares `points`
+///
+/// At 0 this is the secret. Given at least `threshold` shares, any other `x`
+/// gives the share a dealer would have made there.
+///
+/// # Errors
+///
+/// Returns an error if there are no shares, two share the same point, or
+/// their lengths differ
+pub fn interpolate(points: &[(u8, Vec<u8>)], x: u8) -> Result<Vec<u8>> {
+    let invalid = |message: &str| YkvcError::InvalidShareFile(message.to_string());
+
+    let size = points.first().ok_or_else(|| invalid("no shares to combine"))?.1.len();
+    if points.iter().any(|(_, y)| y.len() != size) {
+        return Err(invalid("the shares differ in length"));
+    }
+    if points.iter().map(|(point, _)| point).collect::<BTreeSet<_>>().len() != points.len() {
+        return Err(invalid("two shares lie at the same point"));
+    }
+
+    let mut value = vec![0; size];
+    for (i, (xi, yi)) in points.iter().enumerate() {
+        // Lagrange basis polynomial of point i, evaluated at x
+        let basis = points
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .fold(1, |basis, (_, (xj, _))| mul(basis, mul(x ^ xj, inverse(xi ^ xj))));
+        for (byte, y) in value.iter_mut().zip(yi) {
+            *byte ^= mul(basis, *y);
+        }
+    }
+    Ok(value)
+}
+
+/// Product in GF(256)
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// Multiplicative inverse in GF(256), as a^254; 0 maps to 0
+fn inverse(a: u8) -> u8 {
+    let mut result = 1;
+    let mut power = a;
+    // 254 = 0b1111_1110
+    for bit in 0..8 {
+        if 254 >> bit & 1 == 1 {
+            result = mul(result, power);
+        }
+        power = mul(power, power);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Yields the bytes 0, 1, ..., 255 and starts over
+    struct Counter(u8);
+
+    impl RngCore for Counter {
+        fn next_u32(&mut self) -> u32 {
+            let mut bytes = [0; 4];
+            self.fill_bytes(&mut bytes);
+            u32::from_le_bytes(bytes)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            let mut bytes = [0; 8];
+            self.fill_bytes(&mut bytes);
+            u64::from_le_bytes(bytes)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for byte in dest {
+                *byte = self.0;
+                self.0 = self.0.wrapping_add(1);
+            }
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> std::result::Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_field_arithmetic() {
+        // FIPS 197, section 4.2
+        assert_eq!(mul(0x57, 0x83), 0xc1);
+        assert_eq!(mul(0x57, 0x13), 0xfe);
+        assert_eq!(inverse(0x53), 0xca);
+        assert_eq!(inverse(0), 0);
+        assert!((1..=u8::MAX).all(|a| mul(a, inverse(a)) == 1));
+    }
+
+    #[test]
+    fn test_coefficients_cover_the_whole_field() {
+        // Every random byte becomes a coefficient as it is, zero included
+        let dealer = Dealer::with_rng(&mut Counter(0), &[0xaa; 256], 2);
+        let coefficients: BTreeSet<u8> =
+            dealer.polynomials.iter().map(|coefficients| coefficients[1]).collect();
+        assert_eq!(coefficients.len(), 256);
+        assert!(coefficients.contains(&0));
+        assert!(dealer.polynomials.iter().all(|coefficients| coefficients[0] == 0xaa));
+
+        // A zero coefficient leaves the share of that byte at the secret
+        assert_eq!(dealer.share(7)[0], 0xaa);
+        assert_ne!(dealer.share(7)[1], 0xaa);
+    }
+
+    #[test]
+    fn test_any_threshold_of_shares_rebuild() {
+        let secret = b"threshold secret";
+        let dealer = Dealer::new(secret, 3);
+        let shares: Vec<_> = (1..=5).map(|x| (x, dealer.share(x))).collect();
+
+        for subset in [[0, 1, 2], [0, 2, 4], [4, 3, 1]] {
+            let points: Vec<_> = subset.iter().map(|&i| shares[i].clone()).collect();
+            assert_eq!(interpolate(&points, 0).unwrap(), secret);
+        }
+        assert_eq!(interpolate(&shares, 0).unwrap(), secret);
+        assert_ne!(interpolate(&shares[..2], 0).unwrap(), secret);
+
+        // Three shares also give the share at any other point
+        assert_eq!(interpolate(&shares[..3], 200).unwrap(), dealer.share(200));
+
+        // One of n: every share is the secret
+        let dealer = Dealer::new(secret, 1);
+        assert_eq!(dealer.share(9), secret);
+    }
+
+    #[test]
+    fn test_interpolate_rejects_invalid_shares() {
+        assert!(interpolate(&[], 0).is_err());
+        assert!(interpolate(&[(1, vec![1, 2]), (2, vec![3])], 0).is_err());
+        assert!(interpolate(&[(1, vec![1]), (1, vec![2])], 0).is_err());
+    }
+}
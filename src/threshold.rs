@@ -0,0 +1,618 @@
+//! Threshold keyfiles
+//!
+//! A random master keyfile is split with Shamir's secret sharing over GF(256),
+//! so that any `threshold` of the enrolled keys rebuild it and fewer learn
+//! nothing about it. The shares are kept in a share file, each encrypted with
+//! AES-256-GCM under a key derived from its `YubiKey`'s response to a random
+//! challenge stored beside it: HKDF-SHA256 of the response with the set id as
+//! salt and the info `ykvc threshold share`. The format version, set id,
+//! threshold, serial, share number and challenge are authenticated along with
+//! the share, and the check value, HMAC-SHA256 of the set id under the master
+//! keyfile, confirms the rebuilt keyfile.
+//!
+//! The share file is versioned TOML:
+//!
+//! ```toml
+//! version = 1
+//! threshold = 2
+//! id = "<16 random bytes in hex>"
+//! check = "<HMAC-SHA256 in hex>"
+//!
+//! [[share]]
+//! serial = 12345678
+//! x = 1
+//! challenge = "<32 random bytes in hex>"
+//! nonce = "<12 random bytes in hex>"
+//! ciphertext = "<64-byte share and 16-byte tag in hex>"
+//! ```
+//!
+//! Removing a key deletes its share, but a copy of the old file still holds
+//! it. Only a new set, made with a new master keyfile, revokes a key for good.
+
+use crate::config::hex_bytes;
+use crate::crypto;
+use crate::error::{Result, YkvcError};
+use crate::shamir;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use hkdf::Hkdf;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Share file format written and read by this build
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Size of the master keyfile
+pub const MASTER_SIZE: usize = 64;
+
+/// Size of the challenge each key answers
+const CHALLENGE_SIZE: usize = 32;
+
+/// Size of the set id
+const ID_SIZE: usize = 16;
+
+/// Size of an AES-GCM nonce
+const NONCE_SIZE: usize = 12;
+
+/// Size of the AES-GCM tag after each share
+const TAG_SIZE: usize = 16;
+
+/// HKDF info of the share encryption keys
+const SHARE_INFO: &[u8] = b"ykvc threshold share";
+
+/// A key to enroll, with its challenge and its response to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Enrollment {
+    /// Serial number of the key
+    pub serial: u32,
+    /// Random challenge from [`new_challenge`]
+    pub challenge: Vec<u8>,
+    /// Response of the key to the challenge
+    pub response: Vec<u8>,
+}
+
+/// Threshold and encrypted shares of one master keyfile
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShareFile {
+    /// Format version
+    version: u32,
+    /// Number of keys needed to rebuild the keyfile
+    threshold: u8,
+    /// Random id of the set, binding the shares to it
+    #[serde(with = "hex_bytes")]
+    id: Vec<u8>,
+    /// HMAC-SHA256 of the id under the master keyfile
+    #[serde(with = "hex_bytes")]
+    check: Vec<u8>,
+    /// Share of every enrolled key
+    #[serde(default, rename = "share")]
+    shares: Vec<EncryptedShare>,
+}
+
+/// Share of one enrolled key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EncryptedShare {
+    /// Serial number of the key
+    serial: u32,
+    /// Point the share was taken at, never 0
+    x: u8,
+    /// Challenge the key answers
+    #[serde(with = "hex_bytes")]
+    challenge: Vec<u8>,
+    /// AES-GCM nonce
+    #[serde(with = "hex_bytes")]
+    nonce: Vec<u8>,
+    /// Encrypted share followed by the AES-GCM tag
+    #[serde(with = "hex_bytes")]
+    ciphertext: Vec<u8>,
+}
+
+/// Creates a random challenge for a key to enroll
+#[must_use]
+pub fn new_challenge() -> Vec<u8> {
+    random(CHALLENGE_SIZE)
+}
+
+/// Checks that `threshold` of the keys with `serials` can make a set
+///
+/// # Errors
+///
+/// Returns an error if the threshold is 0 or more than the number of keys,
+/// there are more than 255 keys, or a key is listed twice
+pub fn check_set(threshold: u8, serials: &[u32]) -> Result<()> {
+    if threshold == 0 || usize::from(threshold) > serials.len() {
+        return Err(YkvcError::InvalidThreshold(format!(
+            "{threshold} of {} keys, it must be between 1 and the number of keys",
+            serials.len()
+        )));
+    }
+    if serials.len() > usize::from(u8::MAX) {
+        return Err(YkvcError::InvalidThreshold("at most 255 keys can be enrolled".to_string()));
+    }
+    if serials.iter().collect::<BTreeSet<_>>().len() != serials.len() {
+        return Err(YkvcError::InvalidThreshold("every key can be enrolled once".to_string()));
+    }
+    Ok(())
+}
+
+/// Default location of the share file, beside the configuration file
+#[must_use]
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("ykvc").join("threshold.toml"))
+}
+
+impl ShareFile {
+    /// Creates a master keyfile and splits it across the enrolled keys
+    ///
+    /// Returns the share file and the master keyfile.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the threshold is 0 or more than the number of
+    /// keys, there are more than 255 keys, or a key is enrolled twice
+    pub fn create(threshold: u8, enrolled: &[Enrollment]) -> Result<(Self, Vec<u8>)> {
+        let serials: Vec<_> = enrolled.iter().map(|key| key.serial).collect();
+        check_set(threshold, &serials)?;
+
+        let master = random(MASTER_SIZE);
+        let id = random(ID_SIZE);
+        let mut file = Self {
+            version: FORMAT_VERSION,
+            threshold,
+            check: crypto::hmac_sha256(&master, &id).to_vec(),
+            id,
+            shares: Vec::new(),
+        };
+
+        // The share at 0 would be the master keyfile itself
+        let dealer = shamir::Dealer::new(&master, threshold);
+        for (x, key) in (1..=u8::MAX).zip(enrolled) {
+            file.shares.push(file.encrypt(key, x, &dealer.share(x)));
+        }
+        Ok((file, master))
+    }
+
+    /// Reads the share file at `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file is missing, cannot be read, has another
+    /// format version or is malformed
+    pub fn load(path: &Path) -> Result<Self> {
+        let invalid =
+            |message: String| YkvcError::InvalidShareFile(format!("{}: {message}", path.display()));
+
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(invalid("not found, create it with 'ykvc threshold init'".to_string()))
+            }
+            Err(e) => return Err(invalid(e.to_string())),
+        };
+
+        // The version is checked first, so newer files are not reported as malformed
+        let version = text
+            .parse::<toml::Table>()
+            .map_err(|e| invalid(e.message().to_string()))?
+            .get("version")
+            .and_then(toml::Value::as_integer);
+        if version != Some(i64::from(FORMAT_VERSION)) {
+            return Err(invalid(format!(
+                "format version {} is not supported, this ykvc reads version {FORMAT_VERSION}",
+                version.map_or_else(|| "<missing>".to_string(), |v| v.to_string())
+            )));
+        }
+
+        let file: Self = toml::from_str(&text).map_err(|e| invalid(e.message().to_string()))?;
+        file.validate().map_err(invalid)?;
+        Ok(file)
+    }
+
+    /// Checks sizes, share numbers and the threshold
+    fn validate(&self) -> std::result::Result<(), String> {
+        if self.id.len() != ID_SIZE || self.check.len() != 32 {
+            return Err("the id must be 16 bytes and the check 32 bytes".to_string());
+        }
+        if self.threshold == 0 || usize::from(self.threshold) > self.shares.len() {
+            return Err(format!(
+                "a threshold of {} cannot be met by {} shares",
+                self.threshold,
+                self.shares.len()
+            ));
+        }
+        for share in &self.shares {
+            if share.x == 0
+                || share.challenge.len() != CHALLENGE_SIZE
+                || share.nonce.len() != NONCE_SIZE
+                || share.ciphertext.len() != MASTER_SIZE + TAG_SIZE
+            {
+                return Err(format!("the share of YubiKey {} is malformed", share.serial));
+            }
+        }
+        let serials: BTreeSet<_> = self.shares.iter().map(|share| share.serial).collect();
+        let points: BTreeSet<_> = self.shares.iter().map(|share| share.x).collect();
+        if serials.len() != self.shares.len() || points.len() != self.shares.len() {
+            return Err("serial numbers and share numbers must be unique".to_string());
+        }
+        Ok(())
+    }
+
+    /// Writes the share file to `path`, readable by the owner only
+    ///
+    /// The file is written beside `path` and renamed over it once on disk, so
+    /// a crash or a full disk leaves the old file whole.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = toml::to_string(self)
+            .map_err(|e| YkvcError::Other(format!("Failed to encode share file: {e}")))?;
+
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty());
+        if let Some(parent) = parent {
+            fs::create_dir_all(parent).map_err(|e| {
+                YkvcError::FileError(format!("Failed to create {}: {e}", parent.display()))
+            })?;
+        }
+
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}.tmp", std::process::id()));
+        let temporary = path.with_file_name(name);
+        let written = write_new(&temporary, contents.as_bytes())
+            .and_then(|()| fs::rename(&temporary, path))
+            .map_err(|e| YkvcError::FileError(format!("Failed to write {}: {e}", path.display())));
+        if written.is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+        written?;
+
+        // Makes the rename itself durable
+        if let Ok(directory) = fs::File::open(parent.unwrap_or_else(|| Path::new("."))) {
+            let _ = directory.sync_all();
+        }
+        Ok(())
+    }
+
+    /// Number of keys needed to rebuild the keyfile
+    #[must_use]
+    pub const fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// Serial numbers of the enrolled keys
+    #[must_use]
+    pub fn serials(&self) -> Vec<u32> {
+        self.shares.iter().map(|share| share.serial).collect()
+    }
+
+    /// Challenge the enrolled key with `serial` answers
+    #[must_use]
+    pub fn challenge(&self, serial: u32) -> Option<&[u8]> {
+        self.share(serial).map(|share| share.challenge.as_slice())
+    }
+
+    /// Checks the response of one enrolled key against its share
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key is not enrolled or its share does not decrypt
+    pub fn verify(&self, serial: u32, response: &[u8]) -> Result<()> {
+        self.open(serial, response).map(drop)
+    }
+
+    /// Rebuilds the master keyfile from the responses of enrolled keys
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there are fewer responses than the threshold, a
+    /// share does not decrypt or the rebuilt keyfile fails its check
+    pub fn recover(&self, responses: &[(u32, Vec<u8>)]) -> Result<Vec<u8>> {
+        let master = shamir::interpolate(&self.decrypt(responses)?, 0)?;
+
+        if crypto::hmac_sha256(&master, &self.id)[..] != self.check[..] {
+            return Err(YkvcError::InvalidShareFile(
+                "the rebuilt keyfile does not match its check value".to_string(),
+            ));
+        }
+        Ok(master)
+    }
+
+    /// Enrolls another key, using the responses of enrolled keys
+    ///
+    /// The new share lies on the same polynomial, so the master keyfile and
+    /// the other shares stay as they are.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key is already enrolled, all 255 share numbers
+    /// are taken or the keyfile cannot be rebuilt from the responses
+    pub fn add(&mut self, responses: &[(u32, Vec<u8>)], key: &Enrollment) -> Result<()> {
+        if self.share(key.serial).is_some() {
+            return Err(YkvcError::InvalidThreshold(format!(
+                "YubiKey {} is already enrolled",
+                key.serial
+            )));
+        }
+        let x = (1..=u8::MAX).find(|x| self.shares.iter().all(|share| share.x != *x)).ok_or_else(
+            || YkvcError::InvalidThreshold("at most 255 keys can be enrolled".to_string()),
+        )?;
+
+        // Confirms the responses before a share is derived from them
+        self.recover(responses)?;
+
+        let y = shamir::interpolate(&self.decrypt(responses)?, x)?;
+        let share = self.encrypt(key, x, &y);
+        self.shares.push(share);
+        Ok(())
+    }
+
+    /// Removes the share of the key with `serial`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key is not enrolled or fewer keys than the
+    /// threshold would be left
+    pub fn remove(&mut self, serial: u32) -> Result<()> {
+        if self.share(serial).is_none() {
+            return Err(YkvcError::InvalidThreshold(format!("YubiKey {serial} is not enrolled")));
+        }
+        if self.shares.len() <= usize::from(self.threshold) {
+            return Err(YkvcError::InvalidThreshold(format!(
+                "removing YubiKey {serial} would leave {} keys for a threshold of {}",
+                self.shares.len() - 1,
+                self.threshold
+            )));
+        }
+        self.shares.retain(|share| share.serial != serial);
+        Ok(())
+    }
+
+    fn share(&self, serial: u32) -> Option<&EncryptedShare> {
+        self.shares.iter().find(|share| share.serial == serial)
+    }
+
+    /// Decrypts the shares of the responding keys into points and values
+    fn decrypt(&self, responses: &[(u32, Vec<u8>)]) -> Result<Vec<(u8, Vec<u8>)>> {
+        if responses.len() < usize::from(self.threshold) {
+            return Err(YkvcError::InvalidThreshold(format!(
+                "{} of {} keys answered",
+                responses.len(),
+                self.threshold
+            )));
+        }
+
+        responses.iter().map(|(serial, response)| self.open(*serial, response)).collect()
+    }
+
+    /// Decrypts the share of one key into its point and value
+    fn open(&self, serial: u32, response: &[u8]) -> Result<(u8, Vec<u8>)> {
+        let share = self.share(serial).ok_or_else(|| {
+            YkvcError::InvalidThreshold(format!("YubiKey {serial} is not enrolled"))
+        })?;
+        let aad = self.associated_data(serial, share.x, &share.challenge);
+        let y = cipher(&self.id, response)
+            .decrypt(Nonce::from_slice(&share.nonce), Payload { msg: &share.ciphertext, aad: &aad })
+            .map_err(|_| YkvcError::ShareRejected(serial))?;
+        Ok((share.x, y))
+    }
+
+    /// Encrypts the value `y` at `x` for `key`
+    fn encrypt(&self, key: &Enrollment, x: u8, y: &[u8]) -> EncryptedShare {
+        let nonce = random(NONCE_SIZE);
+        let aad = self.associated_data(key.serial, x, &key.challenge);
+        let ciphertext = cipher(&self.id, &key.response)
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: y, aad: &aad })
+            .expect("AES-GCM encrypts shares of any size");
+        EncryptedShare {
+            serial: key.serial,
+            x,
+            challenge: key.challenge.clone(),
+            nonce,
+            ciphertext,
+        }
+    }
+
+    /// Everything a share is bound to besides its value
+    fn associated_data(&self, serial: u32, x: u8, challenge: &[u8]) -> Vec<u8> {
+        [
+            &self.version.to_be_bytes()[..],
+            &self.id,
+            &[self.threshold],
+            &serial.to_be_bytes(),
+            &[x],
+            challenge,
+        ]
+        .concat()
+    }
+}
+
+/// AES-256-GCM keyed from a response
+fn cipher(id: &[u8], response: &[u8]) -> Aes256Gcm {
+    let mut key = [0; 32];
+    Hkdf::<Sha256>::new(Some(id), response)
+        .expand(SHARE_INFO, &mut key)
+        .expect("32 bytes are within the HKDF output limit");
+    Aes256Gcm::new(&key.into())
+}
+
+/// Writes `contents` to a new file at `path`, created readable by the owner only
+fn write_new(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    // Left over from an earlier run that died before renaming it
+    let _ = fs::remove_file(path);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+fn random(size: usize) -> Vec<u8> {
+    let mut bytes = vec![0; size];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enrollment(serial: u32) -> Enrollment {
+        Enrollment { serial, challenge: new_challenge(), response: serial.to_be_bytes().repeat(5) }
+    }
+
+    fn responses(keys: &[&Enrollment]) -> Vec<(u32, Vec<u8>)> {
+        keys.iter().map(|key| (key.serial, key.response.clone())).collect()
+    }
+
+    #[test]
+    fn test_any_threshold_of_keys_rebuild() {
+        let keys = [enrollment(1), enrollment(2), enrollment(3)];
+        let (file, master) = ShareFile::create(2, &keys).unwrap();
+        assert_eq!(master.len(), MASTER_SIZE);
+        assert_eq!(file.serials(), vec![1, 2, 3]);
+        assert_eq!(file.challenge(2), Some(keys[1].challenge.as_slice()));
+
+        for pair in [[&keys[0], &keys[1]], [&keys[0], &keys[2]], [&keys[2], &keys[1]]] {
+            assert_eq!(file.recover(&responses(&pair)).unwrap(), master);
+        }
+        assert_eq!(file.recover(&responses(&[&keys[0], &keys[1], &keys[2]])).unwrap(), master);
+        assert!(matches!(
+            file.recover(&responses(&[&keys[0]])),
+            Err(YkvcError::InvalidThreshold(_))
+        ));
+    }
+
+    #[test]
+    fn test_create_validation() {
+        let keys = [enrollment(1), enrollment(2)];
+        assert!(ShareFile::create(0, &keys).is_err());
+        assert!(ShareFile::create(3, &keys).is_err());
+        assert!(ShareFile::create(1, &[enrollment(1), enrollment(1)]).is_err());
+        assert!(check_set(2, &[1, 2]).is_ok());
+        assert!(check_set(1, &(0..256).collect::<Vec<_>>()).is_err());
+
+        // One of n: every key alone rebuilds the keyfile
+        let (file, master) = ShareFile::create(1, &keys).unwrap();
+        assert_eq!(file.recover(&responses(&[&keys[1]])).unwrap(), master);
+    }
+
+    #[test]
+    fn test_wrong_response_or_tampering_is_detected() {
+        let keys = [enrollment(1), enrollment(2)];
+        let (file, _) = ShareFile::create(2, &keys).unwrap();
+
+        let mut wrong = responses(&[&keys[0], &keys[1]]);
+        wrong[1].1[0] ^= 1;
+        assert!(matches!(file.recover(&wrong), Err(YkvcError::ShareRejected(2))));
+        assert!(file.verify(1, &wrong[0].1).is_ok());
+        assert!(matches!(file.verify(2, &wrong[1].1), Err(YkvcError::ShareRejected(2))));
+        assert!(matches!(file.verify(3, &wrong[1].1), Err(YkvcError::InvalidThreshold(_))));
+
+        let mut tampered = file.clone();
+        tampered.shares[0].x = 7;
+        assert!(matches!(
+            tampered.recover(&responses(&[&keys[0], &keys[1]])),
+            Err(YkvcError::ShareRejected(1))
+        ));
+
+        // Shares of another set do not mix in
+        let (other, _) = ShareFile::create(2, &keys).unwrap();
+        let mut mixed = file.clone();
+        mixed.shares[1] = other.shares[1].clone();
+        assert!(matches!(
+            mixed.recover(&responses(&[&keys[0], &keys[1]])),
+            Err(YkvcError::ShareRejected(2))
+        ));
+
+        let mut wrong_check = file;
+        wrong_check.check[0] ^= 1;
+        assert!(matches!(
+            wrong_check.recover(&responses(&[&keys[0], &keys[1]])),
+            Err(YkvcError::InvalidShareFile(_))
+        ));
+    }
+
+    #[test]
+    fn test_add_and_remove() {
+        let keys = [enrollment(1), enrollment(2), enrollment(3)];
+        let (mut file, master) = ShareFile::create(2, &keys[..2]).unwrap();
+
+        file.add(&responses(&[&keys[0], &keys[1]]), &keys[2]).unwrap();
+        assert_eq!(file.serials(), vec![1, 2, 3]);
+        assert_eq!(file.recover(&responses(&[&keys[2], &keys[0]])).unwrap(), master);
+        assert_eq!(file.recover(&responses(&[&keys[1], &keys[2]])).unwrap(), master);
+        assert!(file.add(&responses(&[&keys[0], &keys[1]]), &keys[2]).is_err());
+
+        file.remove(1).unwrap();
+        assert_eq!(file.recover(&responses(&[&keys[1], &keys[2]])).unwrap(), master);
+        assert!(matches!(file.remove(2), Err(YkvcError::InvalidThreshold(_))));
+        assert!(matches!(file.remove(1), Err(YkvcError::InvalidThreshold(_))));
+
+        // The freed share number goes to the next key
+        let key = enrollment(4);
+        file.add(&responses(&[&keys[1], &keys[2]]), &key).unwrap();
+        assert_eq!(file.share(4).unwrap().x, 1);
+        assert_eq!(file.recover(&responses(&[&key, &keys[1]])).unwrap(), master);
+    }
+
+    #[test]
+    fn test_added_share_matches_dealt_share() {
+        let key = enrollment(4);
+        let keys = [enrollment(1), enrollment(2), enrollment(3)];
+        let (mut file, _) = ShareFile::create(3, &keys).unwrap();
+        file.add(&responses(&[&keys[0], &keys[1], &keys[2]]), &key).unwrap();
+
+        // The added share lies on the polynomials of the dealt ones
+        let dealt = file.decrypt(&responses(&[&keys[0], &keys[1], &keys[2]])).unwrap();
+        let (x, added) = file.open(4, &key.response).unwrap();
+        assert_eq!(shamir::interpolate(&dealt, x).unwrap(), added);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("threshold.toml");
+        let keys = [enrollment(1), enrollment(2)];
+        let (file, _) = ShareFile::create(2, &keys).unwrap();
+
+        file.save(&path).unwrap();
+        assert_eq!(ShareFile::load(&path).unwrap(), file);
+        let mode = fs::metadata(&path).unwrap().permissions();
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777, 0o600);
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("version = 1\n"), "{text}");
+
+        let newer = text.replacen("version = 1", "version = 2", 1);
+        fs::write(&path, newer).unwrap();
+        let err = ShareFile::load(&path).unwrap_err().to_string();
+        assert!(err.contains("format version 2 is not supported"), "{err}");
+
+        // An existing file readable by others is replaced by a private one
+        fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o644)).unwrap();
+        file.save(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions();
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777, 0o600);
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        let truncated = text.replacen("x = 2", "x = 0", 1);
+        fs::write(&path, truncated).unwrap();
+        assert!(matches!(ShareFile::load(&path), Err(YkvcError::InvalidShareFile(_))));
+
+        let err = ShareFile::load(&dir.path().join("missing.toml")).unwrap_err().to_string();
+        assert!(err.contains("ykvc threshold init"), "{err}");
+    }
+}
@@ -2,6 +2,7 @@
 
 use assert_cmd::Command;
 use predicates::prelude::*;
+use std::path::{Path, PathBuf};
 
 #[test]
 fn test_cli_help() {
@@ -37,6 +38,17 @@ fn test_cli_slot2_help() {
         .stdout(predicate::str::contains("restore"));
 }
 
+#[test]
+fn test_cli_slot_help() {
+    let mut cmd = Command::cargo_bin("ykvc").unwrap();
+    cmd.args(["slot", "1", "--help"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("delete"))
+        .stdout(predicate::str::contains("swap"));
+}
+
 #[test]
 fn test_cli_generate_help() {
     let mut cmd = Command::cargo_bin("ykvc").unwrap();
@@ -45,7 +57,8 @@ fn test_cli_generate_help() {
     cmd.assert()
         .success()
         .stdout(predicate::str::contains("output"))
-        .stdout(predicate::str::contains("-o"));
+        .stdout(predicate::str::contains("-o"))
+        .stdout(predicate::str::contains("--slot"));
 }
 
 #[test]
@@ -56,15 +69,1438 @@ fn test_cli_invalid_command() {
     cmd.assert().failure();
 }
 
-// Note: The following tests would require YubiKey hardware or mocking:
-// - test_info_command_with_yubikey()
-// - test_info_command_without_yubikey()
-// - test_slot2_check_programmed()
-// - test_slot2_check_not_programmed()
-// - test_generate_command()
-// - test_test_command()
-//
-// These require either:
-// 1. Mock YubiKey device
-// 2. Actual hardware
-// 3. Test doubles for system commands
+// End-to-end tests against the software emulator backend
+
+/// RFC 2202 test case 1 key, used as the emulated slot 2 secret
+const SECRET: &str = "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b";
+
+/// HMAC-SHA1 of "Hi There" under [`SECRET`]
+const HI_THERE_RESPONSE: &str = "b617318655057264e28bc0b6fb378c8ef146be00";
+
+/// Runs ykvc against an emulated token whose slots live in `state_file`
+fn emulator(state_file: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("ykvc").unwrap();
+    cmd.env("YKVC_BACKEND", "emulator")
+        .env("YKVC_EMULATOR_FILE", state_file)
+        .env("YKVC_CONFIG", state_file.with_file_name("config.toml"))
+        .env_remove("YKVC_EMULATOR_SECRET");
+    cmd
+}
+
+/// Emulator state file with [`SECRET`] in slot 2
+fn programmed_token(dir: &tempfile::TempDir) -> PathBuf {
+    let path = dir.path().join("emulator.toml");
+    std::fs::write(&path, format!("[slot2]\nsecret = \"{SECRET}\"\n")).unwrap();
+    path
+}
+
+#[test]
+fn test_emulator_info_unprogrammed() {
+    let dir = tempfile::tempdir().unwrap();
+
+    emulator(&dir.path().join("emulator.toml"))
+        .arg("info")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("YubiKey detected"))
+        .stdout(predicate::str::contains("Not Programmed"));
+}
+
+#[test]
+fn test_emulator_backend_flag() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = programmed_token(&dir);
+
+    Command::cargo_bin("ykvc")
+        .unwrap()
+        .args(["--backend", "emulator", "slot2", "check"])
+        .env("YKVC_EMULATOR_FILE", &state_file)
+        .env_remove("YKVC_BACKEND")
+        .env_remove("YKVC_EMULATOR_SECRET")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slot 2 is programmed"));
+}
+
+#[test]
+fn test_emulator_slot2_restore() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("emulator.toml");
+
+    emulator(&state_file)
+        .args(["slot2", "restore"])
+        .write_stdin(format!("{SECRET}\ny\n"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slot 2 restored and verified successfully"));
+
+    assert!(std::fs::read_to_string(&state_file).unwrap().contains(SECRET));
+
+    emulator(&state_file)
+        .args(["slot2", "check"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slot 2 is programmed"));
+}
+
+#[test]
+fn test_emulator_slot2_restore_cancelled() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("emulator.toml");
+
+    emulator(&state_file)
+        .args(["slot2", "restore"])
+        .write_stdin(format!("{SECRET}\nn\n"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cancelled"));
+
+    assert!(!state_file.exists());
+}
+
+#[test]
+fn test_emulator_slot2_program() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("emulator.toml");
+
+    let output = emulator(&state_file)
+        .args(["slot2", "program"])
+        .write_stdin("y\n\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slot 2 configured and verified successfully"))
+        .get_output()
+        .stdout
+        .clone();
+
+    // The secret shown for backup is the one that was programmed
+    let state = std::fs::read_to_string(&state_file).unwrap();
+    let secret =
+        state.lines().find_map(|line| line.strip_prefix("secret = ")).unwrap().trim_matches('"');
+    assert_eq!(secret.len(), 40);
+    assert!(String::from_utf8_lossy(&output).contains(secret));
+}
+
+#[test]
+fn test_emulator_slot1_restore_and_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("emulator.toml");
+
+    emulator(&state_file)
+        .args(["slot", "1", "restore"])
+        .write_stdin(format!("{SECRET}\ny\n"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slot 1 restored and verified successfully"));
+
+    emulator(&state_file)
+        .args(["test", "--slot", "1"])
+        .write_stdin("Hi There\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(HI_THERE_RESPONSE));
+
+    // Slot 2 stays empty
+    emulator(&state_file)
+        .args(["test", "--slot", "2"])
+        .write_stdin("Hi There\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Slot 2 is not programmed"));
+}
+
+#[test]
+fn test_emulator_slot_swap_and_delete() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = programmed_token(&dir);
+
+    emulator(&state_file)
+        .args(["slot", "2", "swap"])
+        .write_stdin("y\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slots swapped successfully"))
+        .stdout(predicate::str::contains("Operation:         swap slots 1 and 2"))
+        .stdout(predicate::str::contains("Slot 1:            Empty -> HMAC-SHA1"))
+        .stdout(predicate::str::contains("Slot 2:            HMAC-SHA1 -> Empty"));
+
+    emulator(&state_file)
+        .args(["slot", "1", "check"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slot 1 is programmed"));
+
+    emulator(&state_file)
+        .args(["slot", "1", "delete"])
+        .write_stdin("y\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slot 1 deleted successfully"))
+        .stdout(predicate::str::contains("Slot 1:            HMAC-SHA1 -> Empty"))
+        .stdout(predicate::str::contains("Slot 2:            Empty (unchanged)"));
+
+    emulator(&state_file)
+        .arg("info")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slot 1 Status:     Not Programmed"))
+        .stdout(predicate::str::contains("Slot 2 Status:     Not Programmed"));
+}
+
+#[test]
+fn test_emulator_slot_delete_cancelled() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = programmed_token(&dir);
+
+    emulator(&state_file)
+        .args(["slot", "2", "delete"])
+        .write_stdin("n\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cancelled"));
+
+    emulator(&state_file)
+        .args(["slot", "2", "check"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slot 2 is programmed"));
+}
+
+#[test]
+fn test_emulator_test_command() {
+    let dir = tempfile::tempdir().unwrap();
+
+    emulator(&programmed_token(&dir))
+        .arg("test")
+        .write_stdin("Hi There\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(HI_THERE_RESPONSE));
+}
+
+#[test]
+fn test_emulator_generate() {
+    let dir = tempfile::tempdir().unwrap();
+    let keyfile = dir.path().join("test.key");
+
+    emulator(&programmed_token(&dir))
+        .args(["generate", "-o"])
+        .arg(&keyfile)
+        .write_stdin("Hi There\n\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Keyfile generated successfully"))
+        .stdout(predicate::str::contains("Keyfile deleted securely"));
+
+    assert!(!keyfile.exists());
+}
+
+#[test]
+fn test_emulator_generate_scheme() {
+    let dir = tempfile::tempdir().unwrap();
+    let keyfile = dir.path().join("test.key");
+
+    emulator(&programmed_token(&dir))
+        .args(["generate", "--scheme", "v2-hkdf-sha256", "--size", "4096", "-o"])
+        .arg(&keyfile)
+        .write_stdin("Hi There\n\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Size:    4096 bytes"))
+        .stdout(predicate::str::contains("Scheme:  v2-hkdf-sha256"));
+
+    Command::cargo_bin("ykvc")
+        .unwrap()
+        .args(["recover", "--scheme", "v2-hkdf-sha256", "-o"])
+        .arg(&keyfile)
+        .write_stdin(format!("{SECRET}\nHi There\n\n"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Size:    64 bytes"));
+
+    // The raw scheme writes the response as it is, so a size is refused up front
+    emulator(&programmed_token(&dir))
+        .args(["generate", "--size", "32", "-o"])
+        .arg(&keyfile)
+        .write_stdin("Hi There\n\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid keyfile size"));
+
+    assert!(!keyfile.exists());
+}
+
+#[test]
+fn test_emulator_generate_unprogrammed() {
+    let dir = tempfile::tempdir().unwrap();
+    let keyfile = dir.path().join("test.key");
+
+    emulator(&dir.path().join("emulator.toml"))
+        .args(["generate", "-o"])
+        .arg(&keyfile)
+        .write_stdin("Hi There\n\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not programmed"));
+
+    assert!(!keyfile.exists());
+}
+
+#[test]
+fn test_recover_without_device() {
+    let dir = tempfile::tempdir().unwrap();
+    let keyfile = dir.path().join("recovered.key");
+
+    // Any backend selection is ignored, recovery never touches a device
+    Command::cargo_bin("ykvc")
+        .unwrap()
+        .args(["--backend", "hid", "recover", "-o"])
+        .arg(&keyfile)
+        .write_stdin(format!("{SECRET}\nHi There\n\n"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Keyfile recovered successfully"))
+        .stdout(predicate::str::contains("Keyfile deleted securely"));
+
+    assert!(!keyfile.exists());
+}
+
+#[test]
+fn test_recover_rejects_invalid_secret() {
+    let dir = tempfile::tempdir().unwrap();
+    let keyfile = dir.path().join("recovered.key");
+
+    Command::cargo_bin("ykvc")
+        .unwrap()
+        .args(["recover", "-o"])
+        .arg(&keyfile)
+        .write_stdin("0b0b\nHi There\n\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid secret length"));
+
+    assert!(!keyfile.exists());
+}
+
+#[test]
+fn test_emulator_list() {
+    let dir = tempfile::tempdir().unwrap();
+
+    emulator(&programmed_token(&dir))
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Found 1 YubiKey(s)"))
+        .stdout(predicate::str::contains("emulated"));
+}
+
+#[test]
+fn test_emulator_wait() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = programmed_token(&dir);
+
+    emulator(&state_file)
+        .args(["--wait", "info"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("YubiKey detected"));
+
+    emulator(&state_file)
+        .args(["--wait=1", "--serial", "0", "info"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Waiting").not());
+
+    // The emulated token has serial 0, so serial 42 never shows up
+    emulator(&state_file)
+        .args(["--serial", "42", "--wait=1", "info"])
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("Waiting for YubiKey 42 to be connected"))
+        .stderr(predicate::str::contains("Timed out after 1 seconds"));
+}
+
+#[test]
+fn test_emulator_serial_selection() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = programmed_token(&dir);
+
+    emulator(&state_file)
+        .args(["--serial", "0", "test"])
+        .write_stdin("Hi There\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(HI_THERE_RESPONSE));
+
+    emulator(&state_file)
+        .args(["info", "--serial", "12345678"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No YubiKey with serial number 12345678"));
+}
+
+#[test]
+fn test_emulator_require_touch() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("emulator.toml");
+
+    emulator(&state_file)
+        .args(["slot2", "restore", "--require-touch"])
+        .write_stdin(format!("{SECRET}\ny\n"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Touch the YubiKey"));
+
+    assert!(std::fs::read_to_string(&state_file).unwrap().contains("require_touch = true"));
+
+    emulator(&state_file)
+        .args(["test", "--touch-timeout", "5"])
+        .write_stdin("Hi There\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(HI_THERE_RESPONSE))
+        .stderr(predicate::str::contains("Touch your YubiKey now (waiting up to 5 seconds)"));
+}
+
+#[test]
+fn test_cli_touch_timeout_rejects_zero() {
+    let mut cmd = Command::cargo_bin("ykvc").unwrap();
+    cmd.args(["test", "--touch-timeout", "0"]);
+
+    cmd.assert().failure();
+}
+
+/// Access code used to lock emulated slots
+const ACCESS_CODE: &str = "010203040506";
+
+#[test]
+fn test_emulator_access_code() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("emulator.toml");
+
+    emulator(&state_file)
+        .args(["slot2", "restore", "--access-code"])
+        .write_stdin(format!("{SECRET}\ny\n{ACCESS_CODE}\n{ACCESS_CODE}\n"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slot 2 is locked"));
+
+    emulator(&state_file)
+        .arg("info")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slot 2 Protected:  Yes"));
+
+    // Reprogramming asks for the current code and refuses a wrong one
+    emulator(&state_file)
+        .args(["slot2", "program"])
+        .write_stdin("y\n060504030201\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Current access code for slot 2"))
+        .stderr(predicate::str::contains("Slot 2 is protected by an access code"));
+
+    assert!(std::fs::read_to_string(&state_file).unwrap().contains(SECRET));
+
+    emulator(&state_file)
+        .args(["slot2", "delete"])
+        .write_stdin(format!("y\n{ACCESS_CODE}\n"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slot 2 deleted successfully"));
+}
+
+#[test]
+fn test_emulator_access_code_mismatch() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("emulator.toml");
+
+    emulator(&state_file)
+        .args(["slot", "1", "program", "--access-code"])
+        .write_stdin(format!("y\n{ACCESS_CODE}\n060504030201\n"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("do not match"));
+
+    assert!(!state_file.exists());
+}
+
+#[test]
+fn test_emulator_challenge_format_hex() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = programmed_token(&dir);
+
+    // "Hi There" in hex answers like the text phrase
+    emulator(&state_file)
+        .args(["test", "--challenge-format", "hex"])
+        .write_stdin("4869205468657265\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("8 bytes"))
+        .stdout(predicate::str::contains(HI_THERE_RESPONSE));
+
+    emulator(&state_file)
+        .args(["test", "--challenge-format", "hex"])
+        .write_stdin("not hex\n")
+        .assert()
+        .failure();
+}
+
+/// Response of a fixed 64-byte slot holding `SECRET` to "Hi There" in raw64 format
+const HI_THERE_RAW64_RESPONSE: &str = "d84ecfa1c5bef7b09175dbed49763c07a59a1e3e";
+
+#[test]
+fn test_emulator_fixed_64() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("emulator.toml");
+
+    emulator(&state_file)
+        .args(["slot2", "restore", "--fixed-64"])
+        .write_stdin(format!("{SECRET}\ny\n"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("restored and verified successfully"));
+
+    assert!(std::fs::read_to_string(&state_file).unwrap().contains("fixed_64 = true"));
+
+    emulator(&state_file)
+        .args(["test", "--challenge-format", "raw64"])
+        .write_stdin("Hi There\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("64 bytes"))
+        .stdout(predicate::str::contains(HI_THERE_RAW64_RESPONSE));
+}
+
+// Secret handling of the command-line tool backend, against fake Yubico tools
+
+/// Shell prologue of every fake tool
+///
+/// While the tool runs, it appends its own `/proc/<pid>/cmdline` and then its
+/// stdin to the log named by `FAKE_LOG`.
+const FAKE_PROLOGUE: &str = r#"#!/bin/sh
+printf 'argv:' >> "$FAKE_LOG"
+tr '\0' ' ' < /proc/$$/cmdline >> "$FAKE_LOG"
+echo >> "$FAKE_LOG"
+log_stdin() { printf 'stdin:' >> "$FAKE_LOG"; cat >> "$FAKE_LOG"; echo >> "$FAKE_LOG"; }
+"#;
+
+/// Fake `ykman` for a single `YubiKey` with slot 2 programmed
+const FAKE_YKMAN: &str = r#"[ "$1" = --device ] && shift 2
+case "$*" in
+    "list --serials") echo 12345678 ;;
+    list) echo "YubiKey 5 NFC (5.4.3) [OTP+FIDO+CCID] Serial: 12345678" ;;
+    info) printf 'Serial number: 12345678\nFirmware version: 5.4.3\n' ;;
+    "otp info") printf 'Slot 1: empty\nSlot 2: programmed\n' ;;
+    *) log_stdin; echo 0000000000000000000000000000000000000000 ;;
+esac
+"#;
+
+/// Fake `ykchalresp` answering like a slot holding [`SECRET`] to "Hi There"
+const FAKE_YKCHALRESP: &str = r#"log_stdin
+echo b617318655057264e28bc0b6fb378c8ef146be00
+"#;
+
+/// Directory of fake Yubico tools, and the log they write to
+struct FakeTools {
+    dir: tempfile::TempDir,
+}
+
+impl FakeTools {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("bin");
+        std::fs::create_dir(&bin).unwrap();
+
+        let tools = [
+            // Dependency checks run `command -v`, which is a shell builtin
+            ("command", "exit 0\n"),
+            ("ykman", FAKE_YKMAN),
+            ("ykpersonalize", "log_stdin\n"),
+            ("ykchalresp", FAKE_YKCHALRESP),
+        ];
+        let fake = Self { dir };
+        for (name, body) in tools {
+            fake.replace(name, body);
+        }
+        fake
+    }
+
+    /// Installs a fake `name` running `body` after the logging prologue
+    fn replace(&self, name: &str, body: &str) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = self.dir.path().join("bin").join(name);
+        std::fs::write(&path, format!("{FAKE_PROLOGUE}{body}")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    /// Removes the fake `name`, as if the tool were not installed
+    fn remove(&self, name: &str) {
+        std::fs::remove_file(self.dir.path().join("bin").join(name)).unwrap();
+    }
+
+    /// Runs ykvc with the command-line tool backend and the fake tools first on `PATH`
+    fn ykvc(&self) -> Command {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let mut paths = vec![self.dir.path().join("bin")];
+        paths.extend(std::env::split_paths(&path));
+
+        let mut cmd = Command::cargo_bin("ykvc").unwrap();
+        cmd.env("YKVC_BACKEND", "cli")
+            .env("PATH", std::env::join_paths(paths).unwrap())
+            .env("FAKE_LOG", self.dir.path().join("log"))
+            .env("YKVC_CONFIG", self.config_file());
+        cmd
+    }
+
+    /// Configuration file the ykvc runs read, missing unless a test writes it
+    fn config_file(&self) -> PathBuf {
+        self.dir.path().join("config.toml")
+    }
+
+    /// Command lines the tools saw, as read from `/proc`
+    fn argv(&self) -> Vec<String> {
+        self.log_lines("argv:")
+    }
+
+    /// Everything the tools read on stdin
+    fn stdin(&self) -> Vec<String> {
+        self.log_lines("stdin:")
+    }
+
+    fn log_lines(&self, prefix: &str) -> Vec<String> {
+        std::fs::read_to_string(self.dir.path().join("log"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.strip_prefix(prefix))
+            .map(ToString::to_string)
+            .collect()
+    }
+}
+
+#[test]
+fn test_cli_backend_challenge_not_in_argv() {
+    let tools = FakeTools::new();
+    // "Hi There" in hex, as it appears in the padded challenge block
+    let challenge_hex = "4869205468657265";
+
+    tools
+        .ykvc()
+        .arg("test")
+        .write_stdin("Hi There\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(HI_THERE_RESPONSE));
+
+    assert!(tools.argv().iter().any(|argv| argv.contains("bin/ykchalresp")));
+    assert!(tools.argv().iter().all(|argv| !argv.contains(challenge_hex)));
+    assert!(tools.stdin().iter().any(|stdin| stdin.starts_with(challenge_hex)));
+
+    // Through ykman when a serial is selected
+    let tools = FakeTools::new();
+    tools
+        .ykvc()
+        .args(["--serial", "12345678", "test"])
+        .write_stdin("Hi There\n")
+        .assert()
+        .success();
+
+    assert!(tools.argv().iter().any(|argv| argv.contains("otp calculate 2")));
+    assert!(tools.argv().iter().all(|argv| !argv.contains(challenge_hex)));
+    assert!(tools.stdin().iter().any(|stdin| stdin.starts_with(challenge_hex)));
+}
+
+#[test]
+fn test_cli_backend_secret_not_in_argv() {
+    let tools = FakeTools::new();
+
+    // The fake slot does not really hold the secret, so verification fails
+    // after ykpersonalize has run
+    tools
+        .ykvc()
+        .args(["slot2", "restore"])
+        .write_stdin(format!("{SECRET}\ny\n"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("failed verification"));
+
+    assert!(tools.argv().iter().any(|argv| argv.contains("bin/ykpersonalize")));
+    assert!(tools.argv().iter().all(|argv| !argv.contains(SECRET)));
+    assert!(tools.stdin().contains(&SECRET.to_string()));
+}
+
+#[test]
+fn test_cli_backend_access_code_not_in_argv() {
+    let tools = FakeTools::new();
+
+    tools
+        .ykvc()
+        .args(["--serial", "12345678", "slot2", "restore", "--access-code"])
+        .write_stdin(format!("{SECRET}\ny\n{ACCESS_CODE}\n{ACCESS_CODE}\n"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("failed verification"));
+
+    assert!(tools.argv().iter().any(|argv| argv.contains("otp chalresp --force 2")));
+    assert!(tools.argv().iter().any(|argv| argv.contains("--new-access-code -")));
+    assert!(tools.argv().iter().all(|argv| !argv.contains(SECRET)));
+    assert!(tools.argv().iter().all(|argv| !argv.contains(ACCESS_CODE)));
+    assert!(tools.stdin().contains(&SECRET.to_string()));
+    assert!(tools.stdin().iter().any(|stdin| stdin.starts_with(ACCESS_CODE)));
+
+    // ykpersonalize would need the code on its command line, so it is never run
+    let tools = FakeTools::new();
+    tools
+        .ykvc()
+        .args(["slot2", "restore", "--access-code"])
+        .write_stdin(format!("{SECRET}\ny\n{ACCESS_CODE}\n{ACCESS_CODE}\n"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("only takes access codes on its command line"));
+
+    assert!(tools.argv().iter().all(|argv| !argv.contains("bin/ykpersonalize")));
+}
+
+#[test]
+fn test_cli_backend_access_code_failure_rolls_back() {
+    // Refuses to set access codes, and deletes slots by running `delete`
+    let ykman = |delete: &str| {
+        format!(
+            r#"case "$*" in
+    *"otp settings"*) log_stdin; echo "Error: settings refused" >&2; exit 1 ;;
+    *"otp delete"*) {delete} ;;
+esac
+{FAKE_YKMAN}"#
+        )
+    };
+    let restore = |tools: &FakeTools| {
+        tools
+            .ykvc()
+            .args(["--serial", "12345678", "slot2", "restore", "--access-code"])
+            .write_stdin(format!("{SECRET}\ny\n{ACCESS_CODE}\n{ACCESS_CODE}\n"))
+            .assert()
+            .failure()
+    };
+
+    let tools = FakeTools::new();
+    tools.replace("ykman", &ykman("exit 0"));
+    restore(&tools)
+        .stderr(predicate::str::contains("deleted again and the slot is empty"))
+        .stderr(predicate::str::contains("settings refused"));
+    assert!(tools.argv().iter().any(|argv| argv.contains("otp delete --force 2")));
+
+    let tools = FakeTools::new();
+    tools.replace("ykman", &ykman(r#"echo "Error: delete refused" >&2; exit 1"#));
+    restore(&tools)
+        .stderr(predicate::str::contains("Slot 2 is programmed but NOT protected"))
+        .stderr(predicate::str::contains("delete refused"));
+}
+
+#[test]
+fn test_cli_backend_changes_current_access_code() {
+    // Slot 2 is locked: writes without an access code are refused
+    let locked = format!(
+        r#"case "$*" in
+    *"otp chalresp"* | *"otp settings"* | *"otp delete"*)
+        echo "Error: Failed to write to the YubiKey. Make sure the device does not have restricted access." >&2
+        exit 1 ;;
+esac
+{FAKE_YKMAN}"#
+    );
+    let current = "a1a2a3a4a5a6";
+
+    // A new code replaces the current one, which unlocks the slot
+    let tools = FakeTools::new();
+    tools.replace("ykman", &locked);
+    tools
+        .ykvc()
+        .args(["--serial", "12345678", "slot2", "restore", "--access-code"])
+        .write_stdin(format!("{SECRET}\ny\n{ACCESS_CODE}\n{ACCESS_CODE}\n{current}\n"))
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("Slot 2 is protected by an access code"))
+        .stderr(predicate::str::contains("failed verification"));
+    assert!(tools
+        .argv()
+        .iter()
+        .any(|argv| argv.contains("otp --access-code - chalresp --force 2")));
+    assert!(tools
+        .argv()
+        .iter()
+        .any(|argv| argv.contains("otp --access-code - settings --force --new-access-code - 2")));
+    // Both the chalresp and the settings run are unlocked with the current code
+    assert_eq!(tools.stdin().iter().filter(|stdin| *stdin == current).count(), 2);
+
+    // Without a new code, the current one is removed like on the other backends
+    let tools = FakeTools::new();
+    tools.replace("ykman", &locked);
+    tools
+        .ykvc()
+        .args(["--serial", "12345678", "slot2", "restore"])
+        .write_stdin(format!("{SECRET}\ny\n{current}\n"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("failed verification"));
+    assert!(tools
+        .argv()
+        .iter()
+        .any(|argv| argv.contains("otp --access-code - settings --force --delete-access-code 2")));
+
+    // A failed change is rolled back with the code the slot still has
+    let tools = FakeTools::new();
+    tools.replace(
+        "ykman",
+        &format!(
+            r#"case "$*" in
+    *"settings"*) log_stdin; echo "Error: settings refused" >&2; exit 1 ;;
+    *"otp delete"*) echo "Error: write error" >&2; exit 1 ;;
+esac
+{locked}"#
+        ),
+    );
+    tools
+        .ykvc()
+        .args(["--serial", "12345678", "slot2", "restore", "--access-code"])
+        .write_stdin(format!("{SECRET}\ny\n{ACCESS_CODE}\n{ACCESS_CODE}\n{current}\n"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("deleted again and the slot is empty"));
+    assert!(tools.argv().iter().any(|argv| argv.contains("otp --access-code - delete --force 2")));
+}
+
+#[test]
+fn test_cli_backend_ykman_toolchain() {
+    let tools = FakeTools::new();
+    tools.remove("ykpersonalize");
+    tools.remove("ykchalresp");
+
+    tools
+        .ykvc()
+        .args(["slot2", "restore", "--require-touch"])
+        .write_stdin(format!("{SECRET}\ny\n"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("failed verification"));
+
+    assert!(tools.argv().iter().any(|argv| argv.contains("otp chalresp --force --touch 2")));
+    assert!(tools.argv().iter().any(|argv| argv.contains("otp calculate 2")));
+    assert!(tools.argv().iter().all(|argv| !argv.contains(SECRET)));
+    assert!(tools.stdin().contains(&SECRET.to_string()));
+}
+
+#[test]
+fn test_cli_backend_tool_timeout() {
+    let tools = FakeTools::new();
+    tools.replace("ykchalresp", "sleep 10\n");
+
+    // A challenge waiting for touch runs into the touch timeout
+    let started = std::time::Instant::now();
+    tools
+        .ykvc()
+        .args(["test", "--touch-timeout", "1"])
+        .write_stdin("Hi There\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("waiting for the YubiKey to be touched"));
+    assert!(started.elapsed() < std::time::Duration::from_secs(8));
+
+    // A stuck ykman is stopped after the timeout from the configuration file
+    tools.replace("ykman", "sleep 10\n");
+    std::fs::write(tools.config_file(), "[timeouts]\ntool = 1\n").unwrap();
+    let started = std::time::Instant::now();
+    tools
+        .ykvc()
+        .arg("info")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'ykman' did not finish within 1 seconds"));
+    assert!(started.elapsed() < std::time::Duration::from_secs(8));
+
+    // The command line wins over the configuration file
+    tools
+        .ykvc()
+        .args(["info", "--tool-timeout", "2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("within 2 seconds"));
+}
+
+#[test]
+fn test_cli_backend_retries_busy_device() {
+    let tools = FakeTools::new();
+    // Busy on the first challenge only
+    tools.replace(
+        "ykchalresp",
+        r#"log_stdin
+if [ ! -e "$FAKE_LOG.busy" ]; then
+    touch "$FAKE_LOG.busy"
+    echo "USB error: device busy" >&2
+    exit 1
+fi
+echo b617318655057264e28bc0b6fb378c8ef146be00
+"#,
+    );
+
+    tools
+        .ykvc()
+        .arg("test")
+        .write_stdin("Hi There\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(HI_THERE_RESPONSE));
+
+    let runs = tools.argv().iter().filter(|argv| argv.contains("bin/ykchalresp")).count();
+    assert_eq!(runs, 2);
+}
+
+#[test]
+fn test_invalid_config_file() {
+    let tools = FakeTools::new();
+    std::fs::write(tools.config_file(), "[timeouts]\ntool = \"soon\"\n").unwrap();
+
+    tools
+        .ykvc()
+        .arg("info")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid configuration file"));
+}
+
+#[test]
+fn test_emulator_slot_config_types() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("emulator.toml");
+    std::fs::write(
+        &state_file,
+        format!(
+            "[slot1]\nsecret = \"{SECRET}\"\nrequire_touch = true\n\
+             [slot2]\nkind = \"static-password\"\nsecret = \"{SECRET}\"\n"
+        ),
+    )
+    .unwrap();
+
+    emulator(&state_file)
+        .arg("info")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Slot 1 Status:     HMAC-SHA1 (touch)"))
+        .stdout(predicate::str::contains("Slot 2 Status:     Static password"));
+
+    emulator(&state_file)
+        .args(["slot", "2", "check"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("holds configuration type 'Static password'"));
+
+    emulator(&state_file)
+        .arg("generate")
+        .write_stdin("Hi There\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not set up for HMAC-SHA1 challenge-response"));
+}
+
+/// Runs ykvc against a software FIDO2 authenticator kept in `dir`
+fn fido2(dir: &tempfile::TempDir) -> Command {
+    let mut cmd = Command::cargo_bin("ykvc").unwrap();
+    cmd.env("YKVC_BACKEND", "fido2")
+        .env("YKVC_FIDO2_AUTHENTICATOR", dir.path().join("authenticator.toml"))
+        .env("YKVC_CONFIG", dir.path().join("config.toml"));
+    cmd
+}
+
+/// Response printed in hex by `ykvc test`, given `input` line by line
+fn test_response(cmd: &mut Command, input: &str) -> String {
+    let output = cmd.arg("test").write_stdin(format!("{input}\n")).assert().success();
+    let stdout = String::from_utf8_lossy(&output.get_output().stdout).into_owned();
+    let mut lines = stdout.lines().skip_while(|line| !line.contains("Response (hex)"));
+    lines.nth(1).unwrap().trim().to_string()
+}
+
+#[test]
+fn test_fido2_enroll_and_generate() {
+    let dir = tempfile::tempdir().unwrap();
+
+    fido2(&dir)
+        .args(["fido2", "enroll"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("FIDO2 credential enrolled successfully"));
+
+    let config = std::fs::read_to_string(dir.path().join("config.toml")).unwrap();
+    assert!(config.contains("[fido2]"));
+    assert!(config.contains("credential_id"));
+
+    let response = test_response(&mut fido2(&dir), "Hi There");
+    assert_eq!(response.len(), 64);
+    assert_eq!(response, test_response(&mut fido2(&dir), "Hi There"));
+    assert_ne!(response, test_response(&mut fido2(&dir), "Bye There"));
+
+    let keyfile = dir.path().join("test.key");
+    fido2(&dir)
+        .args(["generate", "-o"])
+        .arg(&keyfile)
+        .write_stdin("Hi There\n\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Keyfile generated successfully"));
+}
+
+#[test]
+fn test_fido2_not_enrolled() {
+    let dir = tempfile::tempdir().unwrap();
+
+    fido2(&dir)
+        .arg("generate")
+        .write_stdin("Hi There\n\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("ykvc fido2 enroll"));
+}
+
+#[test]
+fn test_fido2_enroll_with_pin() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("authenticator.toml"), "pin = \"1234\"\n").unwrap();
+
+    fido2(&dir)
+        .args(["fido2", "enroll"])
+        .write_stdin("4321\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("PIN"));
+
+    fido2(&dir)
+        .args(["fido2", "enroll", "--resident"])
+        .write_stdin("1234\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Resident:       Yes"));
+
+    // Re-enrolling replaces the credential only after confirmation
+    fido2(&dir)
+        .args(["fido2", "enroll"])
+        .write_stdin("n\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cancelled"));
+}
+
+/// Private key of the software PIV card's slot 9d
+const PIV_KEY: &str = "7d7dc5f71eb29ddaf80d6214632eeae03d9058af1fb6d22ed80badb62bc1a534";
+
+/// Runs ykvc against a software PIV card with [`PIV_KEY`] in slot 9d, kept in `dir`
+fn piv(dir: &tempfile::TempDir) -> Command {
+    let card = dir.path().join("card.toml");
+    if !card.exists() {
+        std::fs::write(&card, format!("serial = 7654321\n[keys]\n9d = \"{PIV_KEY}\"\n")).unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin("ykvc").unwrap();
+    cmd.env("YKVC_BACKEND", "piv")
+        .env("YKVC_PIV_CARD", card)
+        .env("YKVC_CONFIG", dir.path().join("config.toml"));
+    cmd
+}
+
+#[test]
+fn test_piv_enroll_and_generate() {
+    let dir = tempfile::tempdir().unwrap();
+
+    piv(&dir)
+        .args(["piv", "enroll"])
+        .write_stdin("123456\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("PIV key enrolled successfully"))
+        .stdout(predicate::str::contains("Serial: 7654321"));
+
+    let config = std::fs::read_to_string(dir.path().join("config.toml")).unwrap();
+    assert!(config.contains("[piv]"));
+    assert!(config.contains("slot = \"9d\""));
+
+    let response = test_response(&mut piv(&dir), "Hi There\n123456");
+    assert_eq!(response.len(), 64);
+    assert_eq!(response, test_response(&mut piv(&dir), "Hi There\n123456"));
+    assert_ne!(response, test_response(&mut piv(&dir), "Bye There\n123456"));
+
+    piv(&dir)
+        .args(["generate", "-o"])
+        .arg(dir.path().join("test.key"))
+        .write_stdin("Hi There\n123456\n\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Keyfile generated successfully"));
+}
+
+#[test]
+fn test_piv_enroll_errors() {
+    let dir = tempfile::tempdir().unwrap();
+
+    piv(&dir)
+        .args(["piv", "enroll"])
+        .write_stdin("000000\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Wrong PIV PIN"));
+
+    piv(&dir)
+        .args(["piv", "enroll", "--slot", "9a"])
+        .write_stdin("123456\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("PIV slot 9a holds no P-256 key"));
+
+    piv(&dir)
+        .arg("generate")
+        .write_stdin("Hi There\n\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("ykvc piv enroll"));
+}
+
+#[test]
+fn test_volume_labels_and_profiles() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = programmed_token(&dir);
+    let response = |args: &[&str]| {
+        let output = emulator(&state_file)
+            .arg("test")
+            .args(args)
+            .write_stdin("Hi There\n")
+            .assert()
+            .success();
+        let stdout = String::from_utf8_lossy(&output.get_output().stdout).into_owned();
+        let mut lines = stdout.lines().skip_while(|line| !line.contains("Response (hex)"));
+        lines.nth(1).unwrap().trim().to_string()
+    };
+
+    let unlabelled = response(&[]);
+    assert_eq!(unlabelled, HI_THERE_RESPONSE);
+    let volume_a = response(&["--label", "volume-a"]);
+    assert_ne!(volume_a, unlabelled);
+    assert_ne!(volume_a, response(&["--label", "volume-b"]));
+
+    emulator(&state_file)
+        .args(["profile", "set", "a", "--label", "volume-a"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Profile 'a' saved"));
+    emulator(&state_file)
+        .args(["profile", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Label:   volume-a"));
+    assert_eq!(response(&["--profile", "a"]), volume_a);
+
+    emulator(&state_file).args(["profile", "remove", "a"]).assert().success();
+    emulator(&state_file)
+        .args(["test", "--profile", "a"])
+        .write_stdin("Hi There\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Profile 'a' not found"));
+}
+
+#[test]
+fn test_argon2_profile() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = programmed_token(&dir);
+    let keyfile = dir.path().join("test.key");
+
+    // Costs need --argon2, which needs no label
+    emulator(&state_file).args(["profile", "set", "slow", "--argon2-time", "1"]).assert().failure();
+    emulator(&state_file)
+        .args(["profile", "set", "slow", "--argon2", "--argon2-memory", "64", "--argon2-time", "1"])
+        .assert()
+        .success();
+    let config = std::fs::read_to_string(dir.path().join("config.toml")).unwrap();
+    assert!(config.contains("[profiles.slow.argon2]"), "{config}");
+    assert!(config.contains("memory_kib = 64"), "{config}");
+
+    emulator(&state_file)
+        .args(["profile", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Argon2:  Argon2id, 64 KiB, 1 passes"));
+
+    emulator(&state_file)
+        .args(["generate", "--profile", "slow", "-o"])
+        .arg(&keyfile)
+        .write_stdin("Hi There\n\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Stretching the challenge phrase with Argon2id"))
+        .stdout(predicate::str::contains("Argon2:  Argon2id, 64 KiB, 1 passes"));
+    assert!(!keyfile.exists());
+
+    Command::cargo_bin("ykvc")
+        .unwrap()
+        .env("YKVC_CONFIG", dir.path().join("config.toml"))
+        .args(["recover", "--profile", "slow", "-o"])
+        .arg(&keyfile)
+        .write_stdin(format!("{SECRET}\nHi There\n\n"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Stretching the challenge phrase with Argon2id"));
+
+    // Replacing the salt would change every keyfile, so it is confirmed first
+    emulator(&state_file)
+        .args(["profile", "set", "slow", "--label", "x"])
+        .write_stdin("n\n")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("has an Argon2id salt"));
+    assert_eq!(std::fs::read_to_string(dir.path().join("config.toml")).unwrap(), config);
+}
+
+/// Emulator state of a token with `serial` and `secret` in slot 2
+fn token_state(serial: u32, secret: &str) -> String {
+    format!("serial = {serial}\n[slot2]\nsecret = \"{secret}\"\n")
+}
+
+/// Running emulator process, for tests that swap keys while it waits
+struct Session {
+    child: std::process::Child,
+    stdin: std::process::ChildStdin,
+    stdout: std::io::Lines<std::io::BufReader<std::process::ChildStdout>>,
+}
+
+impl Session {
+    fn spawn(state_file: &Path, args: &[&str]) -> Self {
+        use std::io::BufRead;
+        use std::process::Stdio;
+
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("ykvc"))
+            .args(["--backend", "emulator"])
+            .args(args)
+            .env("YKVC_EMULATOR_FILE", state_file)
+            .env("YKVC_CONFIG", state_file.with_file_name("config.toml"))
+            .env_remove("YKVC_EMULATOR_SECRET")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = std::io::BufReader::new(child.stdout.take().unwrap()).lines();
+        Self { child, stdin, stdout }
+    }
+
+    /// Output up to and including the first line containing `text`
+    fn read_until(&mut self, text: &str) -> String {
+        let mut seen = String::new();
+        for line in self.stdout.by_ref() {
+            let line = line.unwrap();
+            seen.push_str(&line);
+            seen.push('\n');
+            if line.contains(text) {
+                return seen;
+            }
+        }
+        panic!("'{text}' not printed:\n{seen}");
+    }
+
+    fn send(&mut self, input: &str) {
+        use std::io::Write;
+        self.stdin.write_all(input.as_bytes()).unwrap();
+    }
+
+    /// Hands back the keyfile the session shows, after it has been deleted
+    fn take_keyfile(&mut self, keyfile: &Path) -> Vec<u8> {
+        let contents = std::fs::read(keyfile).unwrap();
+        self.send("\n");
+        self.read_until("Operation completed");
+        assert!(self.child.wait().unwrap().success());
+        assert!(!keyfile.exists());
+        contents
+    }
+}
+
+#[test]
+fn test_combined_keyfile_with_key_swap() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("emulator.toml");
+    let keyfile = dir.path().join("combined.key");
+    std::fs::write(&state_file, token_state(1, SECRET)).unwrap();
+
+    let mut session = Session::spawn(
+        &state_file,
+        &["generate", "--combine", "1", "2", "-o", keyfile.to_str().unwrap()],
+    );
+    session.send("Hi There\n");
+    session.read_until("Response from YubiKey 1 received");
+    session.read_until("Remove YubiKey 1 if no other USB port is free, then insert YubiKey 2");
+
+    // Only one port: the first key goes out and the second comes in
+    std::fs::write(&state_file, token_state(2, &"0c".repeat(20))).unwrap();
+    let info = session.read_until("Use this keyfile with VeraCrypt");
+    assert!(info.contains("Keys:    YubiKey 1 + YubiKey 2"), "{info}");
+    assert_eq!(
+        hex::encode(session.take_keyfile(&keyfile)),
+        "399d1b6eb2bef7e12dca468694fd8bd69426bc21a3b9c87e3b82e5df443e1c64"
+    );
+}
+
+#[test]
+fn test_combined_keyfile_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("emulator.toml");
+    let keyfile = dir.path().join("combined.key");
+    std::fs::write(&state_file, token_state(1, SECRET)).unwrap();
+
+    // The second key never shows up
+    emulator(&state_file)
+        .args(["--wait=1", "generate", "--combine", "1", "2", "--separate-phrases", "-o"])
+        .arg(&keyfile)
+        .write_stdin("Hi There\n")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("Response from YubiKey 1 received"))
+        .stdout(predicate::str::contains("then insert YubiKey 2"))
+        .stderr(predicate::str::contains("Timed out after 1 seconds"));
+
+    emulator(&state_file)
+        .args(["--serial", "1", "generate", "--combine", "1", "2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("leave out --serial"));
+    emulator(&state_file)
+        .args(["generate", "--combine", "1", "1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("combine two different keys"));
+
+    assert!(!keyfile.exists());
+}
+
+#[test]
+fn test_threshold_keyfile_with_key_swaps() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("emulator.toml");
+    let shares = dir.path().join("threshold.toml");
+    let keyfile = dir.path().join("threshold.key");
+    let secret = |serial: u32| format!("{serial:02x}").repeat(20);
+    let insert = |serial: u32| std::fs::write(&state_file, token_state(serial, &secret(serial)));
+    let args = |command: &'static str| {
+        vec![
+            "threshold",
+            command,
+            "--file",
+            shares.to_str().unwrap(),
+            "-o",
+            keyfile.to_str().unwrap(),
+        ]
+    };
+    insert(1).unwrap();
+
+    let mut init = args("init");
+    init.extend(["-k", "2", "1", "2", "3"]);
+    let mut session = Session::spawn(&state_file, &init);
+    session.read_until("Response from YubiKey 1 received");
+    for serial in [2, 3] {
+        session.read_until(&format!(
+            "Remove YubiKey {} if no other USB port is free, then insert YubiKey {serial}",
+            serial - 1
+        ));
+        insert(serial).unwrap();
+        session.read_until(&format!("Response from YubiKey {serial} received"));
+    }
+    let info = session.read_until("Use this keyfile with VeraCrypt");
+    assert!(info.contains("Keys:    YubiKey 1 + YubiKey 2 + YubiKey 3"), "{info}");
+    let master = session.take_keyfile(&keyfile);
+    assert_eq!(master.len(), 64);
+
+    // Key 3 is still in, so one more key is asked for
+    let mut session = Session::spawn(&state_file, &args("generate"));
+    session.read_until("Response from YubiKey 3 received");
+    session.read_until(
+        "Remove YubiKey 3 if no other USB port is free, then insert one of the enrolled YubiKeys 1, 2 (1 more needed)",
+    );
+    insert(1).unwrap();
+    let info = session.read_until("Use this keyfile with VeraCrypt");
+    assert!(info.contains("Keys:    YubiKey 3 + YubiKey 1"), "{info}");
+    assert_eq!(session.take_keyfile(&keyfile), master);
+
+    // Key 1 answering with another secret cannot open its share
+    std::fs::write(&state_file, token_state(1, &secret(9))).unwrap();
+    emulator(&state_file)
+        .args(["--wait=1"])
+        .args(args("generate"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("share of YubiKey 1 failed its integrity check"));
+    assert!(!keyfile.exists());
+
+    emulator(&state_file)
+        .args(["threshold", "remove", "3", "--file"])
+        .arg(&shares)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Share of YubiKey 3 removed"));
+    emulator(&state_file)
+        .args(["threshold", "remove", "2", "--file"])
+        .arg(&shares)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid threshold"));
+
+    // Keys 1 and 2 let key 4 in, on the same keyfile
+    insert(1).unwrap();
+    let mut session =
+        Session::spawn(&state_file, &["threshold", "add", "4", "--file", shares.to_str().unwrap()]);
+    session.read_until("Response from YubiKey 1 received");
+    session.read_until("then insert one of the enrolled YubiKeys 2 (1 more needed)");
+    insert(2).unwrap();
+    session.read_until("Remove YubiKey 2 if no other USB port is free, then insert YubiKey 4");
+    insert(4).unwrap();
+    session.read_until("YubiKey 4 enrolled, 3 keys can now rebuild the keyfile");
+    assert!(session.child.wait().unwrap().success());
+
+    let mut session = Session::spawn(&state_file, &args("generate"));
+    session.read_until("Response from YubiKey 4 received");
+    session.read_until("then insert one of the enrolled YubiKeys 1, 2 (1 more needed)");
+    insert(2).unwrap();
+    let info = session.read_until("Use this keyfile with VeraCrypt");
+    assert!(info.contains("Keys:    YubiKey 4 + YubiKey 2"), "{info}");
+    assert_eq!(session.take_keyfile(&keyfile), master);
+}
+
+#[test]
+fn test_threshold_share_file_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_file = dir.path().join("emulator.toml");
+    let shares = dir.path().join("threshold.toml");
+    std::fs::write(&state_file, token_state(1, SECRET)).unwrap();
+    let generate = || {
+        let mut cmd = emulator(&state_file);
+        cmd.args(["threshold", "generate", "--file"]).arg(&shares);
+        cmd
+    };
+
+    generate()
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("create it with 'ykvc threshold init'"));
+
+    std::fs::write(&shares, "version = 2\n").unwrap();
+    generate()
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("format version 2 is not supported"));
+
+    emulator(&state_file)
+        .args(["--serial", "1", "threshold", "init", "-k", "1", "1", "--file"])
+        .arg(&shares)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("leave out --serial"));
+    emulator(&state_file)
+        .args(["threshold", "init", "-k", "3", "1", "2", "--file"])
+        .arg(&shares)
+        .write_stdin("y\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid threshold"));
+}